@@ -0,0 +1,392 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::model::{ModelTables, NGramRecord, TableEntry};
+use crate::utilities::{LimitedMinHeap, LinesIterator, WordListIterator};
+use crate::{SENTENCE_END, SENTENCE_START, UNKNOWN_WORD};
+
+/// Log10 probability ARPA files use for impossible events
+const ARPA_LOG_ZERO: f32 = -99.0;
+
+impl ModelTables {
+    /// Write the model to a file in the ARPA format
+    /// The backoff weights are only written if the model has any
+    pub fn write_arpa<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let with_backoff = self.has_backoff();
+        let tables: Vec<&[NGramRecord]> = self.ngrams.iter().map(|table| &table[..]).collect();
+        write_arpa(path.as_ref(), &self.symbols, &tables, with_backoff)
+    }
+}
+
+/// Convert a natural log to the log10 ARPA uses
+fn to_log10(ln: f32) -> f32 {
+    if ln == f32::NEG_INFINITY {
+        ARPA_LOG_ZERO
+    } else {
+        ln * std::f32::consts::LOG10_E
+    }
+}
+
+/// Write the tables to a file in the ARPA format
+/// The words of an ngram are found by following the children ranges from the unigrams,
+/// so ngrams that are not within the range of their prefix are not written
+pub(crate) fn write_arpa<E: TableEntry>(
+    path: &Path,
+    symbols: &[String],
+    tables: &[&[E]],
+    with_backoff: bool,
+) -> Result<()> {
+    let write_error = |err| Error::io(path, err);
+    let mut f_write = BufWriter::new(File::create(path).map_err(write_error)?);
+    let max_ngram_len = tables.len();
+
+    // For every ngram except the unigrams and the highest order store the index of its prefix, so its words can be looked up
+    let mut prefixes: Vec<Vec<u32>> = vec![Vec::new(); max_ngram_len];
+    for k in 1..max_ngram_len.saturating_sub(1) {
+        let mut prefix = vec![u32::MAX; tables[k].len()];
+        for (h, history) in tables[k - 1].iter().enumerate() {
+            for i in history.children() {
+                if let Some(prefix) = prefix.get_mut(i) {
+                    *prefix = h as u32;
+                }
+            }
+        }
+        prefixes[k] = prefix;
+    }
+    // Labels of the ngram at index idx of the table k
+    let words = |k: usize, idx: usize| -> Option<Vec<u32>> {
+        let mut labels = vec![0; k + 1];
+        let mut idx = idx;
+        for level in (0..=k).rev() {
+            let entry = tables[level].get(idx)?;
+            labels[level] = entry.label();
+            if level > 0 {
+                idx = *prefixes[level].get(idx)? as usize;
+                if idx == u32::MAX as usize {
+                    return None;
+                }
+            }
+        }
+        Some(labels)
+    };
+
+    // Only the ngrams within the range of a prefix whose words are known can be written
+    let mut no_ngrams = vec![tables[0].len()];
+    for k in 1..max_ngram_len {
+        no_ngrams.push(
+            tables[k - 1]
+                .iter()
+                .enumerate()
+                .filter(|(h, _)| k == 1 || words(k - 1, *h).is_some())
+                .map(|(_, history)| history.children().filter(|&i| i < tables[k].len()).count())
+                .sum(),
+        );
+    }
+
+    writeln!(f_write).map_err(write_error)?;
+    writeln!(f_write, "\\data\\").map_err(write_error)?;
+    for (k, no) in no_ngrams.iter().enumerate() {
+        writeln!(f_write, "ngram {}={}", k + 1, no).map_err(write_error)?;
+    }
+
+    for k in 0..max_ngram_len {
+        writeln!(f_write).map_err(write_error)?;
+        writeln!(f_write, "\\{}-grams:", k + 1).map_err(write_error)?;
+        let has_backoff = with_backoff && k + 1 < max_ngram_len;
+        let mut write_entry = |labels: &[u32], entry: &E| -> Result<()> {
+            let mut line = to_log10(entry.log_prob()).to_string();
+            for &label in labels {
+                let word = symbols.get(label as usize).ok_or_else(|| {
+                    Error::InconsistentModel(format!(
+                        "The symbol {} is not in the symbol table",
+                        label
+                    ))
+                })?;
+                line.push('\t');
+                line.push_str(word);
+            }
+            if has_backoff {
+                line.push('\t');
+                line.push_str(&to_log10(entry.backoff()).to_string());
+            }
+            writeln!(f_write, "{}", line).map_err(write_error)
+        };
+        if k == 0 {
+            for (i, entry) in tables[0].iter().enumerate() {
+                write_entry(&[i as u32], entry)?;
+            }
+        } else {
+            for (h, history) in tables[k - 1].iter().enumerate() {
+                let history_labels = match words(k - 1, h) {
+                    Some(labels) => labels,
+                    None => continue,
+                };
+                for i in history.children() {
+                    if let Some(entry) = tables[k].get(i) {
+                        let mut labels = history_labels.clone();
+                        labels.push(entry.label());
+                        write_entry(&labels, entry)?;
+                    }
+                }
+            }
+        }
+    }
+    writeln!(f_write).map_err(write_error)?;
+    writeln!(f_write, "\\end\\").map_err(write_error)?;
+    f_write.flush().map_err(write_error)
+}
+
+/// Key that orders log probabilities the same way as the floats, so they can be ranked with the `LimitedMinHeap`
+fn order_key(log_prob: f32) -> u32 {
+    let bits = log_prob.to_bits();
+    if bits >> 31 == 1 {
+        !bits
+    } else {
+        bits | 0x8000_0000
+    }
+}
+
+/// Convert a log10 from an ARPA file to a natural log
+fn from_log10(log10: f32) -> f32 {
+    if log10 <= ARPA_LOG_ZERO {
+        f32::NEG_INFINITY
+    } else {
+        log10 / std::f32::consts::LOG10_E
+    }
+}
+
+/// An ngram of an ARPA file whose words were translated to symbols
+struct ArpaNGram {
+    symbols: Vec<u32>,
+    log_prob: f32,
+    backoff: f32,
+}
+
+/// Read a model in the ARPA format and convert it to the tables `generate` builds
+///
+/// The vocabulary is chosen the same way as by `generate`: only words from the dictionary are kept
+/// and of those only the `max_no_words` with the highest unigram probability (ties at the threshold are all kept).
+/// Ngrams with other words or whose prefix or suffix was not kept are dropped.
+/// The probabilities and backoff weights are taken over as they are, so they are not renormalized for the smaller vocabulary.
+///
+/// With `reserved_symbols`, `<s>`, `</s>` and `<unk>` are kept if the file has them, like `generate` keeps them,
+/// and they don't take up places of the vocabulary. Unlike `generate`, the ngrams with words that are not kept
+/// are still dropped instead of becoming `<unk>`: the file only has their probabilities, which can't be merged
+/// with the ones of the `<unk>` ngrams without recomputing the backoff weights of their contexts.
+pub fn import_arpa<P: AsRef<Path>, Q: AsRef<Path>>(
+    arpa_file: P,
+    dictionary_file: Q,
+    max_no_words: usize,
+    reserved_symbols: bool,
+) -> Result<ModelTables> {
+    let dictionary: HashSet<String> =
+        WordListIterator::new(dictionary_file)?.collect::<Result<_>>()?;
+    let mut lines = LinesIterator::new(arpa_file)?;
+
+    // Skip everything up to the header and read the number of ngrams of each order
+    let mut no_ngrams = Vec::new();
+    let mut in_header = false;
+    let mut section = None;
+    while let Some(line) = lines.next() {
+        let line = line?;
+        let line = line.trim();
+        if !in_header {
+            in_header = line == "\\data\\";
+            continue;
+        }
+        if let Some(counts) = line.strip_prefix("ngram ") {
+            let no = counts.split_once('=').and_then(|(n, no)| {
+                Some((
+                    n.trim().parse::<usize>().ok()?,
+                    no.trim().parse::<usize>().ok()?,
+                ))
+            });
+            match no {
+                Some((n, no)) if n == no_ngrams.len() + 1 => no_ngrams.push(no),
+                _ => return Err(lines.parse_error(line, "Invalid ngram count".to_string())),
+            }
+        } else if line.starts_with('\\') {
+            section = Some(line.to_string());
+            break;
+        } else if !line.is_empty() {
+            return Err(lines.parse_error(line, "Expected an ngram count".to_string()));
+        }
+    }
+    if no_ngrams.is_empty() {
+        return Err(Error::InconsistentModel(format!(
+            "{} has no \\data\\ section with ngram counts",
+            lines.path().display()
+        )));
+    }
+    let max_ngram_len = no_ngrams.len();
+
+    let mut model = ModelTables {
+        symbols: Vec::new(),
+        ngrams: Vec::with_capacity(max_ngram_len),
+    };
+    let mut sybt: HashMap<String, u32> = HashMap::new();
+    // The symbols of the ngrams of the last order, sorted, so the prefixes and suffixes can be found with a binary search
+    let mut lower_order_symbols: Vec<Vec<u32>> = Vec::new();
+
+    for n in 1..=max_ngram_len {
+        let expected_section = format!("\\{}-grams:", n);
+        match &section {
+            Some(section) if *section == expected_section => {}
+            _ => {
+                return Err(Error::InconsistentModel(format!(
+                    "Expected the section {} in {}",
+                    expected_section,
+                    lines.path().display()
+                )))
+            }
+        }
+
+        // Read all ngrams of the section
+        let mut unigrams = Vec::new();
+        let mut threshold = 0;
+        let mut min_heap = LimitedMinHeap::new(max_no_words);
+        let mut ngrams = Vec::with_capacity(if n == 1 { 0 } else { no_ngrams[n - 1] });
+        section = None;
+        'line_loop: while let Some(line) = lines.next() {
+            let line = line?;
+            let line = line.trim();
+            if line.starts_with('\\') {
+                section = Some(line.to_string());
+                break;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() != n + 1 && columns.len() != n + 2 {
+                return Err(lines.parse_error(
+                    line,
+                    format!(
+                        "Expected a probability, {} words and an optional backoff weight",
+                        n
+                    ),
+                ));
+            }
+            let parse_log10 = |value: &str| {
+                value.parse::<f32>().map_err(|err| {
+                    lines.parse_error(
+                        line,
+                        format!("Invalid log probability '{}' ({})", value, err),
+                    )
+                })
+            };
+            let log10_prob = parse_log10(columns[0])?;
+            let backoff = match columns.get(n + 1) {
+                Some(backoff) => from_log10(parse_log10(backoff)?),
+                None => 0.0,
+            };
+
+            if n == 1 {
+                // Intersect the unigrams with the dictionary and keep track of the k most probable ones
+                let word = columns[1];
+                let reserved = reserved_symbols
+                    && [SENTENCE_START, SENTENCE_END, UNKNOWN_WORD].contains(&word);
+                if reserved {
+                    unigrams.push((word.to_string(), None, from_log10(log10_prob), backoff));
+                    continue;
+                }
+                if !dictionary.contains(word) {
+                    continue;
+                }
+                let key = order_key(log10_prob);
+                if let Some(new_k_highest) = min_heap.insert(key) {
+                    threshold = new_k_highest;
+                }
+                if key < threshold {
+                    continue;
+                }
+                unigrams.push((word.to_string(), Some(key), from_log10(log10_prob), backoff));
+            } else {
+                let mut symbols = Vec::with_capacity(n);
+                for word in &columns[1..=n] {
+                    match sybt.get(*word) {
+                        Some(&id) => symbols.push(id),
+                        None => continue 'line_loop,
+                    }
+                }
+                ngrams.push(ArpaNGram {
+                    symbols,
+                    log_prob: from_log10(log10_prob),
+                    backoff,
+                });
+            }
+        }
+
+        if n == 1 {
+            // Build the symbol table from the reserved symbols and the unigrams that meet the final threshold
+            let mut table = Vec::new();
+            for (word, key, log_prob, backoff) in unigrams {
+                if key.is_none_or(|key| key >= threshold) {
+                    let id = model.symbols.len() as u32;
+                    sybt.insert(word.clone(), id);
+                    model.symbols.push(word);
+                    lower_order_symbols.push(vec![id]);
+                    table.push(NGramRecord {
+                        label: id,
+                        log_prob,
+                        suffix: id,
+                        backoff,
+                        ..Default::default()
+                    });
+                }
+            }
+            model.ngrams.push(table);
+            continue;
+        }
+
+        // Sort the ngrams so the ones with the same prefix are next to each other
+        ngrams.sort_unstable_by(|a, b| a.symbols.cmp(&b.symbols));
+        ngrams.dedup_by(|a, b| a.symbols == b.symbols);
+
+        let (lower, _) = model.ngrams.split_at_mut(n - 1);
+        let lower_table = &mut lower[n - 2];
+        let mut table = Vec::with_capacity(ngrams.len());
+        let mut symbols = Vec::with_capacity(ngrams.len());
+        for ngram in ngrams {
+            let prefix =
+                lower_order_symbols.binary_search_by(|s| s[..].cmp(&ngram.symbols[..n - 1]));
+            let suffix = lower_order_symbols.binary_search_by(|s| s[..].cmp(&ngram.symbols[1..]));
+            let (prefix, suffix) = match (prefix, suffix) {
+                (Ok(prefix), Ok(suffix)) => (prefix, suffix),
+                _ => continue,
+            };
+            let idx = table.len() as u32;
+            let prefix_entry = &mut lower_table[prefix];
+            if prefix_entry.no_longer_ngrams == 0 {
+                prefix_entry.offset = idx;
+            }
+            prefix_entry.no_longer_ngrams += 1;
+            table.push(NGramRecord {
+                label: ngram.symbols[n - 1],
+                log_prob: ngram.log_prob,
+                suffix: suffix as u32,
+                backoff: ngram.backoff,
+                ..Default::default()
+            });
+            symbols.push(ngram.symbols);
+        }
+        model.ngrams.push(table);
+        lower_order_symbols = symbols;
+    }
+
+    match section.as_deref() {
+        Some("\\end\\") => Ok(model),
+        Some(section) => Err(Error::InconsistentModel(format!(
+            "Unexpected section {} in {}",
+            section,
+            lines.path().display()
+        ))),
+        None => Err(Error::InconsistentModel(format!(
+            "{} ends without \\end\\",
+            lines.path().display()
+        ))),
+    }
+}
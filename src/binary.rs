@@ -0,0 +1,568 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::error::{Error, Result};
+use crate::model::{ModelTables, NGramRecord, TableEntry};
+use crate::quantize::{Codebook, Quantization};
+
+/// First bytes of every binary model file
+const MAGIC: [u8; 8] = *b"NGRAMLM\0";
+/// Version of the layout, files with another version are rejected
+const VERSION: u32 = 1;
+/// Set in the flags of the header if the records of all but the highest order have a backoff weight
+const FLAG_BACKOFF: u32 = 1;
+/// Set in the flags of the header if the log probabilities and backoff weights of all but the unigrams are quantized
+const FLAG_QUANTIZED: u32 = 2;
+/// Size of the fixed part of the header in bytes
+const HEADER_LEN: usize = 28;
+
+impl ModelTables {
+    /// Write the model to a file in the binary format, with quantized values if a quantization is given
+    /// The backoff weights are only written if the model has any
+    pub fn write_binary<P: AsRef<Path>>(
+        &self,
+        path: P,
+        quantization: Option<Quantization>,
+    ) -> Result<()> {
+        let with_backoff = self.has_backoff();
+        let tables: Vec<&[NGramRecord]> = self.ngrams.iter().map(|table| &table[..]).collect();
+        write_binary(
+            path.as_ref(),
+            &self.symbols,
+            &tables,
+            with_backoff,
+            quantization,
+        )
+    }
+
+    /// Read a model in the binary format completely into memory
+    pub fn load_binary<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mapped = MappedTables::open(path)?;
+        let ngrams = (0..mapped.max_ngram_len())
+            .map(|n| {
+                (0..mapped.len(n))
+                    .map(|idx| mapped.record(n, idx))
+                    .collect()
+            })
+            .collect();
+        Ok(Self {
+            symbols: mapped.symbols,
+            ngrams,
+        })
+    }
+}
+
+/// Which columns the records of an order have, they are the same ones the text tables have
+#[derive(Clone, Copy, Debug)]
+struct Layout {
+    has_label: bool,
+    has_suffix: bool,
+    has_children: bool,
+    has_backoff: bool,
+    /// Size of the log probability and the backoff weight, 4 bytes unless they are quantized
+    value_bytes: usize,
+}
+
+impl Layout {
+    fn new(n: usize, max_ngram_len: usize, with_backoff: bool, quantized_bytes: usize) -> Self {
+        Self {
+            has_label: n > 1,
+            has_suffix: n >= 3,
+            has_children: n < max_ngram_len,
+            has_backoff: with_backoff && n < max_ngram_len,
+            value_bytes: if n > 1 { quantized_bytes } else { 4 },
+        }
+    }
+
+    /// Size of a record in bytes, the label, suffix, offset and number of longer ngrams take 4 bytes each
+    fn width(&self) -> usize {
+        4 * (self.has_label as usize + self.has_suffix as usize + 2 * self.has_children as usize)
+            + self.value_bytes * (1 + self.has_backoff as usize)
+    }
+
+    fn is_quantized(&self) -> bool {
+        self.value_bytes < 4
+    }
+}
+
+/// Number of bytes a value takes, 4 if it is not quantized
+fn quantized_bytes(quantization: Option<Quantization>) -> Result<usize> {
+    quantization.map_or(Ok(4), |quantization| quantization.value_bytes())
+}
+
+/// Size of the records of all orders in the binary format in bytes
+pub(crate) fn records_len(
+    tables: &ModelTables,
+    with_backoff: bool,
+    quantization: Option<Quantization>,
+) -> Result<u64> {
+    let quantized_bytes = quantized_bytes(quantization)?;
+    let max_ngram_len = tables.max_ngram_len();
+    Ok(tables
+        .ngrams
+        .iter()
+        .enumerate()
+        .map(|(i, table)| {
+            let layout = Layout::new(i + 1, max_ngram_len, with_backoff, quantized_bytes);
+            (table.len() * layout.width()) as u64
+        })
+        .sum())
+}
+
+/// Append a value to a record, either as it is or as its index in the codebook
+fn push_value(record: &mut Vec<u8>, value: f32, codebook: Option<&Codebook>, value_bytes: usize) {
+    match codebook {
+        None => record.extend_from_slice(&value.to_le_bytes()),
+        Some(codebook) => {
+            record.extend_from_slice(&codebook.encode(value).to_le_bytes()[..value_bytes])
+        }
+    }
+}
+
+/// Write the tables to a file in the binary format
+///
+/// All numbers are little endian. The file starts with a header:
+/// - the magic bytes `NGRAMLM\0` and the version
+/// - the length of the longest ngrams N, the flags, the number of symbols and the length of the symbol table in bytes
+/// - for each order the number of ngrams and whether the labels within every range of children of that order are sorted
+/// - if the values are quantized, the number of bits of a quantized value
+///
+/// It is followed by the words of the symbol table, each one terminated by a newline and padded with zeros to a multiple of 4 bytes.
+/// If the values are quantized, the codebooks of the orders from 2 up come next, for each order the one of the log probabilities
+/// and, if the order has backoff weights, the one of the backoff weights. Each codebook is its number of values followed by the values.
+/// Then come the tables, with fixed-width records that have the same columns as the lines of the text tables.
+/// Quantized values take 1 or 2 bytes, everything else 4 bytes.
+pub(crate) fn write_binary<E: TableEntry>(
+    path: &Path,
+    symbols: &[String],
+    tables: &[&[E]],
+    with_backoff: bool,
+    quantization: Option<Quantization>,
+) -> Result<()> {
+    let quantized_bytes = quantized_bytes(quantization)?;
+    let write_error = |err| Error::io(path, err);
+    let mut f_write = BufWriter::new(File::create(path).map_err(write_error)?);
+    let max_ngram_len = tables.len();
+
+    let mut symbol_table: Vec<u8> = Vec::new();
+    for symbol in symbols {
+        symbol_table.extend_from_slice(symbol.as_bytes());
+        symbol_table.push(b'\n');
+    }
+    let padding = (4 - symbol_table.len() % 4) % 4;
+
+    let mut header = Vec::with_capacity(HEADER_LEN + 8 * max_ngram_len);
+    header.extend_from_slice(&MAGIC);
+    let mut flags = if with_backoff { FLAG_BACKOFF } else { 0 };
+    if quantization.is_some() {
+        flags |= FLAG_QUANTIZED;
+    }
+    for value in [
+        VERSION,
+        max_ngram_len as u32,
+        flags,
+        symbols.len() as u32,
+        symbol_table.len() as u32,
+    ] {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    for (i, table) in tables.iter().enumerate() {
+        // The unigrams are never searched, because their index is their symbol
+        let sorted = i == 0 || children_sorted(tables[i - 1], table);
+        header.extend_from_slice(&(table.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(sorted as u32).to_le_bytes());
+    }
+    if let Some(quantization) = quantization {
+        header.extend_from_slice(&(quantization.bits() as u32).to_le_bytes());
+    }
+    f_write.write_all(&header).map_err(write_error)?;
+    f_write.write_all(&symbol_table).map_err(write_error)?;
+    f_write.write_all(&[0; 3][..padding]).map_err(write_error)?;
+
+    let layouts: Vec<Layout> = (1..=max_ngram_len)
+        .map(|n| Layout::new(n, max_ngram_len, with_backoff, quantized_bytes))
+        .collect();
+    let mut codebooks = Vec::with_capacity(max_ngram_len);
+    for (table, layout) in tables.iter().zip(&layouts) {
+        let (log_probs, backoffs) = match quantization {
+            Some(quantization) if layout.is_quantized() => {
+                let log_probs = quantization.codebook(table.iter().map(|entry| entry.log_prob()));
+                let backoffs = layout
+                    .has_backoff
+                    .then(|| quantization.codebook(table.iter().map(|entry| entry.backoff())));
+                (Some(log_probs), backoffs)
+            }
+            _ => (None, None),
+        };
+        for codebook in log_probs.iter().chain(&backoffs) {
+            let mut bytes = Vec::with_capacity(4 * (codebook.centers.len() + 1));
+            bytes.extend_from_slice(&(codebook.centers.len() as u32).to_le_bytes());
+            for center in &codebook.centers {
+                bytes.extend_from_slice(&center.to_le_bytes());
+            }
+            f_write.write_all(&bytes).map_err(write_error)?;
+        }
+        codebooks.push((log_probs, backoffs));
+    }
+
+    for ((table, layout), (log_probs, backoffs)) in tables.iter().zip(&layouts).zip(&codebooks) {
+        let mut record = Vec::with_capacity(layout.width());
+        for entry in table.iter() {
+            record.clear();
+            if layout.has_label {
+                record.extend_from_slice(&entry.label().to_le_bytes());
+            }
+            push_value(
+                &mut record,
+                entry.log_prob(),
+                log_probs.as_ref(),
+                layout.value_bytes,
+            );
+            if layout.has_suffix {
+                record.extend_from_slice(&entry.suffix().to_le_bytes());
+            }
+            if layout.has_children {
+                record.extend_from_slice(&entry.offset().to_le_bytes());
+                record.extend_from_slice(&entry.no_longer_ngrams().to_le_bytes());
+            }
+            if layout.has_backoff {
+                push_value(
+                    &mut record,
+                    entry.backoff(),
+                    backoffs.as_ref(),
+                    layout.value_bytes,
+                );
+            }
+            f_write.write_all(&record).map_err(write_error)?;
+        }
+    }
+    f_write.flush().map_err(write_error)
+}
+
+/// Whether the labels within the range of children of every history are sorted
+fn children_sorted<E: TableEntry>(histories: &[E], children: &[E]) -> bool {
+    histories.iter().all(|history| {
+        let range = history.children();
+        children.get(range).is_some_and(|children| {
+            children
+                .windows(2)
+                .all(|pair| pair[0].label() < pair[1].label())
+        })
+    })
+}
+
+/// Position of the records of an order in a binary model file and the codebooks of its quantized values
+#[derive(Clone, Debug)]
+struct MappedOrder {
+    start: usize,
+    len: usize,
+    layout: Layout,
+    log_probs: Option<Codebook>,
+    backoffs: Option<Codebook>,
+}
+
+/// A model in the binary format that is memory mapped, so its records are only read from the file when they are needed
+pub(crate) struct MappedTables {
+    data: Mmap,
+    pub(crate) symbols: Vec<String>,
+    orders: Vec<MappedOrder>,
+    /// Whether the labels within every range of children of the order are sorted
+    pub(crate) sorted_children: Vec<bool>,
+}
+
+/// Reads the numbers of the header one after the other
+struct HeaderReader<'a> {
+    path: &'a Path,
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn error(&self, reason: String) -> Error {
+        Error::InvalidModelFile {
+            path: self.path.to_path_buf(),
+            reason,
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let data = self.data;
+        let bytes = data
+            .get(self.position..self.position + len)
+            .ok_or_else(|| self.error("The file is truncated".to_string()))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(read_u32(self.bytes(4)?, 0))
+    }
+
+    fn codebook(&mut self) -> Result<Codebook> {
+        let len = self.u32()? as usize;
+        let centers = self
+            .bytes(4 * len)?
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        Ok(Codebook { centers })
+    }
+}
+
+fn read_u32(data: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes([
+        data[position],
+        data[position + 1],
+        data[position + 2],
+        data[position + 3],
+    ])
+}
+
+/// Reads the columns of a record one after the other
+struct RecordReader<'a> {
+    data: &'a [u8],
+    value_bytes: usize,
+}
+
+impl RecordReader<'_> {
+    fn u32(&mut self) -> u32 {
+        let value = read_u32(self.data, 0);
+        self.data = &self.data[4..];
+        value
+    }
+
+    /// A log probability or backoff weight, looked up in the codebook if it is quantized
+    fn value(&mut self, codebook: Option<&Codebook>) -> f32 {
+        let value = match codebook {
+            None => f32::from_bits(read_u32(self.data, 0)),
+            Some(codebook) => {
+                let mut index = [0; 2];
+                index[..self.value_bytes].copy_from_slice(&self.data[..self.value_bytes]);
+                codebook.decode(u16::from_le_bytes(index))
+            }
+        };
+        self.data = &self.data[self.value_bytes..];
+        value
+    }
+}
+
+impl MappedTables {
+    /// Memory map a model in the binary format and check that its header fits to the size of the file and the records fit together
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|err| Error::io(path, err))?;
+        // Safety: The file must not be modified while it is mapped, like all other files the model is read from
+        let data = unsafe { Mmap::map(&file) }.map_err(|err| Error::io(path, err))?;
+
+        let mut reader = HeaderReader {
+            path,
+            data: &data,
+            position: 0,
+        };
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(reader.error("The magic bytes are missing".to_string()));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(reader.error(format!(
+                "Version {} is not supported, only version {}",
+                version, VERSION
+            )));
+        }
+        let max_ngram_len = reader.u32()? as usize;
+        if max_ngram_len == 0 {
+            return Err(reader.error("The model has no ngrams".to_string()));
+        }
+        let flags = reader.u32()?;
+        if flags & !(FLAG_BACKOFF | FLAG_QUANTIZED) != 0 {
+            return Err(reader.error(format!("Unknown flags {:#x}", flags)));
+        }
+        let with_backoff = flags & FLAG_BACKOFF != 0;
+        let no_symbols = reader.u32()? as usize;
+        let symbol_table_len = reader.u32()? as usize;
+
+        let mut lens = Vec::with_capacity(max_ngram_len);
+        let mut sorted_children = Vec::with_capacity(max_ngram_len);
+        for _ in 0..max_ngram_len {
+            lens.push(reader.u32()? as usize);
+            sorted_children.push(reader.u32()? != 0);
+        }
+        let quantized_bytes = if flags & FLAG_QUANTIZED != 0 {
+            match reader.u32()? {
+                8 => 1,
+                16 => 2,
+                bits => {
+                    return Err(reader.error(format!("{} bit quantization is not supported", bits)))
+                }
+            }
+        } else {
+            4
+        };
+
+        let symbol_table = reader.bytes(symbol_table_len)?;
+        let symbols: Vec<String> = std::str::from_utf8(symbol_table)
+            .map_err(|err| reader.error(format!("The symbol table is not valid UTF-8 ({})", err)))?
+            .split_terminator('\n')
+            .map(|symbol| symbol.to_string())
+            .collect();
+        if symbols.len() != no_symbols {
+            return Err(reader.error(format!(
+                "Expected {} symbols, found {}",
+                no_symbols,
+                symbols.len()
+            )));
+        }
+        reader.bytes((4 - symbol_table_len % 4) % 4)?;
+
+        let mut orders = Vec::with_capacity(max_ngram_len);
+        for (i, &len) in lens.iter().enumerate() {
+            let layout = Layout::new(i + 1, max_ngram_len, with_backoff, quantized_bytes);
+            let (log_probs, backoffs) = if layout.is_quantized() {
+                let log_probs = reader.codebook()?;
+                let backoffs = if layout.has_backoff {
+                    Some(reader.codebook()?)
+                } else {
+                    None
+                };
+                (Some(log_probs), backoffs)
+            } else {
+                (None, None)
+            };
+            orders.push(MappedOrder {
+                start: 0,
+                len,
+                layout,
+                log_probs,
+                backoffs,
+            });
+        }
+        for order in &mut orders {
+            order.start = reader.position;
+            reader.bytes(order.len * order.layout.width())?;
+        }
+        if reader.position != data.len() {
+            return Err(reader.error(format!(
+                "Expected {} bytes, found {}",
+                reader.position,
+                data.len()
+            )));
+        }
+
+        let tables = Self {
+            data,
+            symbols,
+            orders,
+            sorted_children,
+        };
+        tables
+            .check_records()
+            .map_err(|reason| Error::InvalidModelFile {
+                path: path.to_path_buf(),
+                reason,
+            })?;
+        Ok(tables)
+    }
+
+    /// Check that there is a unigram for every symbol, the labels are symbols, the children are in bounds
+    /// of the next order and the children are sorted by their labels in the orders whose flag says so
+    fn check_records(&self) -> std::result::Result<(), String> {
+        let no_symbols = self.symbols.len();
+        if self.len(0) != no_symbols {
+            return Err(format!(
+                "Expected {} unigrams, one for each symbol, found {}",
+                no_symbols,
+                self.len(0)
+            ));
+        }
+        for n in 1..self.max_ngram_len() {
+            let no_children = self.len(n);
+            for idx in 0..no_children {
+                let label = self.record(n, idx).label;
+                if label as usize >= no_symbols {
+                    return Err(format!(
+                        "The {}gram {} has the label {}, but there are only {} symbols",
+                        n + 1,
+                        idx,
+                        label,
+                        no_symbols
+                    ));
+                }
+            }
+            for idx in 0..self.len(n - 1) {
+                let children = self.record(n - 1, idx).children();
+                if children.end > no_children {
+                    return Err(format!(
+                        "The {}gram {} has the children {:?}, but there are only {} {}grams",
+                        n,
+                        idx,
+                        children,
+                        no_children,
+                        n + 1
+                    ));
+                }
+                let labels: Vec<u32> = children.map(|child| self.record(n, child).label).collect();
+                if self.sorted_children[n] && !labels.windows(2).all(|pair| pair[0] < pair[1]) {
+                    return Err(format!(
+                        "The children of the {}gram {} are not sorted by their labels, although the {}grams are flagged as sorted",
+                        n,
+                        idx,
+                        n + 1
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Length of the longest ngrams of the model
+    pub(crate) fn max_ngram_len(&self) -> usize {
+        self.orders.len()
+    }
+
+    /// Number of records of the table n (starting at 0)
+    pub(crate) fn len(&self, n: usize) -> usize {
+        self.orders[n].len
+    }
+
+    /// Decode the record at index idx of the table n (starting at 0)
+    pub(crate) fn record(&self, n: usize, idx: usize) -> NGramRecord {
+        let order = &self.orders[n];
+        let layout = order.layout;
+        let mut reader = RecordReader {
+            data: &self.data[order.start + idx * layout.width()..],
+            value_bytes: layout.value_bytes,
+        };
+        let label = if layout.has_label {
+            reader.u32()
+        } else {
+            idx as u32
+        };
+        let log_prob = reader.value(order.log_probs.as_ref());
+        let suffix = if layout.has_suffix {
+            reader.u32()
+        } else {
+            label
+        };
+        let (offset, no_longer_ngrams) = if layout.has_children {
+            (reader.u32(), reader.u32())
+        } else {
+            (0, 0)
+        };
+        let backoff = if layout.has_backoff {
+            reader.value(order.backoffs.as_ref())
+        } else {
+            0.0
+        };
+        NGramRecord {
+            label,
+            log_prob,
+            suffix,
+            offset,
+            no_longer_ngrams,
+            backoff,
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::input::InputFormat;
+use crate::prune::Pruning;
+use crate::quantize::Quantization;
+use crate::smoothing::Smoothing;
+use crate::vocabulary::{TieBreak, VocabularySelection};
+
+/// Everything `generate` needs to know to build a language model
+#[derive(Clone, Debug)]
+pub struct GeneratorConfig {
+    /// Files with the ngrams and their counts, the file at index i contains the ngrams of length i + 1
+    pub ngram_files: Vec<PathBuf>,
+    /// File with the allowed words, one word per line
+    pub dictionary: PathBuf,
+    /// Directory the symbol table and the translated ngrams are written to
+    pub output_dir: PathBuf,
+    /// Maximum number of words in the vocabulary
+    pub max_no_words: usize,
+    /// How the words of the vocabulary are chosen among the allowed unigrams
+    pub vocabulary: VocabularySelection,
+    /// Which of the words with the same count as the last word of the vocabulary are kept
+    pub tie_break: TieBreak,
+    /// File with words that are always kept if they are in the unigram file, one word per line
+    pub include_words: Option<PathBuf>,
+    /// File with words that are never kept, one word per line
+    pub deny_words: Option<PathBuf>,
+    /// How the probabilities and backoff weights are estimated from the counts
+    pub smoothing: Smoothing,
+    /// Which of the ngrams of length 2 or more are removed from the model
+    pub pruning: Pruning,
+    /// File the model is additionally written to in the ARPA format
+    pub arpa_file: Option<PathBuf>,
+    /// File the model is additionally written to in the binary format
+    pub binary_file: Option<PathBuf>,
+    /// How the values of the binary format are quantized, they are stored exactly if it is None
+    pub quantization: Option<Quantization>,
+    /// Maximum number of bytes the ngrams that are sorted may take in memory before they are spilled to the output directory
+    /// If it is None, the ngrams are kept in the order of the input files and the whole model is kept in memory
+    pub memory_budget: Option<usize>,
+    /// Whether the ngrams of each order are sorted before they are added to the model
+    /// Otherwise the ngrams with the same prefix need to be next to each other in the input files
+    pub sort_ngrams: bool,
+    /// Number of threads that parse the ngram files and translate their words to symbols
+    pub threads: usize,
+    /// Format of the lines of the ngram files
+    pub input_format: InputFormat,
+    /// Whether `<s>`, `</s>` and `<unk>` are reserved symbols of the vocabulary
+    pub reserved_symbols: bool,
+}
+
+impl GeneratorConfig {
+    /// Create a builder for a config
+    pub fn builder() -> GeneratorConfigBuilder {
+        GeneratorConfigBuilder::default()
+    }
+
+    /// Create a config for the folder layout this crate always used:
+    /// the ngrams in `<root>/ngrams_ALL/<n>gms.txt`, the dictionary in `<root>/dict/words_allow.txt`
+    /// and the results in `<root>/ngrams_result/`
+    pub fn from_root<P: AsRef<Path>>(
+        root: P,
+        max_no_words: usize,
+        max_ngram_len: usize,
+    ) -> Result<Self> {
+        let root = root.as_ref();
+        Self::builder()
+            .ngrams_dir(root.join("ngrams_ALL"))
+            .dictionary(root.join("dict").join("words_allow.txt"))
+            .output_dir(root.join("ngrams_result"))
+            .max_no_words(max_no_words)
+            .max_ngram_len(max_ngram_len)
+            .build()
+    }
+
+    /// Length of the longest ngrams of the model
+    pub fn max_ngram_len(&self) -> usize {
+        self.ngram_files.len()
+    }
+
+    /// File the ngrams of length n are written to
+    pub fn output_file(&self, n: usize) -> PathBuf {
+        self.output_dir.join(format!("{}gms.txt", n))
+    }
+
+    /// File the symbol table is written to
+    pub fn symbol_table_file(&self) -> PathBuf {
+        self.output_dir.join("symt.txt")
+    }
+}
+
+/// Builder for a `GeneratorConfig`
+///
+/// The ngram files can either be set individually or be looked up as `<n>gms.txt` in a directory.
+/// Files that were set individually take precedence.
+/// If there is no `<n>gms.txt`, a compressed `<n>gms.txt.<ext>` or `<n>gms.<ext>` or the first of the shards
+/// `<n>gms-00000-of-<parts>[.<ext>]` is used, where `<ext>` is one of `gz`, `zst`, `xz` and `bz2`.
+#[derive(Clone, Debug)]
+pub struct GeneratorConfigBuilder {
+    ngram_files: Vec<Option<PathBuf>>,
+    ngrams_dir: Option<PathBuf>,
+    dictionary: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    max_no_words: usize,
+    max_ngram_len: usize,
+    vocabulary: VocabularySelection,
+    tie_break: TieBreak,
+    include_words: Option<PathBuf>,
+    deny_words: Option<PathBuf>,
+    smoothing: Smoothing,
+    pruning: Pruning,
+    arpa_file: Option<PathBuf>,
+    binary_file: Option<PathBuf>,
+    quantization: Option<Quantization>,
+    memory_budget: Option<usize>,
+    sort_ngrams: bool,
+    threads: usize,
+    input_format: InputFormat,
+    reserved_symbols: bool,
+}
+
+impl Default for GeneratorConfigBuilder {
+    fn default() -> Self {
+        Self {
+            ngram_files: Vec::new(),
+            ngrams_dir: None,
+            dictionary: None,
+            output_dir: None,
+            max_no_words: 30_000,
+            max_ngram_len: 3,
+            vocabulary: VocabularySelection::default(),
+            tie_break: TieBreak::default(),
+            include_words: None,
+            deny_words: None,
+            smoothing: Smoothing::default(),
+            pruning: Pruning::default(),
+            arpa_file: None,
+            binary_file: None,
+            quantization: None,
+            memory_budget: None,
+            sort_ngrams: false,
+            threads: 1,
+            input_format: InputFormat::default(),
+            reserved_symbols: false,
+        }
+    }
+}
+
+impl GeneratorConfigBuilder {
+    /// Set the file with the ngrams of length n
+    pub fn ngram_file<P: Into<PathBuf>>(mut self, n: usize, file: P) -> Self {
+        assert!(n > 0, "The ngrams need to have a length of at least 1");
+        if self.ngram_files.len() < n {
+            self.ngram_files.resize(n, None);
+        }
+        self.ngram_files[n - 1] = Some(file.into());
+        self
+    }
+
+    /// Set the directory in which the ngrams of length n are looked up as `<n>gms.txt`, compressed or split into shards
+    pub fn ngrams_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.ngrams_dir = Some(dir.into());
+        self
+    }
+
+    /// Set the file with the allowed words
+    pub fn dictionary<P: Into<PathBuf>>(mut self, file: P) -> Self {
+        self.dictionary = Some(file.into());
+        self
+    }
+
+    /// Set the directory the results are written to
+    pub fn output_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.output_dir = Some(dir.into());
+        self
+    }
+
+    /// Set the maximum number of words in the vocabulary (default: 30,000)
+    pub fn max_no_words(mut self, max_no_words: usize) -> Self {
+        self.max_no_words = max_no_words;
+        self
+    }
+
+    /// Set the length of the longest ngrams of the model (default: 3)
+    pub fn max_ngram_len(mut self, max_ngram_len: usize) -> Self {
+        self.max_ngram_len = max_ngram_len;
+        self
+    }
+
+    /// Set how the words of the vocabulary are chosen among the allowed unigrams (default: the most frequent)
+    pub fn vocabulary(mut self, vocabulary: VocabularySelection) -> Self {
+        self.vocabulary = vocabulary;
+        self
+    }
+
+    /// Set which of the words with the same count as the last word of the vocabulary are kept (default: all of them)
+    /// With `TieBreak::FirstSeen` or `TieBreak::Lexicographic` the vocabulary never has more than the maximum number of words,
+    /// unless more words are included, and the summary tells how many tied words were dropped.
+    pub fn tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        self
+    }
+
+    /// Set a file with words that are always kept, even if they are not in the dictionary or not frequent enough
+    /// They count towards the maximum number of words, but are kept even if there are more of them.
+    pub fn include_words<P: Into<PathBuf>>(mut self, file: P) -> Self {
+        self.include_words = Some(file.into());
+        self
+    }
+
+    /// Set a file with words that are never kept, even if they are in the dictionary or the include list
+    pub fn deny_words<P: Into<PathBuf>>(mut self, file: P) -> Self {
+        self.deny_words = Some(file.into());
+        self
+    }
+
+    /// Set how the probabilities and backoff weights are estimated (default: maximum likelihood without backoff weights)
+    pub fn smoothing(mut self, smoothing: Smoothing) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    /// Set which of the ngrams of length 2 or more are removed from the model (default: none)
+    /// The cutoffs and the maximum numbers of continuations are applied while the ngrams are read,
+    /// the pruning by relative entropy once the probabilities are known, which needs the highest order in memory.
+    pub fn pruning(mut self, pruning: Pruning) -> Self {
+        self.pruning = pruning;
+        self
+    }
+
+    /// Set a file the model is additionally written to in the ARPA format
+    pub fn arpa_file<P: Into<PathBuf>>(mut self, file: P) -> Self {
+        self.arpa_file = Some(file.into());
+        self
+    }
+
+    /// Set a file the model is additionally written to in the binary format
+    pub fn binary_file<P: Into<PathBuf>>(mut self, file: P) -> Self {
+        self.binary_file = Some(file.into());
+        self
+    }
+
+    /// Set how the values of the binary format are quantized (default: not at all)
+    pub fn quantization(mut self, quantization: Quantization) -> Self {
+        self.quantization = Some(quantization);
+        self
+    }
+
+    /// Sort the ngrams of each order by their symbols with at most about `bytes` of memory, spilling them to the output directory
+    ///
+    /// The budget covers the ngrams that are sorted and the buffers of the runs while they are merged, at most 64 at once.
+    /// The tables of the lower orders are still kept in memory on top of it, but they don't need an index to look up the ngrams.
+    /// With maximum likelihood estimates and without an ARPA or binary file the highest order is written without keeping it in memory.
+    /// The tables are sorted by the symbols, so they are in a different order than without a memory budget.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Sort the ngrams of each order in memory, so the input files don't need to be grouped by prefix (default: false)
+    /// Without sorting, input files whose ngrams are not grouped by prefix make `generate` fail.
+    /// With a memory budget the ngrams are always sorted.
+    pub fn sort_ngrams(mut self, sort_ngrams: bool) -> Self {
+        self.sort_ngrams = sort_ngrams;
+        self
+    }
+
+    /// Parse the ngram files and translate their words to symbols on this many threads (default: 1)
+    /// The model does not depend on the number of threads.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Set the format of the lines of the ngram files (default: `InputFormat::Counts`)
+    /// Files in the Google Books format are always read on one thread, because the lines of an ngram need to be aggregated.
+    pub fn input_format(mut self, input_format: InputFormat) -> Self {
+        self.input_format = input_format;
+        self
+    }
+
+    /// Reserve symbols for `<s>`, `</s>` and `<unk>` in the vocabulary (default: false)
+    /// The sentence boundaries are kept if they are in the unigram file, even if they are not in the dictionary.
+    /// All other words that are not kept become `<unk>` instead of dropping the ngrams with them, so their probability mass is kept.
+    /// The ngrams are sorted then, because ngrams that became the same need to be merged.
+    pub fn reserved_symbols(mut self, reserved_symbols: bool) -> Self {
+        self.reserved_symbols = reserved_symbols;
+        self
+    }
+
+    /// Build the config
+    /// Fails if the order is 0, the dictionary or the output directory were not set or if there is no file for one of the orders
+    pub fn build(self) -> Result<GeneratorConfig> {
+        if self.max_ngram_len == 0 {
+            return Err(Error::InvalidConfig(
+                "The ngrams need to have a length of at least 1".to_string(),
+            ));
+        }
+        if self.threads == 0 {
+            return Err(Error::InvalidConfig(
+                "At least 1 thread is needed".to_string(),
+            ));
+        }
+        let mut ngram_files = Vec::with_capacity(self.max_ngram_len);
+        for n in 1..=self.max_ngram_len {
+            let file = match (self.ngram_files.get(n - 1), &self.ngrams_dir) {
+                (Some(Some(file)), _) => file.clone(),
+                (_, Some(dir)) => ngram_file_in(dir, n),
+                _ => {
+                    return Err(Error::InvalidConfig(format!(
+                        "No file with {}grams was set",
+                        n
+                    )))
+                }
+            };
+            ngram_files.push(file);
+        }
+        let dictionary = self
+            .dictionary
+            .ok_or_else(|| Error::InvalidConfig("No dictionary was set".to_string()))?;
+        let output_dir = self
+            .output_dir
+            .ok_or_else(|| Error::InvalidConfig("No output directory was set".to_string()))?;
+        Ok(GeneratorConfig {
+            ngram_files,
+            dictionary,
+            output_dir,
+            max_no_words: self.max_no_words,
+            vocabulary: self.vocabulary,
+            tie_break: self.tie_break,
+            include_words: self.include_words,
+            deny_words: self.deny_words,
+            smoothing: self.smoothing,
+            pruning: self.pruning,
+            arpa_file: self.arpa_file,
+            binary_file: self.binary_file,
+            quantization: self.quantization,
+            memory_budget: self.memory_budget,
+            sort_ngrams: self.sort_ngrams,
+            threads: self.threads,
+            input_format: self.input_format,
+            reserved_symbols: self.reserved_symbols,
+        })
+    }
+}
+
+/// Extensions of the compressed files that are looked up in the ngram directory
+const COMPRESSED_EXTENSIONS: [&str; 4] = ["gz", "zst", "xz", "bz2"];
+
+/// File with the ngrams of length n in the directory
+/// Falls back to `<n>gms.txt` if there is none, so a missing file is reported with its usual name
+fn ngram_file_in(dir: &Path, n: usize) -> PathBuf {
+    let plain = dir.join(format!("{}gms.txt", n));
+    if plain.is_file() {
+        return plain;
+    }
+    let mut compressed = COMPRESSED_EXTENSIONS.iter().flat_map(|extension| {
+        [
+            dir.join(format!("{}gms.txt.{}", n, extension)),
+            dir.join(format!("{}gms.{}", n, extension)),
+        ]
+    });
+    if let Some(file) = compressed.find(|file| file.is_file()) {
+        return file;
+    }
+    // The first shard stands for all of them
+    let first_shard = format!("{}gms-", n);
+    let shards = std::fs::read_dir(dir).into_iter().flatten().flatten();
+    let mut first_shards: Vec<PathBuf> = shards
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(&first_shard))
+                .and_then(|rest| rest.split_once("-of-"))
+                .is_some_and(|(index, _)| !index.is_empty() && index.bytes().all(|b| b == b'0'))
+        })
+        .collect();
+    first_shards.sort();
+    first_shards.into_iter().next().unwrap_or(plain)
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::external::ExternalSorter;
+use crate::utilities::LinesIterator;
+use crate::{SENTENCE_END, SENTENCE_START};
+
+/// Characters that are split off the beginning of a word in running text
+const LEADING_PUNCTUATION: &[char] = &['(', '[', '{', '"', '\'', '¿', '¡', '«', '“', '‘'];
+/// Characters that are split off the end of a word in running text
+const TRAILING_PUNCTUATION: &[char] = &[
+    '.', ',', ';', ':', '!', '?', ')', ']', '}', '"', '\'', '»', '”', '’',
+];
+
+/// How a corpus is split into sentences and words
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CorpusFormat {
+    /// One sentence per line with the words separated by whitespace, as in most tokenized corpora
+    #[default]
+    SentencePerLine,
+    /// Running text: punctuation is split off the words and sentences end with '.', '!' or '?' or an empty line
+    /// Abbreviations like "e.g." end a sentence as well.
+    PlainText,
+}
+
+impl fmt::Display for CorpusFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CorpusFormat::SentencePerLine => write!(f, "lines"),
+            CorpusFormat::PlainText => write!(f, "text"),
+        }
+    }
+}
+
+impl FromStr for CorpusFormat {
+    type Err = Error;
+
+    /// Parse `lines` or `text`
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lines" => Ok(CorpusFormat::SentencePerLine),
+            "text" => Ok(CorpusFormat::PlainText),
+            _ => Err(Error::InvalidConfig(format!(
+                "Invalid corpus format '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Counts the ngrams of all lengths up to a maximum in the sentences of a corpus
+///
+/// Every sentence starts with `<s>` and ends with `</s>`. The counts can be read as ngrams followed by their counts,
+/// the same ngrams `NGramIterator` reads from a file, or be written to `<n>gms.txt` files that `generate` reads.
+/// The ngrams of each order come out grouped by their prefixes.
+///
+/// With a memory budget, the counts are sorted on disk in runs whenever they take more memory than the budget,
+/// so only the vocabulary has to fit into memory.
+pub struct CorpusCounter {
+    format: CorpusFormat,
+    /// Symbols of the words, which are only used while counting
+    vocabulary: HashMap<String, u32>,
+    words: Vec<String>,
+    orders: Vec<OrderCounter>,
+    /// Directory for the runs and the budget of each order in bytes
+    spill: Option<(PathBuf, usize)>,
+    no_sentences: u64,
+}
+
+/// Counts of the ngrams of one length
+struct OrderCounter {
+    n: usize,
+    counts: HashMap<Vec<u32>, u32>,
+    sorter: Option<ExternalSorter>,
+}
+
+impl OrderCounter {
+    /// Estimate of the memory an ngram takes in the map
+    fn bytes_per_ngram(&self) -> usize {
+        std::mem::size_of::<(Vec<u32>, u32)>() + 4 * self.n + 8
+    }
+
+    /// Move the counts into the sorter
+    fn spill(&mut self, dir: &Path, budget: usize) -> Result<()> {
+        if self.sorter.is_none() {
+            fs::create_dir_all(dir).map_err(|err| Error::io(dir, err))?;
+        }
+        let n = self.n;
+        let sorter = self
+            .sorter
+            .get_or_insert_with(|| ExternalSorter::new(dir, n, budget / 2));
+        for (symbols, count) in self.counts.drain() {
+            sorter.push(&symbols, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl CorpusCounter {
+    /// Create a counter for the ngrams of all lengths up to `max_ngram_len`
+    pub fn new(max_ngram_len: usize, format: CorpusFormat) -> Self {
+        Self {
+            format,
+            vocabulary: HashMap::new(),
+            words: Vec::new(),
+            orders: (1..=max_ngram_len)
+                .map(|n| OrderCounter {
+                    n,
+                    counts: HashMap::new(),
+                    sorter: None,
+                })
+                .collect(),
+            spill: None,
+            no_sentences: 0,
+        }
+    }
+
+    /// Keep the counts within about `bytes` bytes of memory by sorting them in runs in the directory
+    /// The vocabulary is not part of the budget.
+    pub fn memory_budget<P: AsRef<Path>>(mut self, bytes: usize, dir: P) -> Self {
+        let budget = bytes / self.orders.len().max(1);
+        self.spill = Some((dir.as_ref().to_path_buf(), budget));
+        self
+    }
+
+    /// Number of sentences that were counted
+    pub fn no_sentences(&self) -> u64 {
+        self.no_sentences
+    }
+
+    /// Count the ngrams of all sentences of a corpus file
+    pub fn add_file<P: AsRef<Path>>(&mut self, filename: P) -> Result<()> {
+        let lines = LinesIterator::new(filename)?;
+        let mut sentence = Vec::new();
+        for line in lines {
+            let line = line?;
+            match self.format {
+                CorpusFormat::SentencePerLine => {
+                    let words: Vec<&str> = line.split_whitespace().collect();
+                    if !words.is_empty() {
+                        self.add_sentence(&words)?;
+                    }
+                }
+                CorpusFormat::PlainText => {
+                    // An empty line ends a paragraph and with it a sentence
+                    if line.trim().is_empty() && !sentence.is_empty() {
+                        self.add_sentence(&sentence)?;
+                        sentence.clear();
+                    }
+                    for token in line.split_whitespace() {
+                        for word in tokenize(token) {
+                            sentence.push(word.to_string());
+                            if matches!(word, "." | "!" | "?") {
+                                self.add_sentence(&sentence)?;
+                                sentence.clear();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if !sentence.is_empty() {
+            self.add_sentence(&sentence)?;
+        }
+        Ok(())
+    }
+
+    /// Count the ngrams of a sentence, `<s>` and `</s>` are added around the words
+    pub fn add_sentence<S: AsRef<str>>(&mut self, words: &[S]) -> Result<()> {
+        let mut symbols = Vec::with_capacity(words.len() + 2);
+        symbols.push(self.symbol(SENTENCE_START));
+        for word in words {
+            symbols.push(self.symbol(word.as_ref()));
+        }
+        symbols.push(self.symbol(SENTENCE_END));
+        self.no_sentences += 1;
+
+        for order in self.orders.iter_mut() {
+            for ngram in symbols.windows(order.n) {
+                match order.counts.get_mut(ngram) {
+                    Some(count) => *count = count.saturating_add(1),
+                    None => {
+                        order.counts.insert(ngram.to_vec(), 1);
+                    }
+                }
+            }
+            if let Some((dir, budget)) = &self.spill {
+                if order.counts.len() * order.bytes_per_ngram() > budget / 2 {
+                    order.spill(dir, *budget)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn symbol(&mut self, word: &str) -> u32 {
+        if let Some(&symbol) = self.vocabulary.get(word) {
+            return symbol;
+        }
+        let symbol = self.words.len() as u32;
+        self.vocabulary.insert(word.to_string(), symbol);
+        self.words.push(word.to_string());
+        symbol
+    }
+
+    /// The counted ngrams of length n followed by their counts
+    /// Their counts are handed out only once, the order is empty afterwards.
+    pub fn ngrams(
+        &mut self,
+        n: usize,
+    ) -> Result<impl Iterator<Item = Result<(Vec<String>, u32)>> + '_> {
+        let order = self
+            .orders
+            .get_mut(n.wrapping_sub(1))
+            .ok_or_else(|| Error::InvalidConfig(format!("No {}grams were counted", n)))?;
+        // Sorting by the symbols groups the ngrams by their prefixes
+        let sorted: Box<dyn Iterator<Item = Result<(Vec<u32>, u32)>>> = match &self.spill {
+            Some((dir, budget)) if order.sorter.is_some() => {
+                order.spill(dir, *budget)?;
+                let sorter = order.sorter.take().expect("the counts were spilled");
+                Box::new(sorter.merge()?)
+            }
+            _ => {
+                let mut counts: Vec<(Vec<u32>, u32)> = order.counts.drain().collect();
+                counts.sort_unstable();
+                Box::new(counts.into_iter().map(Ok))
+            }
+        };
+        let words = &self.words;
+        Ok(sorted.map(move |ngram| {
+            ngram.map(|(symbols, count)| {
+                let ngram = symbols
+                    .iter()
+                    .map(|&symbol| words[symbol as usize].clone())
+                    .collect();
+                (ngram, count)
+            })
+        }))
+    }
+
+    /// Write the ngrams of each length n to `<n>gms.txt` in the directory and return the files
+    pub fn write_ngram_files<P: AsRef<Path>>(mut self, dir: P) -> Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(|err| Error::io(dir, err))?;
+        let mut files = Vec::with_capacity(self.orders.len());
+        for n in 1..=self.orders.len() {
+            let filename = dir.join(format!("{}gms.txt", n));
+            let write_error = |err| Error::io(&filename, err);
+            let mut f_write = BufWriter::new(File::create(&filename).map_err(write_error)?);
+            for ngram in self.ngrams(n)? {
+                let (words, count) = ngram?;
+                writeln!(f_write, "{} {}", words.join(" "), count).map_err(write_error)?;
+            }
+            f_write.flush().map_err(write_error)?;
+            files.push(filename);
+        }
+        Ok(files)
+    }
+}
+
+/// Split the punctuation off the beginning and the end of a whitespace separated token of running text
+fn tokenize(token: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut rest = token;
+    while let Some(c) = rest
+        .chars()
+        .next()
+        .filter(|c| LEADING_PUNCTUATION.contains(c))
+    {
+        words.push(&rest[..c.len_utf8()]);
+        rest = &rest[c.len_utf8()..];
+    }
+    let mut trailing = Vec::new();
+    while let Some(c) = rest
+        .chars()
+        .next_back()
+        .filter(|c| TRAILING_PUNCTUATION.contains(c))
+    {
+        trailing.push(&rest[rest.len() - c.len_utf8()..]);
+        rest = &rest[..rest.len() - c.len_utf8()];
+    }
+    if !rest.is_empty() {
+        words.push(rest);
+    }
+    words.extend(trailing.into_iter().rev());
+    words
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors that can occur while generating a language model
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed
+    Io { path: PathBuf, source: io::Error },
+    /// A line of an input file could not be parsed
+    Parse {
+        path: PathBuf,
+        line_number: usize,
+        line: String,
+        reason: String,
+    },
+    /// The file with the ngrams of length n does not exist
+    MissingOrderFile { n: usize, path: PathBuf },
+    /// The ngrams don't fit together to a valid model
+    InconsistentModel(String),
+    /// The config is incomplete or contradicts itself
+    InvalidConfig(String),
+    /// A binary model file is not in the expected format
+    InvalidModelFile { path: PathBuf, reason: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Create an IO error for the file at the path
+    pub fn io<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        Error::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Parse {
+                path,
+                line_number,
+                line,
+                reason,
+            } => write!(
+                f,
+                "{}:{}: {} in line '{}'",
+                path.display(),
+                line_number,
+                reason,
+                line
+            ),
+            Error::MissingOrderFile { n, path } => write!(
+                f,
+                "The file with the {}grams does not exist: {}",
+                n,
+                path.display()
+            ),
+            Error::InconsistentModel(msg) => write!(f, "Inconsistent model: {}", msg),
+            Error::InvalidConfig(msg) => write!(f, "Invalid config: {}", msg),
+            Error::InvalidModelFile { path, reason } => {
+                write!(f, "{}: Invalid model file: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
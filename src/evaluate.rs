@@ -0,0 +1,304 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::model::LanguageModel;
+use crate::utilities::LinesIterator;
+use crate::{SENTENCE_END, SENTENCE_START};
+
+/// How well a model predicts held-out text
+///
+/// Words that are not in the vocabulary and don't become `<unk>` are not scored, just like the words of the history
+/// before them. If the model has `<s>` and `</s>`, every sentence starts with `<s>` and its end is scored as a word.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Evaluation {
+    /// Number of sentences that were scored, empty lines are skipped
+    pub no_sentences: usize,
+    /// Number of words of the sentences, including their ends if the model has `</s>`
+    pub no_words: usize,
+    /// Number of words that are not in the vocabulary, including the ones that became `<unk>`
+    pub no_oov: usize,
+    /// Sum of the natural log probabilities of the scored words
+    pub log_prob: f64,
+    /// Number of scored words whose probability came from an ngram of length n + 1 after backing off from the longer ones
+    pub hits: Vec<usize>,
+}
+
+impl Evaluation {
+    /// Number of words that were scored
+    pub fn no_scored(&self) -> usize {
+        self.hits.iter().sum()
+    }
+
+    /// Perplexity of the model on the scored words
+    pub fn perplexity(&self) -> f32 {
+        if self.no_scored() == 0 {
+            return f32::INFINITY;
+        }
+        (-self.log_prob / self.no_scored() as f64).exp() as f32
+    }
+
+    /// Average number of bits per scored word, the binary log of the perplexity
+    pub fn cross_entropy(&self) -> f32 {
+        if self.no_scored() == 0 {
+            return f32::INFINITY;
+        }
+        (-self.log_prob / self.no_scored() as f64 / std::f64::consts::LN_2) as f32
+    }
+
+    /// Fraction of the words that are not in the vocabulary
+    pub fn oov_rate(&self) -> f32 {
+        if self.no_words == 0 {
+            return 0.0;
+        }
+        self.no_oov as f32 / self.no_words as f32
+    }
+
+    /// Fraction of the scored words whose probability came from an ngram of length n
+    pub fn hit_rate(&self, n: usize) -> f32 {
+        let no_scored = self.no_scored();
+        match n.checked_sub(1).and_then(|i| self.hits.get(i)) {
+            Some(&hits) if no_scored > 0 => hits as f32 / no_scored as f32,
+            _ => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} sentences with {} words, {} of them not in the vocabulary ({:.3}%)",
+            self.no_sentences,
+            self.no_words,
+            self.no_oov,
+            100.0 * self.oov_rate()
+        )?;
+        writeln!(f, "Perplexity: {}", self.perplexity())?;
+        write!(f, "Cross-entropy: {} bits per word", self.cross_entropy())?;
+        for n in (1..=self.hits.len()).rev() {
+            write!(
+                f,
+                "\n{} words were scored by {}grams ({:.3}%)",
+                self.hits[n - 1],
+                n,
+                100.0 * self.hit_rate(n)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// How much typing the suggestions of a predictive keyboard save on a text and how good its next word predictions are
+///
+/// Every word is typed character by character until it is among the suggestions of the bar, selecting it takes one
+/// keystroke and adds the space after it. The next word predictions are the suggestions before the first character of a word.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeystrokeSavings {
+    /// Number of suggestions shown in the bar
+    pub bar_size: usize,
+    /// Number of words of the text, words that are not in the vocabulary can never be suggested
+    pub no_words: usize,
+    /// Keystrokes to type the text without suggestions, the characters of every word and a space after it
+    pub no_chars: usize,
+    /// Keystrokes to type the text with the suggestions
+    pub no_keystrokes: usize,
+    /// Number of words that were the first next word prediction
+    pub top1: usize,
+    /// Number of words that were among the first three next word predictions
+    pub top3: usize,
+    /// Sum of the reciprocal ranks of the words among the next word predictions of the bar, 0 if they weren't in the bar
+    pub reciprocal_ranks: f64,
+}
+
+impl KeystrokeSavings {
+    /// Fraction of the keystrokes that the suggestions saved
+    pub fn savings_rate(&self) -> f32 {
+        if self.no_chars == 0 {
+            return 0.0;
+        }
+        1.0 - self.no_keystrokes as f32 / self.no_chars as f32
+    }
+
+    /// Fraction of the words that were the first next word prediction
+    pub fn top1_accuracy(&self) -> f32 {
+        self.fraction(self.top1)
+    }
+
+    /// Fraction of the words that were among the first three next word predictions
+    pub fn top3_accuracy(&self) -> f32 {
+        self.fraction(self.top3)
+    }
+
+    /// Mean reciprocal rank of the words among the next word predictions of the bar
+    pub fn mean_reciprocal_rank(&self) -> f32 {
+        if self.no_words == 0 {
+            return 0.0;
+        }
+        (self.reciprocal_ranks / self.no_words as f64) as f32
+    }
+
+    fn fraction(&self, no_words: usize) -> f32 {
+        if self.no_words == 0 {
+            return 0.0;
+        }
+        no_words as f32 / self.no_words as f32
+    }
+}
+
+impl fmt::Display for KeystrokeSavings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} words typed with {} instead of {} keystrokes and {} suggestions",
+            self.no_words, self.no_keystrokes, self.no_chars, self.bar_size
+        )?;
+        writeln!(f, "Keystroke savings: {:.3}%", 100.0 * self.savings_rate())?;
+        writeln!(
+            f,
+            "Next word accuracy: {:.3}% top-1, {:.3}% top-3",
+            100.0 * self.top1_accuracy(),
+            100.0 * self.top3_accuracy()
+        )?;
+        write!(f, "Mean reciprocal rank: {}", self.mean_reciprocal_rank())
+    }
+}
+
+impl LanguageModel {
+    /// Type the sentences, which are whitespace separated words, with a bar of `bar_size` suggestions
+    pub fn keystroke_savings<I, S>(&self, sentences: I, bar_size: usize) -> KeystrokeSavings
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut savings = KeystrokeSavings {
+            bar_size,
+            ..KeystrokeSavings::default()
+        };
+        let start = self.contains(SENTENCE_START).then_some(SENTENCE_START);
+        for sentence in sentences {
+            let mut history: Vec<&str> = start.into_iter().collect();
+            for word in sentence.as_ref().split_whitespace() {
+                let no_chars = word.chars().count();
+                savings.no_words += 1;
+                savings.no_chars += no_chars + 1;
+                if !self.contains(word) {
+                    savings.no_keystrokes += no_chars + 1;
+                    history.push(word);
+                    continue;
+                }
+
+                let rank = self
+                    .predict(&history, "", bar_size.max(3))
+                    .iter()
+                    .position(|prediction| prediction.word == word);
+                if rank == Some(0) {
+                    savings.top1 += 1;
+                }
+                if rank.is_some_and(|rank| rank < 3) {
+                    savings.top3 += 1;
+                }
+                let in_bar = |rank: Option<usize>| rank.filter(|&rank| rank < bar_size);
+                if let Some(rank) = in_bar(rank) {
+                    savings.reciprocal_ranks += 1.0 / (rank + 1) as f64;
+                }
+                // Characters typed before the word was suggested
+                let typed = in_bar(rank).map(|_| 0).or_else(|| {
+                    word.char_indices()
+                        .skip(1)
+                        .enumerate()
+                        .find_map(|(i, (end, _))| {
+                            self.predict(&history, &word[..end], bar_size)
+                                .iter()
+                                .any(|prediction| prediction.word == word)
+                                .then_some(i + 1)
+                        })
+                });
+                savings.no_keystrokes += match typed {
+                    Some(typed) => typed + 1,
+                    None => no_chars + 1,
+                };
+                history.push(word);
+            }
+        }
+        savings
+    }
+
+    /// Score the sentences, which are whitespace separated words
+    pub fn evaluate<I, S>(&self, sentences: I) -> Evaluation
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut evaluation = Evaluation {
+            hits: vec![0; self.max_ngram_len()],
+            ..Evaluation::default()
+        };
+        let boundaries = self.contains(SENTENCE_START) && self.contains(SENTENCE_END);
+        for sentence in sentences {
+            let mut words: Vec<&str> = sentence.as_ref().split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            evaluation.no_sentences += 1;
+            evaluation.no_oov += words.iter().filter(|word| !self.contains(word)).count();
+            if boundaries {
+                words.insert(0, SENTENCE_START);
+                words.push(SENTENCE_END);
+            }
+            // <s> is only the history of the first word
+            let first = usize::from(boundaries);
+            evaluation.no_words += words.len() - first;
+
+            let symbols = self.symbols(&words);
+            for (i, symbol) in symbols.iter().enumerate().skip(first) {
+                if let Some(symbol) = symbol {
+                    let start = (i + 1).saturating_sub(self.max_ngram_len());
+                    let mut ngram = Self::known_history(&symbols[start..i]);
+                    ngram.push(*symbol);
+                    let (log_prob, n) = self.log_prob_symbols(&ngram);
+                    evaluation.log_prob += log_prob as f64;
+                    evaluation.hits[n.max(1) - 1] += 1;
+                }
+            }
+        }
+        evaluation
+    }
+}
+
+/// Score the model on a held-out text file with one sentence per line
+/// The sentences are scored as they are read, so the file doesn't need to fit into memory.
+pub fn evaluate<P: AsRef<Path>>(model: &LanguageModel, heldout_file: P) -> Result<Evaluation> {
+    with_lines(heldout_file, |sentences| model.evaluate(sentences))
+}
+
+/// Type the sentences of a test file with one sentence per line with a bar of `bar_size` suggestions
+/// The sentences are typed as they are read, so the file doesn't need to fit into memory.
+pub fn keystroke_savings<P: AsRef<Path>>(
+    model: &LanguageModel,
+    test_file: P,
+    bar_size: usize,
+) -> Result<KeystrokeSavings> {
+    with_lines(test_file, |sentences| {
+        model.keystroke_savings(sentences, bar_size)
+    })
+}
+
+/// Pass the lines of a file to `consume` as they are read, they end at the first line that can't be read,
+/// whose error is returned instead of the result
+fn with_lines<P, T, F>(path: P, consume: F) -> Result<T>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut dyn Iterator<Item = String>) -> T,
+{
+    let mut error = None;
+    let result = {
+        let mut lines =
+            LinesIterator::new(path)?.map_while(|line| line.map_err(|err| error = Some(err)).ok());
+        consume(&mut lines)
+    };
+    match error {
+        Some(err) => Err(err),
+        None => Ok(result),
+    }
+}
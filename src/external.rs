@@ -0,0 +1,248 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+/// Largest size of the buffer of every run file while they are merged
+const RUN_BUFFER_SIZE: usize = 1 << 16;
+/// Smallest size of the buffer of every run file, even if the memory budget is smaller
+const MIN_RUN_BUFFER_SIZE: usize = 1 << 12;
+/// Largest number of runs that are merged at once, more runs are merged in several passes
+/// It keeps the number of open files low and their buffers within the memory budget.
+const MAX_FAN_IN: usize = 64;
+
+/// The symbols of an ngram and its count
+type CountedNGram = (Vec<u32>, u32);
+
+/// Sorts translated ngrams of one order by their symbols with a bounded amount of memory
+///
+/// The ngrams are collected in a buffer. Whenever it is full, it gets sorted and written to a run file.
+/// `merge` then combines the runs into one sorted stream, at most `fan_in` at once: as long as there are more runs,
+/// the oldest ones are merged into a new run. Each ngram is stored as its n symbols followed by its count.
+pub(crate) struct ExternalSorter {
+    dir: PathBuf,
+    n: usize,
+    /// Symbols and counts of the buffered ngrams, n + 1 numbers per ngram
+    buffer: Vec<u32>,
+    max_buffered: usize,
+    /// Number of runs that are merged at once
+    fan_in: usize,
+    /// Size of the buffer of every run file that is read or written while merging
+    run_buffer_size: usize,
+    runs: Vec<PathBuf>,
+    /// Number of run files that were created, it numbers their names
+    no_runs_created: usize,
+}
+
+impl ExternalSorter {
+    /// Create a sorter for ngrams of length n that writes its runs to the directory
+    /// The buffer and the order it is sorted in take at most about `memory_budget` bytes, and so do the buffers
+    /// of the runs while they are merged, unless the budget is smaller than a few buffers of the minimum size.
+    pub(crate) fn new<P: AsRef<Path>>(dir: P, n: usize, memory_budget: usize) -> Self {
+        let bytes_per_ngram = 4 * (n + 1) + std::mem::size_of::<u32>();
+        // A merge pass reads fan_in runs and writes one
+        let fan_in = (memory_budget / RUN_BUFFER_SIZE).clamp(2, MAX_FAN_IN);
+        let run_buffer_size =
+            (memory_budget / (fan_in + 1)).clamp(MIN_RUN_BUFFER_SIZE, RUN_BUFFER_SIZE);
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            n,
+            buffer: Vec::new(),
+            max_buffered: (memory_budget / bytes_per_ngram).max(1),
+            fan_in,
+            run_buffer_size,
+            runs: Vec::new(),
+            no_runs_created: 0,
+        }
+    }
+
+    /// Path of a new run file
+    fn new_run(&mut self) -> PathBuf {
+        let path = self
+            .dir
+            .join(format!("{}gms_run{}.tmp", self.n, self.no_runs_created));
+        self.no_runs_created += 1;
+        path
+    }
+
+    pub(crate) fn push(&mut self, symbols: &[u32], count: u32) -> Result<()> {
+        self.buffer.extend_from_slice(symbols);
+        self.buffer.push(count);
+        if self.buffer.len() / (self.n + 1) >= self.max_buffered {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sort the buffered ngrams and write them to a new run file
+    fn spill(&mut self) -> Result<()> {
+        let stride = self.n + 1;
+        let no_ngrams = self.buffer.len() / stride;
+        let mut sorted: Vec<u32> = (0..no_ngrams as u32).collect();
+        let key = |i: u32| &self.buffer[i as usize * stride..i as usize * stride + self.n];
+        sorted.sort_by(|&a, &b| key(a).cmp(key(b)));
+
+        let path = self.new_run();
+        let write_error = |err| Error::io(&path, err);
+        let mut f_write = BufWriter::new(File::create(&path).map_err(write_error)?);
+        for i in sorted {
+            let start = i as usize * stride;
+            for value in &self.buffer[start..start + stride] {
+                f_write
+                    .write_all(&value.to_le_bytes())
+                    .map_err(write_error)?;
+            }
+        }
+        f_write.flush().map_err(write_error)?;
+        self.runs.push(path);
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Merge all runs into one stream that is sorted by the symbols
+    /// Ngrams that were pushed more than once are merged into one ngram with the sum of their counts
+    pub(crate) fn merge(mut self) -> Result<MergedNGrams> {
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        // The buffer is not needed anymore, so its memory is free for the buffers of the runs
+        self.buffer = Vec::new();
+        while self.runs.len() > self.fan_in {
+            let paths: Vec<PathBuf> = self.runs.drain(..self.fan_in).collect();
+            let merged = self.open_runs(paths)?;
+            let path = self.new_run();
+            // Pushed right away, so it is removed with the other runs if writing it fails
+            self.runs.push(path.clone());
+            let write_error = |err| Error::io(&path, err);
+            let mut f_write = BufWriter::with_capacity(
+                self.run_buffer_size,
+                File::create(&path).map_err(write_error)?,
+            );
+            for ngram in merged {
+                let (symbols, count) = ngram?;
+                for value in symbols.iter().chain([&count]) {
+                    f_write
+                        .write_all(&value.to_le_bytes())
+                        .map_err(write_error)?;
+                }
+            }
+            f_write.flush().map_err(write_error)?;
+        }
+        let paths = std::mem::take(&mut self.runs);
+        self.open_runs(paths)
+    }
+
+    /// Open the runs to merge them, they are removed once the merged ngrams are dropped
+    fn open_runs(&self, paths: Vec<PathBuf>) -> Result<MergedNGrams> {
+        let mut merged = MergedNGrams {
+            runs: Vec::with_capacity(paths.len()),
+            heap: BinaryHeap::with_capacity(paths.len()),
+            paths,
+        };
+        for (i, path) in merged.paths.iter().enumerate() {
+            let file = File::open(path).map_err(|err| Error::io(path, err))?;
+            let mut run = Run {
+                path: path.clone(),
+                reader: BufReader::with_capacity(self.run_buffer_size, file),
+                n: self.n,
+            };
+            if let Some(ngram) = run.next_ngram()? {
+                merged.heap.push(Reverse((ngram, i)));
+            }
+            merged.runs.push(run);
+        }
+        Ok(merged)
+    }
+}
+
+impl Drop for ExternalSorter {
+    fn drop(&mut self) {
+        // Only runs that were never merged are left here
+        for path in &self.runs {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// A sorted run file that is read one ngram after the other
+struct Run {
+    path: PathBuf,
+    reader: BufReader<File>,
+    n: usize,
+}
+
+impl Run {
+    fn next_ngram(&mut self) -> Result<Option<CountedNGram>> {
+        let mut values = Vec::with_capacity(self.n + 1);
+        let mut bytes = [0; 4];
+        for i in 0..=self.n {
+            match self.reader.read_exact(&mut bytes) {
+                Ok(()) => values.push(u32::from_le_bytes(bytes)),
+                Err(err) if i == 0 && err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(err) => return Err(Error::io(&self.path, err)),
+            }
+        }
+        let count = values.pop().unwrap_or_default();
+        Ok(Some((values, count)))
+    }
+}
+
+/// The ngrams of all runs in ascending order of their symbols
+/// The run files are deleted once the iterator is dropped
+pub(crate) struct MergedNGrams {
+    runs: Vec<Run>,
+    heap: BinaryHeap<Reverse<(CountedNGram, usize)>>,
+    paths: Vec<PathBuf>,
+}
+
+impl MergedNGrams {
+    /// The smallest ngram of all runs, the run it came from is advanced
+    fn pop(&mut self) -> Result<Option<CountedNGram>> {
+        let Reverse((ngram, i)) = match self.heap.pop() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        if let Some(next) = self.runs[i].next_ngram()? {
+            self.heap.push(Reverse((next, i)));
+        }
+        Ok(Some(ngram))
+    }
+}
+
+impl Iterator for MergedNGrams {
+    type Item = Result<CountedNGram>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (symbols, mut count) = match self.pop() {
+            Ok(ngram) => ngram?,
+            Err(err) => return Some(Err(err)),
+        };
+        while let Some(Reverse(((next, _), _))) = self.heap.peek() {
+            if *next != symbols {
+                break;
+            }
+            match self.pop() {
+                Ok(Some((_, next_count))) => count = count.saturating_add(next_count),
+                Ok(None) => break,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        Some(Ok((symbols, count)))
+    }
+}
+
+impl Drop for MergedNGrams {
+    fn drop(&mut self) {
+        // Close the runs first, some systems can't remove open files
+        self.runs.clear();
+        for path in &self.paths {
+            // The runs are only temporary, so a run that can't be removed is no reason to fail
+            let _ = fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
+
+/// Part-of-speech tags of the Google Books Ngram corpus, they are appended to words like `run_VERB`
+const POS_TAGS: [&str; 12] = [
+    "NOUN", "VERB", "ADJ", "ADV", "PRON", "DET", "ADP", "NUM", "CONJ", "PRT", "X", ".",
+];
+
+/// Format of the lines of the ngram files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// `w1 w2 ... wn count`, separated by whitespace
+    #[default]
+    Counts,
+    /// The raw Google Books Ngram format `ngram TAB year TAB match_count TAB volume_count`
+    ///
+    /// The lines of an ngram need to be next to each other. Their match counts are summed over the years
+    /// from `first_year` to `last_year`, ngrams that did not occur in these years are skipped.
+    /// The sums are capped at `u32::MAX`.
+    GoogleBooks {
+        first_year: u32,
+        last_year: u32,
+        pos_tags: PosTags,
+    },
+}
+
+/// What is done with the part-of-speech tags of the Google Books Ngram corpus
+///
+/// Tokens that are only a tag like `_NOUN_` or `_START_` are kept with `Keep` and make the ngram be skipped otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PosTags {
+    /// Words like `run_VERB` are kept as they are
+    #[default]
+    Keep,
+    /// The tags are removed, so `run_VERB` and `run_NOUN` both become `run`
+    /// Unigrams that became the same are always merged. Longer ngrams that became the same are merged if they are
+    /// next to each other or the ngrams are sorted, otherwise the ones that are not next to each other are an error.
+    Strip,
+    /// Ngrams with tagged words are skipped
+    /// The corpus contains every ngram without tags as well, so this does not count them twice like `Strip`
+    Skip,
+}
+
+impl InputFormat {
+    /// Google Books Ngram format with all years and the tags kept
+    pub fn google_books() -> Self {
+        InputFormat::GoogleBooks {
+            first_year: 0,
+            last_year: u32::MAX,
+            pos_tags: PosTags::Keep,
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputFormat::Counts => write!(f, "counts"),
+            InputFormat::GoogleBooks {
+                first_year,
+                last_year,
+                pos_tags,
+            } => write!(f, "google-books:{}-{}:{}", first_year, last_year, pos_tags),
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    /// Parse `counts` or `google-books[:<first_year>-<last_year>][:keep|strip|skip]`
+    /// Either of the years can be left out to not limit the range on that side
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parameters = s.split(':');
+        let invalid = || Error::InvalidConfig(format!("Invalid input format '{}'", s));
+        match parameters.next() {
+            Some("counts") if parameters.next().is_none() => return Ok(InputFormat::Counts),
+            Some("google-books" | "google") => {}
+            _ => return Err(invalid()),
+        }
+        let (mut first_year, mut last_year, mut pos_tags) = (0, u32::MAX, PosTags::Keep);
+        for parameter in parameters {
+            if let Some((first, last)) = parameter.split_once('-') {
+                if !first.is_empty() {
+                    first_year = first.parse().map_err(|_| invalid())?;
+                }
+                if !last.is_empty() {
+                    last_year = last.parse().map_err(|_| invalid())?;
+                }
+            } else {
+                pos_tags = parameter.parse()?;
+            }
+        }
+        if first_year > last_year {
+            return Err(invalid());
+        }
+        Ok(InputFormat::GoogleBooks {
+            first_year,
+            last_year,
+            pos_tags,
+        })
+    }
+}
+
+impl fmt::Display for PosTags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PosTags::Keep => write!(f, "keep"),
+            PosTags::Strip => write!(f, "strip"),
+            PosTags::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+impl FromStr for PosTags {
+    type Err = Error;
+
+    /// Parse `keep`, `strip` or `skip`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(PosTags::Keep),
+            "strip" => Ok(PosTags::Strip),
+            "skip" => Ok(PosTags::Skip),
+            _ => Err(Error::InvalidConfig(format!(
+                "Invalid handling of the POS tags '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+impl PosTags {
+    /// The word as it is used in the model, None if the ngram with the word is skipped
+    pub(crate) fn apply<'a>(&self, word: &'a str) -> Option<&'a str> {
+        if *self == PosTags::Keep {
+            return Some(word);
+        }
+        let is_tag = |tag: &str| POS_TAGS.contains(&tag) || matches!(tag, "START" | "END" | "ROOT");
+        if let Some(tag) = word
+            .strip_prefix('_')
+            .and_then(|word| word.strip_suffix('_'))
+        {
+            if is_tag(tag) {
+                return None;
+            }
+        }
+        match word.rsplit_once('_') {
+            Some((base, tag)) if !base.is_empty() && POS_TAGS.contains(&tag) => match self {
+                PosTags::Strip => Some(base),
+                _ => None,
+            },
+            _ => Some(word),
+        }
+    }
+}
+
+/// Split a line of the Google Books Ngram format into the n words of its ngram, the year and the match count
+/// Returns why the line is invalid if it is
+pub(crate) fn split_google_books_line(
+    line: &str,
+    n: usize,
+) -> Result<(Vec<&str>, u32, u64), String> {
+    let mut fields = line.split('\t');
+    let ngram = fields.next().unwrap_or_default();
+    let words: Vec<&str> = ngram.split_whitespace().collect();
+    if words.len() != n {
+        return Err(format!(
+            "Expected {} words, a year and a match count separated by tabs",
+            n
+        ));
+    }
+    let mut next_number = |what: &str| -> Result<u64, String> {
+        let field = fields
+            .next()
+            .ok_or_else(|| format!("Expected a {} after the ngram", what))?;
+        field
+            .trim()
+            .parse::<u64>()
+            .map_err(|err| format!("Invalid {} '{}' ({})", what, field, err))
+    };
+    let year = next_number("year")?;
+    let match_count = next_number("match count")?;
+    let year = u32::try_from(year).map_err(|err| format!("Invalid year '{}' ({})", year, err))?;
+    Ok((words, year, match_count))
+}
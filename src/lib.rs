@@ -1,321 +1,748 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
-use std::fs;
-use std::io::Write;
-use std::time::Instant;
-
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub mod arpa;
+pub mod binary;
+pub mod config;
+pub mod count;
+pub mod error;
+pub mod evaluate;
+mod external;
+pub mod input;
+pub mod model;
+mod parallel;
+pub mod predict;
+pub mod prune;
+pub mod quantize;
+pub mod smoothing;
 #[cfg(test)]
 mod tests;
 pub mod utilities;
-
+mod validate;
+pub mod vocabulary;
+
+pub use config::{GeneratorConfig, GeneratorConfigBuilder};
+pub use count::{CorpusCounter, CorpusFormat};
+pub use error::{Error, Result};
+pub use evaluate::{Evaluation, KeystrokeSavings};
+use external::ExternalSorter;
+pub use input::{InputFormat, PosTags};
+pub use model::{LanguageModel, ModelTables, NGramRecord};
+use model::{TableEntry, TableWriter};
+use parallel::ParallelTranslator;
+pub use predict::Prediction;
+pub use prune::Pruning;
+pub use quantize::{Quantization, QuantizationReport};
+pub use smoothing::Smoothing;
 use utilities::*;
+pub use vocabulary::{TieBreak, VocabularySelection};
+
+/// Word that marks the beginning of a sentence
+pub const SENTENCE_START: &str = "<s>";
+/// Word that marks the end of a sentence
+pub const SENTENCE_END: &str = "</s>";
+/// Word that stands for all words that are not in the vocabulary
+pub const UNKNOWN_WORD: &str = "<unk>";
+
+/// Information collected for a single ngram while translating the ngrams of one order
+#[derive(Clone, Copy, Default)]
+pub(crate) struct NGramEntry {
+    /// Symbol of the last word of the ngram
+    pub(crate) label: u32,
+    /// Log probability of the last word given the preceding words
+    pub(crate) log_prob: f32,
+    /// Log of the weight the probabilities of the next lower order get multiplied with, if this ngram is used as a history
+    pub(crate) backoff: f32,
+    /// Count of the ngram as read from the input file
+    pub(crate) count: u32,
+    /// Index of the ngram of the next lower order made up of all but the first word
+    pub(crate) suffix: u32,
+    /// Index of the first ngram of the next higher order that has this ngram as its prefix
+    pub(crate) offset: u32,
+    /// Number of ngrams of the next higher order that have this ngram as their prefix
+    pub(crate) no_longer_ngrams: u32,
+}
 
-pub fn generate(test_mode: bool, max_no_words: usize) {
-    // start the clock
-    let time_start = Instant::now();
+/// All ngrams of one order that were kept, in the order they were read in
+#[derive(Default)]
+pub(crate) struct NGramTable {
+    pub(crate) entries: Vec<NGramEntry>,
+    // Maps the translated symbols of an ngram to its index in the entries
+    // It is only needed to look up the prefixes and suffixes of the next higher order
+    pub(crate) index: HashMap<Vec<u32>, u32>,
+}
 
-    let root = if test_mode {
-        "./LanguageModel_TEST/"
-    } else {
-        "./LanguageModel/"
-    };
+/// Number of ngrams and their cumulative count
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NGramCounts {
+    pub ngrams: u32,
+    pub cumulative_count: u64,
+}
 
-    // Folders in which the ngrams and the dictionary resides in
-    let folder_all = "ngrams_ALL/";
-    let folder_result = "ngrams_result/";
-    let folder_dict = "dict/";
+/// Ngrams that were skipped because an ngram of the next lower order they need was not kept
+///
+/// Every ngram is attached to its prefix and refers to its suffix, so the tables stay closed under prefixes and suffixes.
+/// Both can be missing if the lower order was pruned or its ngrams were not in the input file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MissingLowerOrder {
+    /// Ngrams whose prefix (all but the last word) was not kept
+    pub prefix: NGramCounts,
+    /// Ngrams whose prefix was kept, but not their suffix (all but the first word)
+    pub suffix: NGramCounts,
+}
 
-    // Create the directory for the translated ngrams if it does not exist
-    if fs::create_dir(&format!("{}{}", root, folder_result)).is_err() {
-        println!("Folder \'./{}\' already existed!", folder_result)
-    };
+/// Statistics about the ngrams of one order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrderSummary {
+    /// Length of the ngrams
+    pub n: usize,
+    /// Ngrams that were read from the input file
+    pub total: NGramCounts,
+    /// Ngrams that were kept in the model
+    pub kept: NGramCounts,
+    /// Ngrams that only consist of words of the vocabulary, but were skipped because their prefix or suffix was not kept
+    pub missing: MissingLowerOrder,
+    /// Ngrams that were removed by the pruning
+    pub pruned: NGramCounts,
+    /// Time passed since the start of the generation when the ngrams were processed
+    pub time_passed: Duration,
+}
 
-    // Open the file with the dictionary
-    let fname_dict = format! {"{}{}{}", root,folder_dict,"words_allow.txt"};
-    let all_allowed_words = WordListIterator::new(&fname_dict);
+impl OrderSummary {
+    /// Ngrams that were read but not kept
+    pub fn skipped(&self) -> NGramCounts {
+        NGramCounts {
+            ngrams: self.total.ngrams - self.kept.ngrams,
+            cumulative_count: self.total.cumulative_count - self.kept.cumulative_count,
+        }
+    }
+}
 
-    // Open the file with the unigrams
-    let fname_read_unigrams = format!("{}{}{}gms.txt", root, folder_all, 1);
-    let all_unigrams = NGramIterator::new(&fname_read_unigrams, 1);
+/// Summary of a generated language model
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenerationSummary {
+    /// Statistics for each order, starting with the unigrams
+    pub orders: Vec<OrderSummary>,
+    /// Number of words that were not kept in the vocabulary although they had the same count as its last word
+    pub tied_words_dropped: usize,
+    /// Time it took to generate the whole model
+    pub duration: Duration,
+}
 
-    // Open the file with the bigrams
-    let fname_read_bigrams = format!("{}{}{}gms.txt", root, folder_all, 2);
-    let all_bigrams = NGramIterator::new(&fname_read_bigrams, 2);
+impl GenerationSummary {
+    /// Warning about the words that a tie break dropped from the vocabulary, if there are any
+    pub fn tie_warning(&self) -> Option<String> {
+        (self.tied_words_dropped > 0).then(|| {
+            format!(
+                "Warning: {} words were dropped from the vocabulary although they had the same count as its last word",
+                self.tied_words_dropped
+            )
+        })
+    }
+}
 
-    // Open the file with the trigrams
-    let fname_read_trigrams = format!("{}{}{}gms.txt", root, folder_all, 3);
-    let all_trigrams = NGramIterator::new(&fname_read_trigrams, 3);
+/// Builds a language model from the ngram files of the config and writes the symbol table and the translated ngrams to its output directory
+pub fn generate(config: &GeneratorConfig) -> Result<GenerationSummary> {
+    // start the clock
+    let time_start = Instant::now();
 
-    // Create file to write the unigrams to
-    let fname_write_unigrams = format!("{}{}{}gms.txt", root, folder_result, 1);
-    let mut f_write_unigrams = std::fs::File::create(fname_write_unigrams).expect("create failed");
+    let max_ngram_len = config.max_ngram_len();
 
-    // Create file to write the bigrams to
-    let fname_write_bigrams = format!("{}{}{}gms.txt", root, folder_result, 2);
-    let mut f_write_bigrams = std::fs::File::create(fname_write_bigrams).expect("create failed");
+    // Check that the files for all orders exist before spending hours on the lower orders
+    for (i, path) in config.ngram_files.iter().enumerate() {
+        if !path.is_file() {
+            return Err(Error::MissingOrderFile {
+                n: i + 1,
+                path: path.clone(),
+            });
+        }
+    }
 
-    // Create file to write the trigrams to
-    let fname_write_trigrams = format!("{}{}{}gms.txt", root, folder_result, 3);
-    let mut f_write_trigrams = std::fs::File::create(fname_write_trigrams).expect("create failed");
+    // Create the directory for the translated ngrams if it does not exist
+    fs::create_dir_all(&config.output_dir).map_err(|err| Error::io(&config.output_dir, err))?;
 
-    // Create file to write the symbol table to
-    let fname_write_symt = format!("{}{}symt.txt", root, folder_result);
-    let mut f_write_symt = std::fs::File::create(fname_write_symt).expect("create failed");
+    // Open the file with the dictionary
+    let all_allowed_words = WordListIterator::new(&config.dictionary)?;
 
-    // Process n-grams of lengths up to
-    let max_ngram_len = 3;
+    // Open the file with the unigrams
+    let all_unigrams = NGramIterator::with_format(&config.ngram_files[0], 1, config.input_format)?;
+
+    // Create file to write the symbol table to
+    let fname_write_symt = config.symbol_table_file();
+    let mut f_write_symt = BufWriter::new(
+        File::create(&fname_write_symt).map_err(|err| Error::io(&fname_write_symt, err))?,
+    );
 
     // Create Vec to keep track of how many ngrams were read in total and how big their accumulated count was
     // and a vec to store the same values, but only for the ngrams that were kept
-    let mut ngrams_kept = vec![(0, 0); max_ngram_len];
-    let mut ngrams_total = vec![(0, 0); max_ngram_len];
-
-    // Load the dictionary of allowed words from its file
-    println!("Load dictionary");
-    let dictionary: HashSet<String> = all_allowed_words.collect();
+    let mut ngrams_kept = vec![NGramCounts::default(); max_ngram_len];
+    let mut ngrams_total = vec![NGramCounts::default(); max_ngram_len];
+    let mut orders = Vec::with_capacity(max_ngram_len);
+
+    // Load the dictionary of allowed words and the lists of words that are always or never kept from their files
+    let dictionary: HashSet<String> = all_allowed_words.collect::<Result<_>>()?;
+    let read_word_list = |file: &Option<PathBuf>| -> Result<HashSet<String>> {
+        match file {
+            Some(file) => WordListIterator::new(file)?.collect(),
+            None => Ok(HashSet::new()),
+        }
+    };
+    let include_words = read_word_list(&config.include_words)?;
+    let deny_words = read_word_list(&config.deny_words)?;
 
     // Intersect the allowed words from the dictionary with the unigrams
-    println!("Intersecting dictionary with unigrams");
-
-    let mut threshold = 0;
-    let mut allowed_unigrams = Vec::new();
-    allowed_unigrams.reserve(max_no_words); // Reserve space for the specified max
-    let mut min_heap = LimitedMinHeap::new(max_no_words);
+    // The candidates for the vocabulary are kept in the order of the file, which is the order of their symbols
+    let mut allowed_unigrams: Vec<(String, u32, bool)> = Vec::new();
+    // Unigrams that became the same word, like the ones whose tags were stripped, are merged wherever they are in the file
+    let mut candidate_ids: HashMap<String, usize> = HashMap::new();
+    // With reserved symbols, the counts of all unigrams that are not kept go to <unk>
+    let mut unknown_count = 0u64;
     // We go through all of the unigrams and for each of them..
-    for (ngram, ngram_count) in all_unigrams {
-        ngrams_total[0].0 += 1;
-        ngrams_total[0].1 += ngram_count;
-        // We check if the unigram is in our list of allowed words
-        // If it is not in the list, we ignore it and go to the next unigram
-        if !dictionary.contains(&ngram[0]) {
+    for unigram in all_unigrams {
+        let (mut ngram, ngram_count) = unigram?;
+        ngrams_total[0].ngrams += 1;
+        ngrams_total[0].cumulative_count += ngram_count as u64;
+        let word = ngram.swap_remove(0);
+        if config.reserved_symbols && word == UNKNOWN_WORD {
+            unknown_count += ngram_count as u64;
             continue;
         }
-        // We only reach this part if the unigram is one of the allowed words
-
-        // Build a list of the k highest occurrences of the ngrams
-        if let Some(new_k_highest_count) = min_heap.insert(ngram_count) {
-            threshold = new_k_highest_count;
+        // The sentence boundaries are kept regardless of the dictionary and the maximum number of words
+        // and so are the words of the include list, unless they are denied as well
+        let reserved = config.reserved_symbols && (word == SENTENCE_START || word == SENTENCE_END);
+        let denied = deny_words.contains(&word);
+        let always_kept = reserved || (include_words.contains(&word) && !denied);
+        if !always_kept && (!dictionary.contains(&word) || denied) {
+            unknown_count += ngram_count as u64;
+        } else if let Some(&id) = candidate_ids.get(&word) {
+            let count = &mut allowed_unigrams[id].1;
+            *count = count.saturating_add(ngram_count);
+        } else {
+            candidate_ids.insert(word.clone(), allowed_unigrams.len());
+            allowed_unigrams.push((word, ngram_count, always_kept));
         }
-        // If the count of the ngram is lower than the count of the theshold we can already ignore the ngram and skip to the next
-        if ngram_count < threshold {
-            continue;
+    }
+    // Words that are always kept don't take up places of the vocabulary if they are reserved symbols
+    let no_reserved = allowed_unigrams
+        .iter()
+        .filter(|(word, _, _)| {
+            config.reserved_symbols && (word == SENTENCE_START || word == SENTENCE_END)
+        })
+        .count();
+    let (selected, tied_words_dropped) = config.vocabulary.select(
+        &allowed_unigrams,
+        config.max_no_words + no_reserved,
+        config.tie_break,
+        ngrams_total[0].cumulative_count,
+    );
+    let mut vocabulary = Vec::with_capacity(allowed_unigrams.len());
+    for ((word, count, _), selected) in allowed_unigrams.into_iter().zip(selected) {
+        if selected {
+            vocabulary.push((word, count));
+        } else {
+            unknown_count += count as u64;
         }
-        // At this point it is guaranteed the unigram is in the list of allowed words and it's count is greater than the current threshold
-        // The threshold can potentially increase with later unigrams
-        // We temporarily store the unigrams in a Vec because we need to check if they truely meet the threshold again after going through all of them
-        allowed_unigrams.push((ngram[0].clone(), ngram_count));
+    }
+    // The counts of all unigrams that are not kept go to <unk>, which gets the first symbol
+    if config.reserved_symbols && unknown_count > 0 {
+        let count = unknown_count.min(u32::MAX as u64) as u32;
+        vocabulary.insert(0, (UNKNOWN_WORD.to_string(), count));
     }
 
-    // All unigrams that don't meet the final threshold are removed and the SymbolTable created. It is kept in a HashMap and is also written to a file
-    let mut unigrams = Vec::new();
+    // The SymbolTable is created from the selected unigrams. It is kept in a HashMap and is also written to a file
+    let mut unigrams = NGramTable::default();
     let mut sybt = HashMap::new();
-    for (unigram, count) in allowed_unigrams {
-        if count >= threshold {
-            sybt.insert(unigram.clone(), ngrams_kept[0].0);
-            writeln!(f_write_symt, "{}", unigram).expect("write failed");
-            unigrams.push(count);
-            ngrams_kept[0].0 += 1;
-            ngrams_kept[0].1 += count;
-        }
-    }
-    let allowed_unigrams = unigrams;
-
-    // Mapping all symbols of the unigrams that meet the threshold to an integer value to save space and storing them in a HashMap
-    let mut unigrams: Vec<(f32, u32, u32, u16)> = Vec::new();
-    let mut log_prob;
-    for ngram_count in allowed_unigrams {
-        // Calculate the log probability
-        log_prob = (ngram_count as f32 / ngrams_kept[0].1 as f32).ln();
-        // Insert the infos for the unigram (log_probability, count, offset_bigram, no_bigrams)
-        unigrams.push((log_prob, ngram_count, 0, 0));
+    let mut symbols = Vec::new();
+    for (unigram, count) in vocabulary {
+        let id = ngrams_kept[0].ngrams;
+        sybt.insert(unigram.clone(), id);
+        writeln!(f_write_symt, "{}", unigram).map_err(|err| Error::io(&fname_write_symt, err))?;
+        symbols.push(unigram);
+        unigrams.entries.push(NGramEntry {
+            label: id,
+            count,
+            ..Default::default()
+        });
+        unigrams.index.insert(vec![id], id);
+        ngrams_kept[0].ngrams += 1;
+        ngrams_kept[0].cumulative_count += count as u64;
     }
-    println!("Done reading the 1grams!");
-    print_stats(time_start, ngrams_kept[0], ngrams_total[0]);
 
-    // ########## Starting with bigrams ##############
-    println!("Translating bigrams");
+    // Calculate the log probabilities of the unigrams now that the total count of all kept unigrams is known
+    for unigram in unigrams.entries.iter_mut() {
+        unigram.log_prob = (unigram.count as f32 / ngrams_kept[0].cumulative_count as f32).ln();
+    }
+    f_write_symt
+        .flush()
+        .map_err(|err| Error::io(&fname_write_symt, err))?;
+    // Shared with the worker threads that translate the ngrams of every order
+    let sybt = Arc::new(sybt);
+    orders.push(OrderSummary {
+        n: 1,
+        total: ngrams_total[0],
+        kept: ngrams_kept[0],
+        missing: MissingLowerOrder::default(),
+        pruned: NGramCounts::default(),
+        time_passed: time_start.elapsed(),
+    });
 
     // Go through the ngrams with increasing lengths
-    let mut bigrams: BTreeMap<(u32, u32), (u32, f32, u32, u32, u16)> = BTreeMap::new();
-    let mut translated_symbols = Vec::new(); // Temporarily store the translated symbols for the ngrams
-    let mut last_found_prefix = None;
-    let mut no_bigrams = 1;
-    let mut count_prefix = 0;
-    'bigram_loop: for (words, ngram_count) in all_bigrams {
-        translated_symbols.clear();
-        ngrams_total[1].0 += 1;
-        ngrams_total[1].1 += ngram_count;
-        for word in words {
-            if let Some(&id) = sybt.get(&word as &str) {
-                translated_symbols.push(id);
-            } else {
-                continue 'bigram_loop;
+    // The offsets and the number of longer ngrams of a table are only known once the ngrams of the next higher order were processed
+    let with_backoff = config.smoothing.has_backoff();
+    let mut tables = vec![unigrams];
+    let mut streamed = false;
+    // Words that are not in the vocabulary become <unk>, so the ngrams need to be sorted to merge the ones that became the same
+    let unknown = sybt
+        .get(UNKNOWN_WORD)
+        .copied()
+        .filter(|_| config.reserved_symbols);
+    for n in 2..=max_ngram_len {
+        let translated = translate_ngrams(
+            &config.ngram_files[n - 1],
+            n,
+            config.input_format,
+            (&sybt, unknown),
+            config.threads,
+            &mut ngrams_total[n - 1],
+        )?;
+        let mut ngrams = NGramTable::default();
+        let mut missing = MissingLowerOrder::default();
+        let mut pruned = NGramCounts::default();
+        let parts = input_parts(&config.ngram_files[n - 1]);
+        let unsorted_error = |ngram: &TranslatedNGram, disorder: Disorder| {
+            let words: Vec<&str> = ngram
+                .symbols
+                .iter()
+                .map(|&symbol| symbols[symbol as usize].as_str())
+                .collect();
+            let reason = match disorder {
+                Disorder::SplitPrefix => format!(
+                    "Ngrams with the prefix '{}' came before other ngrams",
+                    words[..n - 1].join(" ")
+                ),
+                Disorder::Duplicate => format!(
+                    "The ngram '{}' came before, but not right before",
+                    words.join(" ")
+                ),
+            };
+            Error::Parse {
+                path: parts[ngram.part as usize].clone(),
+                line_number: ngram.line_number,
+                line: format!("{} {}", words.join(" "), ngram.count),
+                reason: format!(
+                    "{}, the file needs to be sorted or sorting the ngrams needs to be enabled",
+                    reason
+                ),
             }
-        }
-        // If all of the words are valid, we found another valid ngram
+        };
 
-        // If the last prefix was not the same as the current one,
-        if Some(translated_symbols[0]) != last_found_prefix {
-            // Since the prefix changed, we know we found the last bigram with the prefix so we write the number of bigrams to the previous unigram
-            if let Some(prev_unigram) = last_found_prefix {
-                unigrams[prev_unigram as usize].3 = no_bigrams;
-                no_bigrams = 1; // We reset the number of unigrams with that prefix
+        if let Some(memory_budget) = config.memory_budget {
+            let mut sorter = ExternalSorter::new(&config.output_dir, n, memory_budget);
+            for ngram in translated {
+                let ngram = ngram?;
+                sorter.push(&ngram.symbols, ngram.count)?;
+            }
+            let merged = sorter.merge()?.map(|ngram| {
+                ngram.map(|(symbols, count)| TranslatedNGram {
+                    symbols,
+                    count,
+                    part: 0,
+                    line_number: 0,
+                })
+            });
+            let merged = config.pruning.apply(merged, n, &mut pruned);
+            // Nothing but the files needs the highest order with maximum likelihood estimates, so it does not have to be kept in memory
+            streamed = n == max_ngram_len
+                && !with_backoff
+                && config.arpa_file.is_none()
+                && config.binary_file.is_none()
+                && config.pruning.max_ngrams.is_none();
+            if streamed {
+                let fname_write_ngrams = config.output_file(n);
+                let mut writer =
+                    TableWriter::create(&fname_write_ngrams, n, max_ngram_len, with_backoff)?;
+                attach_ngrams(
+                    merged,
+                    &mut tables,
+                    Lookup::SortedChildren,
+                    &mut ngrams_kept[n - 1],
+                    &mut missing,
+                    unsorted_error,
+                    |_, entry| writer.write(&entry),
+                )?;
+                writer.finish()?;
+            } else {
+                attach_ngrams(
+                    merged,
+                    &mut tables,
+                    Lookup::SortedChildren,
+                    &mut ngrams_kept[n - 1],
+                    &mut missing,
+                    unsorted_error,
+                    |_, entry| {
+                        ngrams.entries.push(entry);
+                        Ok(())
+                    },
+                )?;
             }
-
-            last_found_prefix = Some(translated_symbols[0]); // we store the new found prefix
-            count_prefix = unigrams[translated_symbols[0] as usize].1;
-            unigrams[translated_symbols[0] as usize].2 = ngrams_kept[1].0; // we found the offset for the unigram table
         } else {
-            no_bigrams += 1; // If the prefix did not change, we found another one with the same prefix, so we increase the number by one
+            // The highest order does not need an index because there is no higher order that needs to look up its ngrams
+            let build_index = n < max_ngram_len;
+            let emit = |symbols: &[u32], entry| {
+                if build_index {
+                    ngrams
+                        .index
+                        .insert(symbols.to_vec(), ngrams.entries.len() as u32);
+                }
+                ngrams.entries.push(entry);
+                Ok(())
+            };
+            if config.sort_ngrams || unknown.is_some() {
+                // Sorting the ngrams by their symbols groups them by their prefixes
+                let mut sorted = translated.collect::<Result<Vec<_>>>()?;
+                sorted.sort_by(|a, b| a.symbols.cmp(&b.symbols));
+                sorted.dedup_by(|ngram, previous| {
+                    let duplicate = ngram.symbols == previous.symbols;
+                    if duplicate {
+                        previous.count = previous.count.saturating_add(ngram.count);
+                    }
+                    duplicate
+                });
+                attach_ngrams(
+                    config
+                        .pruning
+                        .apply(sorted.into_iter().map(Ok), n, &mut pruned),
+                    &mut tables,
+                    Lookup::Index,
+                    &mut ngrams_kept[n - 1],
+                    &mut missing,
+                    unsorted_error,
+                    emit,
+                )?;
+            } else {
+                attach_ngrams(
+                    config.pruning.apply(translated, n, &mut pruned),
+                    &mut tables,
+                    Lookup::Index,
+                    &mut ngrams_kept[n - 1],
+                    &mut missing,
+                    unsorted_error,
+                    emit,
+                )?;
+            }
+            // The index of the lower order is not needed anymore once the ngrams that refer to it were translated
+            tables[n - 2].index = HashMap::new();
+        }
+        orders.push(OrderSummary {
+            n,
+            total: ngrams_total[n - 1],
+            kept: ngrams_kept[n - 1],
+            missing,
+            pruned,
+            time_passed: time_start.elapsed(),
+        });
+        if !streamed {
+            tables.push(ngrams);
         }
+    }
 
-        log_prob = (ngram_count as f32 / count_prefix as f32).ln();
+    // The smoothing needs the counts of all orders, so it can only be applied once all of them were processed
+    smoothing::apply(config.smoothing, &mut tables);
+
+    // The relative entropy of the ngrams is only known once they have their probabilities
+    if let Some(max_ngrams) = config.pruning.max_ngrams {
+        let removed = prune::prune_entropy(&mut tables, max_ngrams, config.smoothing);
+        for (order, removed) in orders.iter_mut().zip(removed) {
+            order.kept.ngrams -= removed.ngrams;
+            order.kept.cumulative_count -= removed.cumulative_count;
+            order.pruned.ngrams += removed.ngrams;
+            order.pruned.cumulative_count += removed.cumulative_count;
+        }
+    }
 
-        bigrams.insert(
-            (translated_symbols[0], translated_symbols[1]),
-            (ngrams_kept[1].0, log_prob, ngram_count, 0, 0),
-        );
-        ngrams_kept[1].0 += 1;
-        ngrams_kept[1].1 += ngram_count;
+    // A streamed highest order was already written
+    for (i, table) in tables.iter().enumerate() {
+        model::write_table(
+            &config.output_file(i + 1),
+            &table.entries,
+            i + 1,
+            max_ngram_len,
+            with_backoff,
+        )?;
     }
 
-    // Add the offset and the no of bigrams for the last bigram to the unigram table
-    if let Some(prev_unigram) = last_found_prefix {
-        unigrams[prev_unigram as usize].3 = no_bigrams;
+    let tables: Vec<&[NGramEntry]> = tables.iter().map(|table| &table.entries[..]).collect();
+    if let Some(arpa_file) = &config.arpa_file {
+        arpa::write_arpa(arpa_file, &symbols, &tables, with_backoff)?;
+    }
+    if let Some(binary_file) = &config.binary_file {
+        binary::write_binary(
+            binary_file,
+            &symbols,
+            &tables,
+            with_backoff,
+            config.quantization,
+        )?;
     }
 
-    println!("Done reading the bigrams!");
-    print_stats(time_start, ngrams_kept[1], ngrams_total[1]);
+    Ok(GenerationSummary {
+        orders,
+        tied_words_dropped,
+        duration: time_start.elapsed(),
+    })
+}
 
-    println!("Writing unigrams to file");
-    for (log_prob, _, offset_longer_ngram, no_longer_ngram) in unigrams {
-        writeln!(
-            f_write_unigrams,
-            "{} {} {}",
-            log_prob, offset_longer_ngram, no_longer_ngram,
-        )
-        .expect("write failed");
+/// An ngram whose words were translated to their symbols
+pub(crate) struct TranslatedNGram {
+    pub(crate) symbols: Vec<u32>,
+    pub(crate) count: u32,
+    /// Index of the part of the input file the ngram was read from
+    pub(crate) part: u32,
+    /// Number of the line of the part the ngram was read from, 0 once the ngrams were sorted
+    pub(crate) line_number: usize,
+}
+
+/// Translates the words of all ngrams of one order to their symbols and keeps those that only consist of words from the symbol table
+/// If there is a symbol for unknown words, all other words become that symbol and all ngrams are kept
+/// All ngrams that were read are added to the total
+/// With more than one thread the file is parsed and translated in parallel, which gives the same ngrams in the same order
+fn translate_ngrams<'a>(
+    filename: &Path,
+    n: usize,
+    input_format: InputFormat,
+    (sybt, unknown): (&'a Arc<HashMap<String, u32>>, Option<u32>),
+    threads: usize,
+    ngrams_total: &'a mut NGramCounts,
+) -> Result<Box<dyn Iterator<Item = Result<TranslatedNGram>> + 'a>> {
+    if threads > 1 && input_format == InputFormat::Counts {
+        let translator =
+            ParallelTranslator::new(filename, n, (sybt, unknown), threads, ngrams_total)?;
+        return Ok(Box::new(translator));
     }
-    println!("Done writing unigrams to file");
-    println!();
+    let mut all_ngrams = NGramIterator::with_format(filename, n, input_format)?;
+    Ok(Box::new(std::iter::from_fn(move || loop {
+        let (words, ngram_count) = match all_ngrams.next()? {
+            Ok(ngram) => ngram,
+            Err(err) => return Some(Err(err)),
+        };
+        ngrams_total.ngrams += 1;
+        ngrams_total.cumulative_count += ngram_count as u64;
+        let translated_symbols = words
+            .iter()
+            .map(|word| sybt.get(word as &str).copied().or(unknown))
+            .collect::<Option<Vec<u32>>>();
+        if let Some(symbols) = translated_symbols {
+            return Some(Ok(TranslatedNGram {
+                symbols,
+                count: ngram_count,
+                part: all_ngrams.part() as u32,
+                line_number: all_ngrams.line_number(),
+            }));
+        }
+    })))
+}
 
-    // ########## Starting with trigrams ##############
-    println!("Translating trigrams");
+/// How the prefixes and suffixes of the ngrams are looked up in the table of the next lower order
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Lookup {
+    /// In the index of the table, which needs to be built while it is translated
+    Index,
+    /// By searching the children from the unigrams on, which needs the labels of all children to be sorted
+    SortedChildren,
+}
 
-    // Go through the ngrams with increasing lengths
-    let mut trigrams = Vec::new();
-    let mut translated_symbols = Vec::new(); // Temporarily store the translated symbols for the ngrams
-    let mut last_found_prefix: Option<(u32, u32)> = None;
-    let mut no_trigrams = 1;
+/// Index of the ngram made up of the symbols, if all tables are sorted by their symbols
+fn find_sorted(tables: &[NGramTable], symbols: &[u32]) -> Option<u32> {
+    let mut idx = *symbols.first()?;
+    tables.first()?.entries.get(idx as usize)?;
+    for (n, &label) in symbols.iter().enumerate().skip(1) {
+        let entry = &tables.get(n - 1)?.entries[idx as usize];
+        let children = tables.get(n)?.entries.get(entry.children())?;
+        let child = children
+            .binary_search_by_key(&label, |child| child.label)
+            .ok()?;
+        idx = entry.offset + child as u32;
+    }
+    Some(idx)
+}
+
+/// Why ngrams that are not sorted can't be attached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Disorder {
+    /// Ngrams with the same prefix are not next to each other
+    SplitPrefix,
+    /// The same ngram occurs twice with the same prefix, but not next to each other
+    Duplicate,
+}
+
+/// Attaches the translated ngrams of one order to their prefixes in the table of the next lower order, which is the last of the tables
+/// Ngrams whose prefix or suffix was not kept are skipped and added to `missing`, the others are passed to `emit` one after the other
+/// The offsets and the number of longer ngrams of the table of the next lower order get updated on the way
+/// The ngrams with the same prefix need to come one after the other and each of them only once,
+/// otherwise the error of `unsorted_error` is returned
+fn attach_ngrams<I, U, F>(
+    ngrams: I,
+    tables: &mut [NGramTable],
+    lookup: Lookup,
+    ngrams_kept: &mut NGramCounts,
+    missing: &mut MissingLowerOrder,
+    unsorted_error: U,
+    mut emit: F,
+) -> Result<()>
+where
+    I: Iterator<Item = Result<TranslatedNGram>>,
+    U: Fn(&TranslatedNGram, Disorder) -> Error,
+    F: FnMut(&[u32], NGramEntry) -> Result<()>,
+{
+    let lower = tables.len() - 1;
+    let mut last_found_prefix: Option<u32> = None;
+    let mut no_longer_ngrams = 1;
     let mut count_prefix = 0;
-    'trigram_loop: for (words, ngram_count) in all_trigrams {
-        translated_symbols.clear();
-        ngrams_total[2].0 += 1;
-        ngrams_total[2].1 += ngram_count;
-        for word in words {
-            if let Some(&id) = sybt.get(&word as &str) {
-                translated_symbols.push(id);
-            } else {
-                continue 'trigram_loop;
-            }
-        }
-        // If all of the words are valid, we found another valid trigram
+    // The labels of the ngrams with the last prefix
+    let mut labels = HashSet::new();
+    for ngram in ngrams {
+        let ngram = ngram?;
+        let (translated_symbols, ngram_count) = (&ngram.symbols, ngram.count);
+
+        // The prefix (all but the last word) and the suffix (all but the first word) need to be ngrams of the lower order
+        // If one of them was not kept, there is nowhere to attach the ngram to so it is skipped
+        let n = translated_symbols.len();
+        let find = |symbols: &[u32]| match lookup {
+            Lookup::Index => tables[lower].index.get(symbols).copied(),
+            Lookup::SortedChildren => find_sorted(tables, symbols),
+        };
+        let skip = |missing: &mut NGramCounts| {
+            missing.ngrams += 1;
+            missing.cumulative_count += ngram_count as u64;
+        };
+        let Some(prefix) = find(&translated_symbols[..n - 1]) else {
+            skip(&mut missing.prefix);
+            continue;
+        };
+        let Some(suffix) = find(&translated_symbols[1..]) else {
+            skip(&mut missing.suffix);
+            continue;
+        };
+        let lower_order_ngrams = &mut tables[lower];
 
         // If the last prefix was not the same as the current one,
-        //                     if let Some(prev_prefix) = last_found_prefix {
-        //                       if (translated_symbols[0], translated_symbols[1]) != prev_prefix {
-
-        if Some((translated_symbols[0], translated_symbols[1])) != last_found_prefix {
-            // Since the prefix changed, we know we found the last trigram with the prefix so we write the number of trigrams to the previous bigram
-            if let Some(prev_bigram) = last_found_prefix {
-                let mut prev_bigram = bigrams.get_mut(&prev_bigram).unwrap();
-                (*prev_bigram).4 = no_trigrams;
-                no_trigrams = 1; // We reset the number of trigrams with that prefix
+        if Some(prefix) != last_found_prefix {
+            // Since the prefix changed, we know we found the last ngram with the prefix so we write the number of ngrams to the previous prefix
+            if let Some(prev_prefix) = last_found_prefix {
+                lower_order_ngrams.entries[prev_prefix as usize].no_longer_ngrams =
+                    no_longer_ngrams;
+                no_longer_ngrams = 1; // We reset the number of ngrams with that prefix
             }
 
-            // Since the prefix changed, we know we found the last bigram with the prefix so we write the number of bigrams to the previous unigram
-            // bigrams.get_mut(&prev_prefix).unwrap().3 = no_bigrams;
-            last_found_prefix = Some((translated_symbols[0], translated_symbols[1])); // we store the new found prefix
-            let bigram_entry = bigrams
-                .entry((translated_symbols[0], translated_symbols[1]))
-                .or_default();
-
-            bigram_entry.3 = ngrams_kept[2].0; // we found the offset for the bigram table
-            count_prefix = bigram_entry.2;
+            last_found_prefix = Some(prefix); // we store the new found prefix
+            let prefix_entry = &mut lower_order_ngrams.entries[prefix as usize];
+            // If the prefix already has longer ngrams, they were not next to this one, so the offset would be wrong
+            if prefix_entry.no_longer_ngrams != 0 {
+                return Err(unsorted_error(&ngram, Disorder::SplitPrefix));
+            }
+            labels.clear();
+            prefix_entry.offset = ngrams_kept.ngrams; // we found the offset for the table of the lower order
+            count_prefix = prefix_entry.count;
         } else {
-            no_trigrams += 1; // If the prefix did not change, we found another one with the same prefix, so we increase the number by one
+            no_longer_ngrams += 1; // If the prefix did not change, we found another one with the same prefix, so we increase the number by one
         }
-        log_prob = (ngram_count as f32 / count_prefix as f32).ln();
 
-        let idx_suffix = bigrams
-            .get_mut(&(translated_symbols[1], translated_symbols[2]))
-            .unwrap()
-            .0;
+        if !labels.insert(translated_symbols[n - 1]) {
+            return Err(unsorted_error(&ngram, Disorder::Duplicate));
+        }
 
-        trigrams.push((translated_symbols[2], log_prob, idx_suffix));
+        let log_prob = (ngram_count as f32 / count_prefix as f32).ln();
 
-        ngrams_kept[2].0 += 1;
-        ngrams_kept[2].1 += ngram_count;
+        // The offsets are stored as u32, so there can't be more ngrams of one order
+        if ngrams_kept.ngrams == u32::MAX {
+            return Err(Error::InconsistentModel(format!(
+                "More than {} {}grams were kept",
+                u32::MAX,
+                n
+            )));
+        }
+        emit(
+            translated_symbols,
+            NGramEntry {
+                label: translated_symbols[n - 1],
+                log_prob,
+                count: ngram_count,
+                suffix,
+                ..Default::default()
+            },
+        )?;
+        ngrams_kept.ngrams += 1;
+        ngrams_kept.cumulative_count += ngram_count as u64;
     }
 
-    // Add the offset and the no of trigrams for the last trigram to the bigram table
-    if let Some(prev_bigram) = last_found_prefix {
-        let mut prev_bigram = bigrams.get_mut(&prev_bigram).unwrap();
-        (*prev_bigram).4 = no_trigrams;
+    // Add the number of longer ngrams for the last prefix to the table of the lower order
+    if let Some(prev_prefix) = last_found_prefix {
+        tables[lower].entries[prev_prefix as usize].no_longer_ngrams = no_longer_ngrams;
     }
+    Ok(())
+}
 
-    println!("Done reading the trigrams!");
-    print_stats(time_start, ngrams_kept[2], ngrams_total[2]);
-
-    // Writing to files
-    println!();
-    println!("Starting to write ngrams to files");
-    println!("Writing bigrams to file!");
-
-    // Write bigrams to file
-    for ((_, label), (_, log_prob, _, offset_longer_ngram, no_longer_ngram)) in bigrams {
+impl fmt::Display for OrderSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let skipped = self.skipped();
+        writeln!(f, "Done processing the {}grams!", self.n)?;
+        writeln!(f, "Time passed since start: {:?}", self.time_passed)?;
         writeln!(
-            f_write_bigrams,
-            "{} {} {} {}",
-            label, log_prob, offset_longer_ngram, no_longer_ngram,
-        )
-        .expect("write failed");
-    }
-    println!("Done writing bigrams to file!");
-
-    println!("Writing trigrams to file!");
-    // Write trigrams to file
-    for (label, log_prob, offset_unigram_referring_to_bigram) in trigrams {
+            f,
+            "{} ngrams with a cumulative count of {} were skipped",
+            skipped.ngrams, skipped.cumulative_count
+        )?;
         writeln!(
-            f_write_trigrams,
-            "{} {} {}",
-            label, log_prob, offset_unigram_referring_to_bigram,
-        )
-        .expect("write failed");
+            f,
+            "{} ngrams with a cumulative count of {} were kept",
+            self.kept.ngrams, self.kept.cumulative_count
+        )?;
+        writeln!(
+            f,
+            "In other words {:.3}% of the ngrams were skipped, which made up {:.3}% of the total count",
+            percentage(skipped.ngrams as u64, self.total.ngrams as u64),
+            percentage(skipped.cumulative_count, self.total.cumulative_count),
+        )?;
+        if self.pruned.ngrams > 0 {
+            writeln!(
+                f,
+                "Of them {} ngrams with a cumulative count of {} were pruned",
+                self.pruned.ngrams, self.pruned.cumulative_count
+            )?;
+        }
+        if self.missing.prefix.ngrams > 0 || self.missing.suffix.ngrams > 0 {
+            writeln!(
+                f,
+                "Of them {} were skipped because their prefix and {} because their suffix was not kept",
+                self.missing.prefix.ngrams, self.missing.suffix.ngrams
+            )?;
+        }
+        Ok(())
     }
-    println!("Done writing trigrams to file!");
 }
 
-fn print_stats(time_start: Instant, ngrams_kept: (u32, u32), ngrams_total: (u32, u32)) {
-    let time_end = Instant::now();
-    let duration = time_end.saturating_duration_since(time_start);
-    println!("Time passed since start: {:?}", duration);
+/// Percentage of the part of the total, 0 if the total is 0 like for an empty ngram file
+fn percentage(part: u64, total: u64) -> f32 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f32 / total as f32 * 100.0
+}
 
-    let ngrams_skipped = (
-        ngrams_total.0 - ngrams_kept.0,
-        ngrams_total.1 - ngrams_kept.1,
-    );
-    println!(
-        "{} ngrams with a cumulative count of {} were skipped",
-        ngrams_skipped.0, ngrams_skipped.1
-    );
-    println!(
-        "{} ngrams with a cumulative count of {} were kept",
-        ngrams_kept.0, ngrams_kept.1
-    );
-    println!(
-        "In other words {:.3}% of the ngrams were skipped, which made up {:.3}% of the total count",
-        ngrams_skipped.0 as f32 / (ngrams_kept.0 as f32 + ngrams_skipped.0 as f32) * 100.0,
-        ngrams_skipped.1 as f32 / (ngrams_kept.1 as f32 + ngrams_skipped.1 as f32) * 100.0,
-    );
-    println!();
+impl fmt::Display for GenerationSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for order in &self.orders {
+            writeln!(f, "{}", order)?;
+        }
+        write!(
+            f,
+            "Done generating the language model in {:?}",
+            self.duration
+        )
+    }
 }
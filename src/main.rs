@@ -1,5 +1,858 @@
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use ngrams_to_language_model::arpa::import_arpa;
+use ngrams_to_language_model::evaluate::{evaluate, keystroke_savings};
+use ngrams_to_language_model::quantize::perplexity_impact;
+use ngrams_to_language_model::utilities::{LinesIterator, NGramIterator, WordListIterator};
+use ngrams_to_language_model::{
+    generate, CorpusCounter, CorpusFormat, Error, GeneratorConfig, InputFormat, LanguageModel,
+    ModelTables, Pruning, Quantization, Result, Smoothing, TieBreak, VocabularySelection,
+};
+
+const USAGE: &str = "Transform ngrams into a language model
+
+Usage: ngrams_to_language_model <COMMAND> [OPTIONS] [WORDS...]
+
+Commands:
+  build      Build a language model from the ngram files, after counting them in the files given with --corpus
+  count      Count the ngrams of the files given with --corpus and write them to the ngram directory
+  stats      Print the number of ngrams of each order of a generated model
+  query      Print the log probability of the last of the WORDS given the preceding ones
+  validate   Check that the ngram files and the dictionary can be parsed, or with --model that the tables of a generated model fit together
+  evaluate   Print the perplexity, cross-entropy, OOV rate and hit rate per order of a generated model on the text given with --heldout,
+             or with --keystrokes the keystroke savings and next word accuracy of typing it with the suggestions of the model
+  export     Write a generated model to the file given with --arpa in the ARPA format and/or to the one given with --binary
+  import     Convert the ARPA model given with --arpa to the format of a generated model
+  help       Print this help
+
+Options:
+  -r, --root <DIR>            Folder with ngrams_ALL/, dict/words_allow.txt and ngrams_result/ [default: ./LanguageModel/]
+  -i, --ngrams-dir <DIR>      Folder with the input files <n>gms.txt [default: <root>/ngrams_ALL/]
+      --ngram-file <N>=<FILE> File with the ngrams of length N, overrides the file from --ngrams-dir
+  -d, --dictionary <FILE>     File with the allowed words [default: <root>/dict/words_allow.txt]
+  -o, --output-dir <DIR>      Folder the model is written to or read from [default: <root>/ngrams_result/]
+  -n, --order <N>             Length of the longest ngrams [default: 3, or the longest ngrams of the model]
+  -k, --max-words <N>         Maximum number of words in the vocabulary [default: 30000]
+      --vocabulary <METHOD>   Choose the words by top, min-count:<N> or coverage:<FRACTION> of the tokens, at most --max-words [default: top]
+      --tie-break <RULE>      Keep all words tied with the last word of the vocabulary or only the first or lexicographic ones [default: keep]
+      --include <FILE>        Words that are always kept, one per line
+      --deny <FILE>           Words that are never kept, one per line
+      --min-counts <N>,...    Prune the bigrams, trigrams, ... that occurred fewer than N times, the last N applies to all longer ngrams
+      --max-continuations <N>,...
+                              Only keep the N most frequent bigrams, trigrams, ... with the same prefix, the last N applies to all longer ngrams
+      --max-ngrams <N>        Prune the ngrams of length 2 or more by relative entropy until N of them are left
+  -a, --arpa <FILE>           ARPA file to import from or export to, build also writes the model to it
+  -b, --binary <FILE>         Binary model file to export to, build also writes the model to it, query and stats read it instead of the output directory
+  -Q, --quantize <METHOD>     Quantize the values of the binary model with uniform[:<bits>] or kmeans[:<bits>], bits is 8 or 16 [default bits: 8]
+      --model                 Make validate check the model in the output directory or the one given with --binary
+      --keystrokes            Make evaluate type the held-out text character by character with a bar of K suggestions [default K: 3]
+      --heldout <FILE>        Text with one sentence per line to evaluate the model on, makes export print how much the quantization changes the perplexity on it
+  -t, --top <K>               Make query print the K most probable words that follow the WORDS, or show K suggestions with --keystrokes
+  -p, --prefix <TEXT>         Make query print the K best completions of TEXT after the WORDS [default K: 10]
+  -m, --memory-budget <MB>    Sort the ngrams on disk with at most about MB megabytes of memory instead of keeping them all in memory
+      --sort                  Sort the ngrams in memory, so the ngram files don't need to be grouped by prefix
+      --unk                   Keep <s> and </s> and map the words that are not in the vocabulary to <unk>, import keeps <s>, </s> and <unk>
+  -j, --threads <N>           Parse the ngram files on N threads [default: 1]
+      --corpus <FILE>         Text file to count the ngrams of, can be given more than once
+      --corpus-format <FORMAT>
+                              lines for one sentence per line or text for running text [default: lines]
+      --input-format <FORMAT> counts or google-books[:<first_year>-<last_year>][:keep|strip|skip] for the raw Google Books Ngram format [default: counts]
+  -s, --smoothing <METHOD>    ml, absolute[:<discount>], kneser-ney, katz[:<max_count>] or stupid[:<alpha>] [default: ml]
+  -f, --format <FORMAT>       Output format, text or json [default: text]
+  -q, --quiet                 Only print errors
+  -v, --verbose               Print more details";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Build,
+    Count,
+    Stats,
+    Query,
+    Validate,
+    Evaluate,
+    Export,
+    Import,
+    Help,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+struct Options {
+    command: Command,
+    root: PathBuf,
+    ngrams_dir: Option<PathBuf>,
+    ngram_files: Vec<(usize, PathBuf)>,
+    dictionary: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    order: Option<usize>,
+    max_words: usize,
+    vocabulary: VocabularySelection,
+    tie_break: TieBreak,
+    include_words: Option<PathBuf>,
+    deny_words: Option<PathBuf>,
+    smoothing: Smoothing,
+    pruning: Pruning,
+    arpa_file: Option<PathBuf>,
+    binary_file: Option<PathBuf>,
+    quantization: Option<Quantization>,
+    heldout_file: Option<PathBuf>,
+    memory_budget: Option<usize>,
+    sort: bool,
+    unk: bool,
+    model: bool,
+    keystrokes: bool,
+    threads: usize,
+    input_format: InputFormat,
+    corpus_files: Vec<PathBuf>,
+    corpus_format: CorpusFormat,
+    top: Option<usize>,
+    prefix: Option<String>,
+    format: Format,
+    verbosity: Verbosity,
+    words: Vec<String>,
+}
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> std::result::Result<Self, String> {
+        let command = match args.next().as_deref() {
+            Some("build") => Command::Build,
+            Some("count") => Command::Count,
+            Some("stats") => Command::Stats,
+            Some("query") => Command::Query,
+            Some("validate") => Command::Validate,
+            Some("evaluate") => Command::Evaluate,
+            Some("export") => Command::Export,
+            Some("import") => Command::Import,
+            Some("help") | Some("-h") | Some("--help") => Command::Help,
+            Some(other) => return Err(format!("Unknown command '{}'", other)),
+            None => return Err("No command given".to_string()),
+        };
+        let mut options = Options {
+            command,
+            root: PathBuf::from("./LanguageModel/"),
+            ngrams_dir: None,
+            ngram_files: Vec::new(),
+            dictionary: None,
+            output_dir: None,
+            order: None,
+            max_words: 30_000,
+            vocabulary: VocabularySelection::default(),
+            tie_break: TieBreak::default(),
+            include_words: None,
+            deny_words: None,
+            smoothing: Smoothing::default(),
+            pruning: Pruning::default(),
+            arpa_file: None,
+            binary_file: None,
+            quantization: None,
+            heldout_file: None,
+            memory_budget: None,
+            sort: false,
+            unk: false,
+            model: false,
+            keystrokes: false,
+            threads: 1,
+            input_format: InputFormat::default(),
+            corpus_files: Vec::new(),
+            corpus_format: CorpusFormat::default(),
+            top: None,
+            prefix: None,
+            format: Format::Text,
+            verbosity: Verbosity::Normal,
+            words: Vec::new(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for '{}'", name))
+            };
+            match arg.as_str() {
+                "-r" | "--root" => options.root = PathBuf::from(value(&arg)?),
+                "-i" | "--ngrams-dir" => options.ngrams_dir = Some(PathBuf::from(value(&arg)?)),
+                "--ngram-file" => {
+                    let value = value(&arg)?;
+                    let (n, file) = value
+                        .split_once('=')
+                        .ok_or_else(|| format!("Expected <N>=<FILE>, got '{}'", value))?;
+                    options
+                        .ngram_files
+                        .push((parse_number(&arg, n)?, PathBuf::from(file)));
+                }
+                "-d" | "--dictionary" => options.dictionary = Some(PathBuf::from(value(&arg)?)),
+                "-o" | "--output-dir" => options.output_dir = Some(PathBuf::from(value(&arg)?)),
+                "-n" | "--order" => options.order = Some(parse_number(&arg, &value(&arg)?)?),
+                "-k" | "--max-words" => options.max_words = parse_number(&arg, &value(&arg)?)?,
+                "--vocabulary" => {
+                    options.vocabulary =
+                        value(&arg)?.parse().map_err(|err: Error| err.to_string())?
+                }
+                "--tie-break" => {
+                    options.tie_break =
+                        value(&arg)?.parse().map_err(|err: Error| err.to_string())?
+                }
+                "--include" => options.include_words = Some(PathBuf::from(value(&arg)?)),
+                "--deny" => options.deny_words = Some(PathBuf::from(value(&arg)?)),
+                "-s" | "--smoothing" => {
+                    options.smoothing =
+                        value(&arg)?.parse().map_err(|err: Error| err.to_string())?
+                }
+                "--min-counts" => options.pruning.min_counts = parse_numbers(&arg, &value(&arg)?)?,
+                "--max-continuations" => {
+                    options.pruning.max_continuations = parse_numbers(&arg, &value(&arg)?)?
+                }
+                "--max-ngrams" => {
+                    options.pruning.max_ngrams = Some(parse_number(&arg, &value(&arg)?)?)
+                }
+                "-t" | "--top" => options.top = Some(parse_number(&arg, &value(&arg)?)?),
+                "-p" | "--prefix" => options.prefix = Some(value(&arg)?),
+                "-a" | "--arpa" => options.arpa_file = Some(PathBuf::from(value(&arg)?)),
+                "-b" | "--binary" => options.binary_file = Some(PathBuf::from(value(&arg)?)),
+                "-Q" | "--quantize" => {
+                    options.quantization =
+                        Some(value(&arg)?.parse().map_err(|err: Error| err.to_string())?)
+                }
+                "-m" | "--memory-budget" => {
+                    options.memory_budget = Some(parse_number(&arg, &value(&arg)?)?)
+                }
+                "--sort" => options.sort = true,
+                "--unk" => options.unk = true,
+                "--model" => options.model = true,
+                "--keystrokes" => options.keystrokes = true,
+                "--corpus" => options.corpus_files.push(PathBuf::from(value(&arg)?)),
+                "--corpus-format" => {
+                    options.corpus_format =
+                        value(&arg)?.parse().map_err(|err: Error| err.to_string())?
+                }
+                "--input-format" => {
+                    options.input_format =
+                        value(&arg)?.parse().map_err(|err: Error| err.to_string())?
+                }
+                "-j" | "--threads" => options.threads = parse_number(&arg, &value(&arg)?)?,
+                "--heldout" => options.heldout_file = Some(PathBuf::from(value(&arg)?)),
+                "-f" | "--format" => {
+                    options.format = match value(&arg)?.as_str() {
+                        "text" => Format::Text,
+                        "json" => Format::Json,
+                        other => return Err(format!("Unknown format '{}'", other)),
+                    }
+                }
+                "-q" | "--quiet" => options.verbosity = Verbosity::Quiet,
+                "-v" | "--verbose" => options.verbosity = Verbosity::Verbose,
+                "-h" | "--help" => options.command = Command::Help,
+                _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+                _ => options.words.push(arg),
+            }
+        }
+        if options.command != Command::Query && !options.words.is_empty() {
+            return Err(format!("Unexpected argument '{}'", options.words[0]));
+        }
+        Ok(options)
+    }
+
+    fn ngrams_dir(&self) -> PathBuf {
+        self.ngrams_dir
+            .clone()
+            .unwrap_or_else(|| self.root.join("ngrams_ALL"))
+    }
+
+    fn output_dir(&self) -> PathBuf {
+        self.output_dir
+            .clone()
+            .unwrap_or_else(|| self.root.join("ngrams_result"))
+    }
+
+    fn dictionary(&self) -> PathBuf {
+        self.dictionary
+            .clone()
+            .unwrap_or_else(|| self.root.join("dict").join("words_allow.txt"))
+    }
+
+    /// Length of the longest ngrams of the model in the output directory, unless it is given with --order
+    fn model_order(&self) -> Result<usize> {
+        match self.order {
+            Some(order) => Ok(order),
+            None => ModelTables::max_ngram_len_in(self.output_dir()),
+        }
+    }
+
+    fn config(&self) -> Result<GeneratorConfig> {
+        let mut builder = GeneratorConfig::builder()
+            .ngrams_dir(self.ngrams_dir())
+            .dictionary(self.dictionary())
+            .output_dir(self.output_dir())
+            .max_no_words(self.max_words)
+            .max_ngram_len(self.order.unwrap_or(3))
+            .vocabulary(self.vocabulary)
+            .tie_break(self.tie_break)
+            .smoothing(self.smoothing)
+            .pruning(self.pruning.clone())
+            .sort_ngrams(self.sort)
+            .reserved_symbols(self.unk)
+            .threads(self.threads)
+            .input_format(self.input_format);
+        for (n, file) in &self.ngram_files {
+            if *n == 0 {
+                return Err(Error::InvalidConfig(
+                    "The ngrams need to have a length of at least 1".to_string(),
+                ));
+            }
+            builder = builder.ngram_file(*n, file);
+        }
+        if let Some(include_words) = &self.include_words {
+            builder = builder.include_words(include_words);
+        }
+        if let Some(deny_words) = &self.deny_words {
+            builder = builder.deny_words(deny_words);
+        }
+        if let Some(arpa_file) = &self.arpa_file {
+            builder = builder.arpa_file(arpa_file);
+        }
+        if let Some(binary_file) = &self.binary_file {
+            builder = builder.binary_file(binary_file);
+        }
+        if let Some(quantization) = self.quantization {
+            builder = builder.quantization(quantization);
+        }
+        if let Some(megabytes) = self.memory_budget {
+            builder = builder.memory_budget(megabytes.saturating_mul(1 << 20));
+        }
+        builder.build()
+    }
+}
+
+fn parse_number(option: &str, value: &str) -> std::result::Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid number '{}' for '{}'", value, option))
+}
+
+/// Parse a comma separated list of numbers
+fn parse_numbers<T: std::str::FromStr>(
+    option: &str,
+    value: &str,
+) -> std::result::Result<Vec<T>, String> {
+    value
+        .split(',')
+        .map(|number| {
+            number
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid number '{}' for '{}'", number, option))
+        })
+        .collect()
+}
+
+/// JSON has no infinity, so impossible events are written as null
+fn json_number(value: f32) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// Escape a string so it can be used inside of quotes in JSON
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn build(options: &Options) -> Result<bool> {
+    if !options.corpus_files.is_empty() {
+        count(options)?;
+    }
+    let config = options.config()?;
+    if options.verbosity == Verbosity::Verbose {
+        eprintln!(
+            "Building a model with {}grams and {} smoothing",
+            config.max_ngram_len(),
+            config.smoothing
+        );
+        for (i, file) in config.ngram_files.iter().enumerate() {
+            eprintln!("{}grams: {}", i + 1, file.display());
+        }
+        eprintln!("Dictionary: {}", config.dictionary.display());
+        eprintln!("Output directory: {}", config.output_dir.display());
+    }
+    let summary = generate(&config)?;
+    if let Some(warning) = summary.tie_warning() {
+        if options.verbosity != Verbosity::Quiet {
+            eprintln!("{}", warning);
+        }
+    }
+    match (options.format, options.verbosity) {
+        (_, Verbosity::Quiet) => {}
+        (Format::Text, _) => println!("{}", summary),
+        (Format::Json, _) => {
+            let orders: Vec<String> = summary
+                .orders
+                .iter()
+                .map(|order| {
+                    format!(
+                        "{{\"n\":{},\"total\":{},\"total_count\":{},\"kept\":{},\"kept_count\":{},\"missing_prefix\":{},\"missing_suffix\":{},\"pruned\":{},\"seconds\":{}}}",
+                        order.n,
+                        order.total.ngrams,
+                        order.total.cumulative_count,
+                        order.kept.ngrams,
+                        order.kept.cumulative_count,
+                        order.missing.prefix.ngrams,
+                        order.missing.suffix.ngrams,
+                        order.pruned.ngrams,
+                        order.time_passed.as_secs_f64()
+                    )
+                })
+                .collect();
+            println!(
+                "{{\"orders\":[{}],\"tied_words_dropped\":{},\"seconds\":{}}}",
+                orders.join(","),
+                summary.tied_words_dropped,
+                summary.duration.as_secs_f64()
+            );
+        }
+    }
+    Ok(true)
+}
+
+fn count(options: &Options) -> Result<bool> {
+    if options.corpus_files.is_empty() {
+        return Err(Error::InvalidConfig(
+            "No corpus given, use --corpus <FILE>".to_string(),
+        ));
+    }
+    let dir = options.ngrams_dir();
+    let mut counter = CorpusCounter::new(options.order.unwrap_or(3), options.corpus_format);
+    if let Some(megabytes) = options.memory_budget {
+        counter = counter.memory_budget(megabytes.saturating_mul(1 << 20), &dir);
+    }
+    for corpus_file in &options.corpus_files {
+        if options.verbosity == Verbosity::Verbose {
+            eprintln!("Counting the ngrams of {}", corpus_file.display());
+        }
+        counter.add_file(corpus_file)?;
+    }
+    let no_sentences = counter.no_sentences();
+    let files = counter.write_ngram_files(&dir)?;
+    if options.verbosity == Verbosity::Verbose {
+        eprintln!("Counted the ngrams of {} sentences", no_sentences);
+        for file in &files {
+            eprintln!("{}", file.display());
+        }
+    }
+    Ok(true)
+}
+
+fn stats(options: &Options) -> Result<bool> {
+    let (no_symbols, no_ngrams) = match &options.binary_file {
+        Some(binary_file) => {
+            let model = LanguageModel::map(binary_file)?;
+            let no_ngrams = (1..=model.max_ngram_len())
+                .map(|n| model.no_ngrams(n))
+                .collect();
+            (model.symbol_table().len(), no_ngrams)
+        }
+        None => {
+            let dir = options.output_dir();
+            let no_symbols = LinesIterator::new(dir.join("symt.txt"))?.count();
+            let order = options.model_order()?;
+            let mut no_ngrams = Vec::with_capacity(order);
+            for n in 1..=order {
+                let path = dir.join(format!("{}gms.txt", n));
+                if !path.is_file() {
+                    return Err(Error::MissingOrderFile { n, path });
+                }
+                no_ngrams.push(LinesIterator::new(path)?.count());
+            }
+            (no_symbols, no_ngrams)
+        }
+    };
+    match options.format {
+        Format::Text => {
+            println!("{} words in the symbol table", no_symbols);
+            for (i, no) in no_ngrams.iter().enumerate() {
+                println!("{} {}grams", no, i + 1);
+            }
+        }
+        Format::Json => {
+            let no_ngrams: Vec<String> = no_ngrams.iter().map(|no| no.to_string()).collect();
+            println!(
+                "{{\"symbols\":{},\"ngrams\":[{}]}}",
+                no_symbols,
+                no_ngrams.join(",")
+            );
+        }
+    }
+    Ok(true)
+}
+
+fn query(options: &Options) -> Result<bool> {
+    let model = match &options.binary_file {
+        Some(binary_file) => LanguageModel::map(binary_file)?,
+        None => LanguageModel::load(options.output_dir(), options.model_order()?)?,
+    };
+    let words: Vec<&str> = options.words.iter().map(|word| word.as_str()).collect();
+    if let Some(prefix) = &options.prefix {
+        let k = options.top.unwrap_or(10);
+        return complete_word(&model, &words, prefix, k, options.format);
+    }
+    if let Some(k) = options.top {
+        return predict_next_words(&model, &words, k, options.format);
+    }
+    if words.is_empty() {
+        return Err(Error::InvalidConfig("No words to query given".to_string()));
+    }
+    let log_prob = model.log_prob(&words);
+    // Whether the whole ngram is in the tables or its probability comes from backing off
+    let in_model = words
+        .iter()
+        .map(|word| model.symbol(word))
+        .collect::<Option<Vec<u32>>>()
+        .and_then(|symbols| model.get(&symbols))
+        .is_some();
+    let ngram = words.join(" ");
+    match (options.format, log_prob) {
+        (Format::Text, Some(log_prob)) => {
+            if options.verbosity == Verbosity::Verbose {
+                let source = if in_model {
+                    "in the model"
+                } else {
+                    "backed off"
+                };
+                println!("{}\t{}\t{}", ngram, log_prob, source);
+            } else {
+                println!("{}\t{}", ngram, log_prob);
+            }
+        }
+        (Format::Text, None) => println!("{}\tunknown word", ngram),
+        (Format::Json, Some(log_prob)) => println!(
+            "{{\"ngram\":{},\"known\":true,\"in_model\":{},\"log_prob\":{}}}",
+            json_string(&ngram),
+            in_model,
+            json_number(log_prob)
+        ),
+        (Format::Json, None) => println!("{{\"ngram\":{},\"known\":false}}", json_string(&ngram)),
+    }
+    Ok(log_prob.is_some())
+}
+
+fn predict_next_words(
+    model: &LanguageModel,
+    history: &[&str],
+    k: usize,
+    format: Format,
+) -> Result<bool> {
+    let candidates = model.next_word_candidates(history, k);
+    match format {
+        Format::Text => {
+            for (word, log_prob) in &candidates {
+                println!("{}\t{}", word, log_prob);
+            }
+        }
+        Format::Json => {
+            let candidates: Vec<String> = candidates
+                .iter()
+                .map(|(word, log_prob)| {
+                    format!(
+                        "{{\"word\":{},\"log_prob\":{}}}",
+                        json_string(word),
+                        json_number(*log_prob)
+                    )
+                })
+                .collect();
+            println!("[{}]", candidates.join(","));
+        }
+    }
+    Ok(!candidates.is_empty())
+}
+
+fn complete_word(
+    model: &LanguageModel,
+    history: &[&str],
+    prefix: &str,
+    k: usize,
+    format: Format,
+) -> Result<bool> {
+    let predictions = model.predict(history, prefix, k);
+    match format {
+        Format::Text => {
+            for prediction in &predictions {
+                println!(
+                    "{}\t{}\t{}",
+                    prediction.word, prediction.log_prob, prediction.order
+                );
+            }
+        }
+        Format::Json => {
+            let predictions: Vec<String> = predictions
+                .iter()
+                .map(|prediction| {
+                    format!(
+                        "{{\"word\":{},\"log_prob\":{},\"order\":{}}}",
+                        json_string(prediction.word),
+                        json_number(prediction.log_prob),
+                        prediction.order
+                    )
+                })
+                .collect();
+            println!("[{}]", predictions.join(","));
+        }
+    }
+    Ok(!predictions.is_empty())
+}
+
+fn validate(options: &Options) -> Result<bool> {
+    if options.model {
+        let tables = match &options.binary_file {
+            Some(binary_file) => ModelTables::load_binary(binary_file)?,
+            None => ModelTables::load(options.output_dir(), options.model_order()?)?,
+        };
+        let no_lines: Vec<(usize, usize)> = tables
+            .ngrams
+            .iter()
+            .enumerate()
+            .map(|(i, table)| (i + 1, table.len()))
+            .collect();
+        return Ok(report_problems(options, &tables.validate(), &no_lines));
+    }
+
+    let config = options.config()?;
+    let mut problems = Vec::new();
+    let mut no_lines = Vec::new();
+
+    match WordListIterator::new(&config.dictionary) {
+        Ok(words) => problems.extend(words.filter_map(|word| word.err())),
+        Err(err) => problems.push(err),
+    }
+    for (i, path) in config.ngram_files.iter().enumerate() {
+        let n = i + 1;
+        if !path.is_file() {
+            problems.push(Error::MissingOrderFile {
+                n,
+                path: path.clone(),
+            });
+            continue;
+        }
+        match NGramIterator::with_format(path, n, config.input_format) {
+            Ok(ngrams) => {
+                let mut no = 0;
+                for ngram in ngrams {
+                    no += 1;
+                    if let Err(err) = ngram {
+                        problems.push(err);
+                    }
+                }
+                no_lines.push((n, no));
+            }
+            Err(err) => problems.push(err),
+        }
+    }
+    Ok(report_problems(options, &problems, &no_lines))
+}
+
+/// Print the problems validate found and the number of lines of each order, returns whether there were none
+fn report_problems(options: &Options, problems: &[Error], no_lines: &[(usize, usize)]) -> bool {
+    match options.format {
+        Format::Text => {
+            if options.verbosity == Verbosity::Verbose {
+                for (n, no) in no_lines {
+                    println!("{} lines with {}grams", no, n);
+                }
+            }
+            for problem in problems {
+                println!("{}", problem);
+            }
+            if options.verbosity > Verbosity::Quiet {
+                println!("{} problems found", problems.len());
+            }
+        }
+        Format::Json => {
+            let problems: Vec<String> = problems
+                .iter()
+                .map(|problem| json_string(&problem.to_string()))
+                .collect();
+            println!(
+                "{{\"valid\":{},\"problems\":[{}]}}",
+                problems.is_empty(),
+                problems.join(",")
+            );
+        }
+    }
+    problems.is_empty()
+}
+
+fn evaluate_model(options: &Options) -> Result<bool> {
+    let heldout_file = options
+        .heldout_file
+        .as_ref()
+        .ok_or_else(|| Error::InvalidConfig("No held-out text given, use --heldout".to_string()))?;
+    let model = match &options.binary_file {
+        Some(binary_file) => LanguageModel::map(binary_file)?,
+        None => LanguageModel::load(options.output_dir(), options.model_order()?)?,
+    };
+    if options.keystrokes {
+        let savings = keystroke_savings(&model, heldout_file, options.top.unwrap_or(3))?;
+        match options.format {
+            Format::Text => println!("{}", savings),
+            Format::Json => println!(
+                "{{\"bar_size\":{},\"words\":{},\"chars\":{},\"keystrokes\":{},\"keystroke_savings\":{},\"top1_accuracy\":{},\"top3_accuracy\":{},\"mrr\":{}}}",
+                savings.bar_size,
+                savings.no_words,
+                savings.no_chars,
+                savings.no_keystrokes,
+                json_number(savings.savings_rate()),
+                json_number(savings.top1_accuracy()),
+                json_number(savings.top3_accuracy()),
+                json_number(savings.mean_reciprocal_rank())
+            ),
+        }
+        return Ok(true);
+    }
+    let evaluation = evaluate(&model, heldout_file)?;
+    match options.format {
+        Format::Text => println!("{}", evaluation),
+        Format::Json => {
+            let hits: Vec<String> = evaluation
+                .hits
+                .iter()
+                .map(|hits| hits.to_string())
+                .collect();
+            let hit_rates: Vec<String> = (1..=evaluation.hits.len())
+                .map(|n| json_number(evaluation.hit_rate(n)))
+                .collect();
+            println!(
+                "{{\"sentences\":{},\"words\":{},\"oov\":{},\"oov_rate\":{},\"perplexity\":{},\"cross_entropy\":{},\"hits\":[{}],\"hit_rates\":[{}]}}",
+                evaluation.no_sentences,
+                evaluation.no_words,
+                evaluation.no_oov,
+                json_number(evaluation.oov_rate()),
+                json_number(evaluation.perplexity()),
+                json_number(evaluation.cross_entropy()),
+                hits.join(","),
+                hit_rates.join(",")
+            );
+        }
+    }
+    Ok(true)
+}
+
+fn export(options: &Options) -> Result<bool> {
+    if options.arpa_file.is_none()
+        && options.binary_file.is_none()
+        && options.heldout_file.is_none()
+    {
+        return Err(Error::InvalidConfig(
+            "No file to export to was given with --arpa or --binary".to_string(),
+        ));
+    }
+    let model = ModelTables::load(options.output_dir(), options.model_order()?)?;
+    if let Some(arpa_file) = &options.arpa_file {
+        model.write_arpa(arpa_file)?;
+        if options.verbosity == Verbosity::Verbose {
+            eprintln!("Wrote the model to {}", arpa_file.display());
+        }
+    }
+    if let Some(binary_file) = &options.binary_file {
+        model.write_binary(binary_file, options.quantization)?;
+        if options.verbosity == Verbosity::Verbose {
+            eprintln!("Wrote the model to {}", binary_file.display());
+        }
+    }
+    if let Some(heldout_file) = &options.heldout_file {
+        let quantization = options.quantization.ok_or_else(|| {
+            Error::InvalidConfig(
+                "No quantization to evaluate was given with --quantize".to_string(),
+            )
+        })?;
+        let report = perplexity_impact(&model, quantization, heldout_file)?;
+        match options.format {
+            Format::Text => println!("{}", report),
+            Format::Json => println!(
+                "{{\"quantization\":{},\"words\":{},\"perplexity\":{},\"quantized_perplexity\":{},\"size\":{},\"quantized_size\":{}}}",
+                json_string(&report.quantization.to_string()),
+                report.no_words,
+                json_number(report.perplexity),
+                json_number(report.quantized_perplexity),
+                report.size,
+                report.quantized_size
+            ),
+        }
+    }
+    Ok(true)
+}
+
+fn import(options: &Options) -> Result<bool> {
+    let arpa_file = options.arpa_file.as_ref().ok_or_else(|| {
+        Error::InvalidConfig("No ARPA file to import was given with --arpa".to_string())
+    })?;
+    let model = import_arpa(
+        arpa_file,
+        options.dictionary(),
+        options.max_words,
+        options.unk,
+    )?;
+    model.write(options.output_dir())?;
+    if options.verbosity > Verbosity::Quiet {
+        println!("{} words in the symbol table", model.symbols.len());
+        for (i, table) in model.ngrams.iter().enumerate() {
+            println!("{} {}grams", table.len(), i + 1);
+        }
+    }
+    Ok(true)
+}
+
 fn main() {
-    let max_no_words = 30_000;
-    let test_mode = false;
-    ngrams_to_language_model::generate(test_mode, max_no_words);
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+    let result = match options.command {
+        Command::Build => build(&options),
+        Command::Count => count(&options),
+        Command::Stats => stats(&options),
+        Command::Query => query(&options),
+        Command::Validate => validate(&options),
+        Command::Evaluate => evaluate_model(&options),
+        Command::Export => export(&options),
+        Command::Import => import(&options),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(true)
+        }
+    };
+    match result {
+        Ok(true) => {}
+        // The command ran, but found nothing or found problems
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
 }
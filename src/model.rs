@@ -0,0 +1,719 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::binary::MappedTables;
+use crate::error::{Error, Result};
+use crate::utilities::LinesIterator;
+use crate::{NGramEntry, SENTENCE_END, SENTENCE_START, UNKNOWN_WORD};
+
+/// An entry of a table of one order, either while it is generated or after it was loaded
+pub(crate) trait TableEntry {
+    fn label(&self) -> u32;
+    fn log_prob(&self) -> f32;
+    fn backoff(&self) -> f32;
+    fn suffix(&self) -> u32;
+    fn offset(&self) -> u32;
+    fn no_longer_ngrams(&self) -> u32;
+
+    /// Indices of the ngrams of the next higher order that have this ngram as their prefix
+    fn children(&self) -> Range<usize> {
+        self.offset() as usize..self.offset() as usize + self.no_longer_ngrams() as usize
+    }
+}
+
+macro_rules! impl_table_entry {
+    ($entry:ty) => {
+        impl TableEntry for $entry {
+            fn label(&self) -> u32 {
+                self.label
+            }
+            fn log_prob(&self) -> f32 {
+                self.log_prob
+            }
+            fn backoff(&self) -> f32 {
+                self.backoff
+            }
+            fn suffix(&self) -> u32 {
+                self.suffix
+            }
+            fn offset(&self) -> u32 {
+                self.offset
+            }
+            fn no_longer_ngrams(&self) -> u32 {
+                self.no_longer_ngrams
+            }
+        }
+    };
+}
+
+impl_table_entry!(NGramEntry);
+impl_table_entry!(NGramRecord);
+
+/// One line of a table written by `generate`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NGramRecord {
+    /// Symbol of the last word of the ngram
+    pub label: u32,
+    /// Log probability of the last word given the preceding words
+    pub log_prob: f32,
+    /// Index of the ngram of the next lower order made up of all but the first word
+    /// For unigrams and bigrams this is the symbol of the last word
+    pub suffix: u32,
+    /// Index of the first ngram of the next higher order that has this ngram as its prefix
+    pub offset: u32,
+    /// Number of ngrams of the next higher order that have this ngram as their prefix
+    pub no_longer_ngrams: u32,
+    /// Log of the weight the probabilities of the next lower order get multiplied with, if this ngram is used as a history
+    /// It is 0 if the model was generated without backoff weights
+    pub backoff: f32,
+}
+
+impl NGramRecord {
+    /// Indices of the ngrams of the next higher order that have this ngram as their prefix
+    pub fn children(&self) -> Range<usize> {
+        self.offset as usize..self.offset as usize + self.no_longer_ngrams as usize
+    }
+}
+
+/// The symbol table and the tables of all orders of a generated model
+#[derive(Clone, Debug, Default)]
+pub struct ModelTables {
+    /// The words of the vocabulary, their index is their symbol
+    pub symbols: Vec<String>,
+    /// The tables of all orders, the table at index i contains the ngrams of length i + 1
+    pub ngrams: Vec<Vec<NGramRecord>>,
+}
+
+impl ModelTables {
+    /// Load the symbol table `symt.txt` and the tables `<n>gms.txt` of the orders up to `max_ngram_len` from a directory
+    pub fn load<P: AsRef<Path>>(dir: P, max_ngram_len: usize) -> Result<Self> {
+        let dir = dir.as_ref();
+        if max_ngram_len == 0 {
+            return Err(Error::InvalidConfig(
+                "The ngrams need to have a length of at least 1".to_string(),
+            ));
+        }
+        let symbols = LinesIterator::new(dir.join("symt.txt"))?.collect::<Result<Vec<_>>>()?;
+
+        let mut ngrams = Vec::with_capacity(max_ngram_len);
+        for n in 1..=max_ngram_len {
+            let path = dir.join(format!("{}gms.txt", n));
+            if !path.is_file() {
+                return Err(Error::MissingOrderFile { n, path });
+            }
+            ngrams.push(read_table(&path, n, max_ngram_len)?);
+        }
+        Ok(Self { symbols, ngrams })
+    }
+
+    /// Length of the longest ngrams of a model in a directory, which has the tables `1gms.txt` up to `<n>gms.txt`
+    pub fn max_ngram_len_in<P: AsRef<Path>>(dir: P) -> Result<usize> {
+        let dir = dir.as_ref();
+        let max_ngram_len = (1..)
+            .take_while(|n| dir.join(format!("{}gms.txt", n)).is_file())
+            .count();
+        if max_ngram_len == 0 {
+            return Err(Error::MissingOrderFile {
+                n: 1,
+                path: dir.join("1gms.txt"),
+            });
+        }
+        Ok(max_ngram_len)
+    }
+
+    /// Write the symbol table and the tables to a directory in the same format as `generate`
+    /// The backoff weights are only written if the model has any
+    pub fn write<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(|err| Error::io(dir, err))?;
+
+        let fname_symt = dir.join("symt.txt");
+        let write_error = |err| Error::io(&fname_symt, err);
+        let mut f_write_symt = BufWriter::new(File::create(&fname_symt).map_err(write_error)?);
+        for symbol in &self.symbols {
+            writeln!(f_write_symt, "{}", symbol).map_err(write_error)?;
+        }
+        f_write_symt.flush().map_err(write_error)?;
+
+        let with_backoff = self.has_backoff();
+        for (i, table) in self.ngrams.iter().enumerate() {
+            write_table(
+                &dir.join(format!("{}gms.txt", i + 1)),
+                table,
+                i + 1,
+                self.max_ngram_len(),
+                with_backoff,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Length of the longest ngrams of the model
+    pub fn max_ngram_len(&self) -> usize {
+        self.ngrams.len()
+    }
+
+    /// Whether any of the ngrams has a backoff weight
+    pub fn has_backoff(&self) -> bool {
+        self.ngrams
+            .iter()
+            .flatten()
+            .any(|record| record.backoff != 0.0)
+    }
+
+    /// Index of the ngram made up of the symbols in the table of its order
+    /// The children of each prefix are searched one after the other, starting with the unigram of the first symbol
+    pub fn find(&self, symbols: &[u32]) -> Option<usize> {
+        if symbols.is_empty() || symbols.len() > self.max_ngram_len() {
+            return None;
+        }
+        let first = symbols[0] as usize;
+        if first >= self.ngrams[0].len() {
+            return None;
+        }
+        let mut idx = first;
+        for (n, &label) in symbols.iter().enumerate().skip(1) {
+            let children = self.ngrams[n - 1][idx].children();
+            idx = children
+                .clone()
+                .find(|&child| self.ngrams[n].get(child).map(|r| r.label) == Some(label))?;
+        }
+        Some(idx)
+    }
+
+    /// Record of the ngram made up of the symbols
+    pub fn get(&self, symbols: &[u32]) -> Option<&NGramRecord> {
+        let idx = self.find(symbols)?;
+        self.ngrams[symbols.len() - 1].get(idx)
+    }
+}
+
+/// Read the table of the ngrams of length n of a model with ngrams up to a length of `max_ngram_len`
+/// The columns are the ones `generate` writes: label (not for unigrams), log probability, suffix (only for n >= 3),
+/// offset and number of longer ngrams (not for the highest order) and optionally the backoff weight (not for the highest order)
+fn read_table(path: &Path, n: usize, max_ngram_len: usize) -> Result<Vec<NGramRecord>> {
+    let has_label = n > 1;
+    let has_suffix = n >= 3;
+    let has_children = n < max_ngram_len;
+    let no_columns = has_label as usize + 1 + has_suffix as usize + 2 * has_children as usize;
+
+    let mut lines = LinesIterator::new(path)?;
+    let mut records = Vec::new();
+    while let Some(line) = lines.next() {
+        let line = line?;
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let has_backoff = has_children && columns.len() == no_columns + 1;
+        if columns.len() != no_columns && !has_backoff {
+            return Err(lines.parse_error(
+                &line,
+                format!("Expected {} columns for the {}grams", no_columns, n),
+            ));
+        }
+        let parse_u32 = |value: &str| {
+            value.parse::<u32>().map_err(|err| {
+                lines.parse_error(&line, format!("Invalid number '{}' ({})", value, err))
+            })
+        };
+
+        let mut column = 0;
+        let label = if has_label {
+            column += 1;
+            parse_u32(columns[0])?
+        } else {
+            records.len() as u32
+        };
+        let log_prob = columns[column].parse::<f32>().map_err(|err| {
+            lines.parse_error(
+                &line,
+                format!("Invalid log probability '{}' ({})", columns[column], err),
+            )
+        })?;
+        column += 1;
+        let suffix = if has_suffix {
+            column += 1;
+            parse_u32(columns[column - 1])?
+        } else {
+            label
+        };
+        let (offset, no_longer_ngrams) = if has_children {
+            (parse_u32(columns[column])?, parse_u32(columns[column + 1])?)
+        } else {
+            (0, 0)
+        };
+        let backoff = if has_backoff {
+            columns[column + 2].parse::<f32>().map_err(|err| {
+                lines.parse_error(
+                    &line,
+                    format!("Invalid backoff weight '{}' ({})", columns[column + 2], err),
+                )
+            })?
+        } else {
+            0.0
+        };
+        records.push(NGramRecord {
+            label,
+            log_prob,
+            suffix,
+            offset,
+            no_longer_ngrams,
+            backoff,
+        });
+    }
+    Ok(records)
+}
+
+/// Writes the table of the ngrams of length n to a file
+/// The columns of each line are:
+/// - the symbol of the last word (omitted for unigrams, because it is the same as the line number)
+/// - the log probability
+/// - the index of the suffix in the table of the next lower order (only for n >= 3, because the suffix of a bigram is its label)
+/// - the offset and the number of the longer ngrams with this prefix (omitted for the highest order)
+/// - the log backoff weight (only if the smoothing calculates them, omitted for the highest order)
+pub(crate) fn write_table<E: TableEntry>(
+    filename: &Path,
+    entries: &[E],
+    n: usize,
+    max_ngram_len: usize,
+    with_backoff: bool,
+) -> Result<()> {
+    let mut writer = TableWriter::create(filename, n, max_ngram_len, with_backoff)?;
+    for entry in entries {
+        writer.write(entry)?;
+    }
+    writer.finish()
+}
+
+/// Writes the lines of a table one after the other, in the format described at `write_table`
+pub(crate) struct TableWriter<'a> {
+    filename: &'a Path,
+    f_write_ngrams: BufWriter<File>,
+    n: usize,
+    max_ngram_len: usize,
+    with_backoff: bool,
+}
+
+impl<'a> TableWriter<'a> {
+    pub(crate) fn create(
+        filename: &'a Path,
+        n: usize,
+        max_ngram_len: usize,
+        with_backoff: bool,
+    ) -> Result<Self> {
+        let f_write_ngrams =
+            BufWriter::new(File::create(filename).map_err(|err| Error::io(filename, err))?);
+        Ok(Self {
+            filename,
+            f_write_ngrams,
+            n,
+            max_ngram_len,
+            with_backoff,
+        })
+    }
+
+    pub(crate) fn write<E: TableEntry>(&mut self, entry: &E) -> Result<()> {
+        let mut line = if self.n == 1 {
+            format!("{}", entry.log_prob())
+        } else {
+            format!("{} {}", entry.label(), entry.log_prob())
+        };
+        if self.n >= 3 {
+            line.push_str(&format!(" {}", entry.suffix()));
+        }
+        if self.n < self.max_ngram_len {
+            line.push_str(&format!(" {} {}", entry.offset(), entry.no_longer_ngrams()));
+            if self.with_backoff {
+                line.push_str(&format!(" {}", entry.backoff()));
+            }
+        }
+        writeln!(self.f_write_ngrams, "{}", line).map_err(|err| Error::io(self.filename, err))
+    }
+
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.f_write_ngrams
+            .flush()
+            .map_err(|err| Error::io(self.filename, err))
+    }
+}
+
+/// The records of a `LanguageModel`, either loaded into memory or read on demand from a memory mapped binary file
+enum Storage {
+    Loaded(ModelTables),
+    Mapped(MappedTables),
+}
+
+impl Storage {
+    fn symbols(&self) -> &[String] {
+        match self {
+            Storage::Loaded(tables) => &tables.symbols,
+            Storage::Mapped(mapped) => &mapped.symbols,
+        }
+    }
+
+    fn max_ngram_len(&self) -> usize {
+        match self {
+            Storage::Loaded(tables) => tables.max_ngram_len(),
+            Storage::Mapped(mapped) => mapped.max_ngram_len(),
+        }
+    }
+
+    /// Number of records of the table n (starting at 0)
+    fn len(&self, n: usize) -> usize {
+        match self {
+            Storage::Loaded(tables) => tables.ngrams[n].len(),
+            Storage::Mapped(mapped) => mapped.len(n),
+        }
+    }
+
+    /// The record at index idx of the table n (starting at 0)
+    fn record(&self, n: usize, idx: usize) -> NGramRecord {
+        match self {
+            Storage::Loaded(tables) => tables.ngrams[n][idx],
+            Storage::Mapped(mapped) => mapped.record(n, idx),
+        }
+    }
+}
+
+/// A generated model that can be queried like a backoff language model
+///
+/// If an ngram is not in the tables, its log probability is the one of the ngram without its first word
+/// plus the log backoff weight of its history. Models that were generated without backoff weights use a weight of 1.
+pub struct LanguageModel {
+    storage: Storage,
+    symbol_ids: HashMap<String, u32>,
+    /// Symbol of `<unk>`, which all words that are not in the vocabulary become
+    unknown: Option<u32>,
+    /// Whether the labels within every range of children of the order are sorted, so they can be binary searched
+    sorted_children: Vec<bool>,
+    /// Symbols of the unigrams that can be predicted, the most probable first and the ones with the same probability by their word
+    pub(crate) unigrams_by_prob: Vec<u32>,
+    /// Symbols of the unigrams that can be predicted, sorted by their word, so all words with the same beginning are next to each other
+    pub(crate) unigrams_by_word: Vec<u32>,
+}
+
+impl LanguageModel {
+    /// Load the symbol table and the tables of the orders up to `max_ngram_len` from a directory
+    pub fn load<P: AsRef<Path>>(dir: P, max_ngram_len: usize) -> Result<Self> {
+        Self::from_tables(ModelTables::load(dir, max_ngram_len)?)
+    }
+
+    /// Memory map a model in the binary format
+    /// Only the symbol table is read into memory, the ngrams are read from the file when they are queried
+    /// after checking once that their labels and children are consistent
+    pub fn map<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mapped = MappedTables::open(path)?;
+        let sorted_children = mapped.sorted_children.clone();
+        Ok(Self::with_storage(Storage::Mapped(mapped), sorted_children))
+    }
+
+    /// Create a model from tables that were already loaded
+    /// Fails if there isn't a unigram for every symbol, a label is not a symbol or the children of an ngram
+    /// are out of bounds of the table of the next higher order
+    pub fn from_tables(tables: ModelTables) -> Result<Self> {
+        let no_symbols = tables.symbols.len();
+        let no_unigrams = match tables.ngrams.first() {
+            Some(unigrams) => unigrams.len(),
+            None => {
+                return Err(Error::InconsistentModel(
+                    "The model has no tables".to_string(),
+                ))
+            }
+        };
+        if no_unigrams != no_symbols {
+            return Err(Error::InconsistentModel(format!(
+                "There are {} unigrams, but {} symbols",
+                no_unigrams, no_symbols
+            )));
+        }
+        // The unigrams are never searched, because their index is their symbol
+        let mut sorted_children = vec![true];
+        for n in 1..tables.max_ngram_len() {
+            let no_children = tables.ngrams[n].len();
+            if let Some(idx) = tables.ngrams[n]
+                .iter()
+                .position(|record| record.label as usize >= no_symbols)
+            {
+                return Err(Error::InconsistentModel(format!(
+                    "The {}gram {} has the label {}, but there are only {} symbols",
+                    n + 1,
+                    idx,
+                    tables.ngrams[n][idx].label,
+                    no_symbols
+                )));
+            }
+            let mut sorted = true;
+            for (idx, record) in tables.ngrams[n - 1].iter().enumerate() {
+                let children = record.children();
+                if children.end > no_children {
+                    return Err(Error::InconsistentModel(format!(
+                        "The {}gram {} has the children {:?}, but there are only {} {}grams",
+                        n,
+                        idx,
+                        children,
+                        no_children,
+                        n + 1
+                    )));
+                }
+                let labels = &tables.ngrams[n][children];
+                if labels.windows(2).any(|pair| pair[0].label >= pair[1].label) {
+                    sorted = false;
+                }
+            }
+            sorted_children.push(sorted);
+        }
+        Ok(Self::with_storage(Storage::Loaded(tables), sorted_children))
+    }
+
+    fn with_storage(storage: Storage, sorted_children: Vec<bool>) -> Self {
+        let symbols = storage.symbols();
+        let symbol_ids: HashMap<String, u32> = symbols
+            .iter()
+            .enumerate()
+            .map(|(id, symbol)| (symbol.clone(), id as u32))
+            .collect();
+        let unknown = symbol_ids.get(UNKNOWN_WORD).copied();
+        let unigram_log_probs: Vec<f32> = (0..storage.len(0))
+            .map(|idx| storage.record(0, idx).log_prob)
+            .collect();
+        let predictable = |&symbol: &u32| is_predictable(&symbols[symbol as usize]);
+        let mut unigrams_by_prob: Vec<u32> = (0..unigram_log_probs.len() as u32)
+            .filter(predictable)
+            .collect();
+        // Ties are sorted by their word, like the predictions
+        unigrams_by_prob.sort_by(|&a, &b| {
+            unigram_log_probs[b as usize]
+                .total_cmp(&unigram_log_probs[a as usize])
+                .then_with(|| symbols[a as usize].cmp(&symbols[b as usize]))
+        });
+        let mut unigrams_by_word: Vec<u32> =
+            (0..symbols.len() as u32).filter(predictable).collect();
+        unigrams_by_word.sort_by(|&a, &b| symbols[a as usize].cmp(&symbols[b as usize]));
+        Self {
+            storage,
+            symbol_ids,
+            unknown,
+            sorted_children,
+            unigrams_by_prob,
+            unigrams_by_word,
+        }
+    }
+
+    /// The tables of the model, None if it is memory mapped
+    pub fn tables(&self) -> Option<&ModelTables> {
+        match &self.storage {
+            Storage::Loaded(tables) => Some(tables),
+            Storage::Mapped(_) => None,
+        }
+    }
+
+    /// Length of the longest ngrams of the model
+    pub fn max_ngram_len(&self) -> usize {
+        self.storage.max_ngram_len()
+    }
+
+    /// Number of ngrams of length n in the model
+    pub fn no_ngrams(&self, n: usize) -> usize {
+        if n == 0 || n > self.max_ngram_len() {
+            return 0;
+        }
+        self.storage.len(n - 1)
+    }
+
+    /// The words of the vocabulary, their index is their symbol
+    pub fn symbol_table(&self) -> &[String] {
+        self.storage.symbols()
+    }
+
+    /// Symbol of a word if it is in the vocabulary
+    /// If the model has `<unk>`, all other words are `<unk>`
+    pub fn symbol(&self, word: &str) -> Option<u32> {
+        self.symbol_ids.get(word).copied().or(self.unknown)
+    }
+
+    /// Whether the word itself is in the vocabulary, so it doesn't become `<unk>`
+    pub(crate) fn contains(&self, word: &str) -> bool {
+        self.symbol_ids.contains_key(word)
+    }
+
+    /// Whether the word of the symbol can be predicted, which `<unk>`, `<s>` and `</s>` can't
+    pub(crate) fn is_predictable(&self, symbol: u32) -> bool {
+        self.word(symbol).is_some_and(is_predictable)
+    }
+
+    /// Word of a symbol
+    pub fn word(&self, symbol: u32) -> Option<&str> {
+        self.storage
+            .symbols()
+            .get(symbol as usize)
+            .map(|word| word.as_str())
+    }
+
+    /// Indices of the children of the ngram at index idx of the table n (starting at 0) in the table n + 1
+    /// Children beyond the end of the table are left out
+    pub(crate) fn children(&self, n: usize, idx: usize) -> Range<usize> {
+        let children = self.storage.record(n, idx).children();
+        let len = self.storage.len(n + 1);
+        children.start.min(len)..children.end.min(len)
+    }
+
+    /// Records of the children of the ngram at index idx of the table n (starting at 0)
+    pub(crate) fn child_records(
+        &self,
+        n: usize,
+        idx: usize,
+    ) -> impl Iterator<Item = NGramRecord> + '_ {
+        self.children(n, idx)
+            .map(move |child| self.storage.record(n + 1, child))
+    }
+
+    /// Index of the child of the ngram at index idx of the table n (starting at 0) that has the label
+    fn child(&self, n: usize, idx: usize, label: u32) -> Option<usize> {
+        let mut children = self.children(n, idx);
+        if self.sorted_children[n + 1] {
+            while children.start < children.end {
+                let middle = children.start + (children.end - children.start) / 2;
+                match self.storage.record(n + 1, middle).label.cmp(&label) {
+                    Ordering::Less => children.start = middle + 1,
+                    Ordering::Equal => return Some(middle),
+                    Ordering::Greater => children.end = middle,
+                }
+            }
+            None
+        } else {
+            children.find(|&child| self.storage.record(n + 1, child).label == label)
+        }
+    }
+
+    /// Index of the ngram made up of the symbols in the table of its order
+    pub fn find(&self, symbols: &[u32]) -> Option<usize> {
+        if symbols.is_empty() || symbols.len() > self.max_ngram_len() {
+            return None;
+        }
+        let mut idx = symbols[0] as usize;
+        if idx >= self.storage.len(0) {
+            return None;
+        }
+        for (n, &label) in symbols.iter().enumerate().skip(1) {
+            idx = self.child(n - 1, idx, label)?;
+        }
+        Some(idx)
+    }
+
+    /// Record of the ngram made up of the symbols
+    pub fn get(&self, symbols: &[u32]) -> Option<NGramRecord> {
+        let idx = self.find(symbols)?;
+        Some(self.storage.record(symbols.len() - 1, idx))
+    }
+
+    /// Log probability of the last symbol given the preceding ones and the length of the longest ngram that was found
+    /// Only the last `max_ngram_len` symbols are used
+    pub(crate) fn log_prob_symbols(&self, symbols: &[u32]) -> (f32, usize) {
+        let symbols = &symbols[symbols.len().saturating_sub(self.max_ngram_len())..];
+        let mut backoff = 0.0;
+        for start in 0..symbols.len() {
+            if let Some(record) = self.get(&symbols[start..]) {
+                return (backoff + record.log_prob, symbols.len() - start);
+            }
+            // The ngram is not in the model, so the probability of the shorter ngram gets multiplied by the backoff weight of the history
+            if let Some(history) = self.get(&symbols[start..symbols.len() - 1]) {
+                backoff += history.backoff;
+            }
+        }
+        (f32::NEG_INFINITY, 0)
+    }
+
+    /// Translate the words to symbols, words that are not in the vocabulary become None
+    pub(crate) fn symbols(&self, words: &[&str]) -> Vec<Option<u32>> {
+        words.iter().map(|word| self.symbol(word)).collect()
+    }
+
+    /// The symbols of the history up to the last word that is not in the vocabulary
+    pub(crate) fn known_history(history: &[Option<u32>]) -> Vec<u32> {
+        let start = history
+            .iter()
+            .rposition(|symbol| symbol.is_none())
+            .map_or(0, |i| i + 1);
+        history[start..].iter().flatten().copied().collect()
+    }
+
+    /// Natural log of the probability of the last word given the preceding words
+    /// Returns None if the last word is not in the vocabulary. Words of the history before a word that is not in the vocabulary are ignored.
+    pub fn log_prob(&self, ngram: &[&str]) -> Option<f32> {
+        let (word, history) = ngram.split_last()?;
+        let mut symbols = Self::known_history(&self.symbols(history));
+        symbols.push(self.symbol(word)?);
+        Some(self.log_prob_symbols(&symbols).0)
+    }
+
+    /// Sum of the natural log probabilities of all words of the sentence given the words before them
+    /// Words that are not in the vocabulary are skipped
+    pub fn score_sentence(&self, words: &[&str]) -> f32 {
+        let symbols = self.symbols(words);
+        let mut score = 0.0;
+        for (i, symbol) in symbols.iter().enumerate() {
+            if let Some(symbol) = symbol {
+                let start = (i + 1).saturating_sub(self.max_ngram_len());
+                let mut ngram = Self::known_history(&symbols[start..i]);
+                ngram.push(*symbol);
+                score += self.log_prob_symbols(&ngram).0;
+            }
+        }
+        score
+    }
+
+    /// Perplexity of the words, words that are not in the vocabulary are skipped
+    pub fn perplexity(&self, words: &[&str]) -> f32 {
+        let no_known = words
+            .iter()
+            .filter(|word| self.symbol(word).is_some())
+            .count();
+        if no_known == 0 {
+            return f32::INFINITY;
+        }
+        (-self.score_sentence(words) / no_known as f32).exp()
+    }
+
+    /// The k most probable next words after the history together with their natural log probability, the most probable first
+    pub fn next_word_candidates(&self, history: &[&str], k: usize) -> Vec<(&str, f32)> {
+        let history = Self::known_history(&self.symbols(history));
+        let history = &history[history.len().saturating_sub(self.max_ngram_len() - 1)..];
+
+        // Every word that was seen after one of the suffixes of the history is a candidate.
+        // All other words have the same backoff weights, so the most probable unigrams are the only other candidates.
+        let mut candidates: Vec<u32> = self.unigrams_by_prob.iter().take(k).copied().collect();
+        for start in 0..history.len() {
+            if let Some(idx) = self.find(&history[start..]) {
+                let n = history.len() - start;
+                candidates.extend(
+                    self.child_records(n - 1, idx)
+                        .map(|record| record.label)
+                        .filter(|&label| self.is_predictable(label)),
+                );
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut ngram = history.to_vec();
+        ngram.push(0);
+        let mut scored: Vec<(&str, f32)> = candidates
+            .into_iter()
+            .filter_map(|symbol| {
+                *ngram.last_mut()? = symbol;
+                Some((self.word(symbol)?, self.log_prob_symbols(&ngram).0))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        scored.truncate(k);
+        scored
+    }
+}
+
+/// Whether a word can be suggested as the next word
+/// `<unk>` stands for many words, no sentence starts in the middle of another one and the end of a sentence can't be typed
+fn is_predictable(word: &str) -> bool {
+    word != UNKNOWN_WORD && word != SENTENCE_START && word != SENTENCE_END
+}
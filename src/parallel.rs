@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::error::{Error, Result};
+use crate::utilities::{split_ngram_line, LinesIterator};
+use crate::{NGramCounts, TranslatedNGram};
+
+/// Number of lines a worker parses and translates at once
+const CHUNK_LINES: usize = 1 << 14;
+/// Number of chunks per worker that may be read ahead of the chunk whose ngrams are returned
+const CHUNKS_AHEAD: usize = 2;
+
+/// The symbols of a parsed ngram if all of its words are in the symbol table and its count
+type ParsedNGram = (Option<Vec<u32>>, u32);
+
+/// Index of the part of the file and number of the line in it
+type Position = (u32, usize);
+
+/// Lines of the file that are parsed together
+struct Chunk {
+    /// Number of the chunk in the order of the file
+    index: usize,
+    lines: Vec<String>,
+    positions: Vec<Position>,
+    /// Error of the line after the lines of the chunk, which ended the reading of the file
+    read_error: Option<(Error, Position)>,
+}
+
+/// The parsed ngrams of a chunk and the positions of their lines
+struct ParsedChunk {
+    index: usize,
+    ngrams: Vec<Result<ParsedNGram>>,
+    positions: Vec<Position>,
+}
+
+/// Parses the ngrams of a file and translates their words to symbols on several threads
+///
+/// A reader thread reads and decompresses the lines in chunks, while a pool of workers parses and translates them.
+/// The chunks are put back together in the order of the file, so the ngrams and the errors are the same
+/// as when the file is read on one thread. At most `CHUNKS_AHEAD` chunks per worker are read ahead of the chunk
+/// whose ngrams are returned, and the threads stop on their own once the translator is dropped.
+pub(crate) struct ParallelTranslator<'a> {
+    /// Chunks that were parsed before the ones that come before them in the file
+    pending: HashMap<usize, ParsedChunk>,
+    /// Index of the chunk whose ngrams come next
+    next_index: usize,
+    parsed: Receiver<ParsedChunk>,
+    /// Every permit allows the reader to read another chunk
+    permits: Sender<()>,
+    threads: Vec<JoinHandle<()>>,
+    /// The rest of the current chunk and the positions of its lines
+    batch: std::iter::Zip<std::vec::IntoIter<Result<ParsedNGram>>, std::vec::IntoIter<Position>>,
+    ngrams_total: &'a mut NGramCounts,
+}
+
+impl<'a> ParallelTranslator<'a> {
+    /// All ngrams that are read are added to the total
+    pub(crate) fn new<P: AsRef<Path>>(
+        filename: P,
+        n: usize,
+        (sybt, unknown): (&Arc<HashMap<String, u32>>, Option<u32>),
+        threads: usize,
+        ngrams_total: &'a mut NGramCounts,
+    ) -> Result<Self> {
+        let lines = LinesIterator::new(filename)?;
+        let parts: Arc<Vec<PathBuf>> = Arc::new(lines.parts().to_vec());
+        let (chunk_sender, chunks) = mpsc::sync_channel(threads);
+        let chunks = Arc::new(Mutex::new(chunks));
+        let (parsed_sender, parsed) = mpsc::channel();
+        let (permits, permit_receiver) = mpsc::channel();
+        for _ in 0..threads * CHUNKS_AHEAD {
+            let _ = permits.send(());
+        }
+
+        let mut handles = Vec::with_capacity(threads + 1);
+        handles.push(thread::spawn(move || {
+            read_chunks(lines, chunk_sender, permit_receiver)
+        }));
+        for _ in 0..threads {
+            let (chunks, parsed_sender) = (Arc::clone(&chunks), parsed_sender.clone());
+            let (sybt, parts) = (Arc::clone(sybt), Arc::clone(&parts));
+            handles.push(thread::spawn(move || loop {
+                // The lock is only held while waiting for the next chunk
+                let chunk = match chunks.lock() {
+                    Ok(chunks) => chunks.recv(),
+                    Err(_) => return,
+                };
+                let Ok(chunk) = chunk else {
+                    return;
+                };
+                if parsed_sender
+                    .send(parse_chunk(chunk, n, &sybt, unknown, &parts))
+                    .is_err()
+                {
+                    return;
+                }
+            }));
+        }
+
+        Ok(Self {
+            pending: HashMap::new(),
+            next_index: 0,
+            parsed,
+            permits,
+            threads: handles,
+            batch: Vec::new().into_iter().zip(Vec::new()),
+            ngrams_total,
+        })
+    }
+
+    /// The next parsed chunk in the order of the file, None once all of them were returned
+    fn next_chunk(&mut self) -> Option<ParsedChunk> {
+        loop {
+            if let Some(chunk) = self.pending.remove(&self.next_index) {
+                self.next_index += 1;
+                // The reader stops on its own if it is already done
+                let _ = self.permits.send(());
+                return Some(chunk);
+            }
+            match self.parsed.recv() {
+                Ok(chunk) => {
+                    self.pending.insert(chunk.index, chunk);
+                }
+                Err(_) => {
+                    // All workers are done, a chunk can only be missing if one of them panicked
+                    for handle in self.threads.drain(..) {
+                        if let Err(panic) = handle.join() {
+                            std::panic::resume_unwind(panic);
+                        }
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Read the lines of the file in chunks, one for every permit, until the file or the permits end
+/// A line that can't be read ends the last chunk with its error.
+fn read_chunks(mut lines: LinesIterator, chunks: SyncSender<Chunk>, permits: Receiver<()>) {
+    for index in 0.. {
+        if permits.recv().is_err() {
+            return;
+        }
+        let mut chunk = Chunk {
+            index,
+            lines: Vec::with_capacity(CHUNK_LINES),
+            positions: Vec::with_capacity(CHUNK_LINES),
+            read_error: None,
+        };
+        let mut finished = false;
+        while chunk.lines.len() < CHUNK_LINES {
+            match lines.next() {
+                Some(Ok(line)) => chunk.lines.push(line),
+                Some(Err(err)) => {
+                    let position = (lines.part() as u32, lines.line_number());
+                    chunk.read_error = Some((err, position));
+                    finished = true;
+                    break;
+                }
+                None => {
+                    finished = true;
+                    break;
+                }
+            }
+            chunk
+                .positions
+                .push((lines.part() as u32, lines.line_number()));
+        }
+        let is_empty = chunk.lines.is_empty() && chunk.read_error.is_none();
+        if !is_empty && chunks.send(chunk).is_err() || finished {
+            return;
+        }
+    }
+}
+
+/// Parse and translate the lines of a chunk
+fn parse_chunk(
+    chunk: Chunk,
+    n: usize,
+    sybt: &HashMap<String, u32>,
+    unknown: Option<u32>,
+    parts: &[PathBuf],
+) -> ParsedChunk {
+    let mut ngrams: Vec<Result<ParsedNGram>> = chunk
+        .lines
+        .into_iter()
+        .zip(&chunk.positions)
+        .map(|(line, &(part, line_number))| {
+            translate_line(&line, n, sybt, unknown).map_err(|reason| Error::Parse {
+                path: parts[part as usize].clone(),
+                line_number,
+                line,
+                reason,
+            })
+        })
+        .collect();
+    let mut positions = chunk.positions;
+    if let Some((err, position)) = chunk.read_error {
+        ngrams.push(Err(err));
+        positions.push(position);
+    }
+    ParsedChunk {
+        index: chunk.index,
+        ngrams,
+        positions,
+    }
+}
+
+/// Parse a line and translate the words of its ngram to their symbols
+fn translate_line(
+    line: &str,
+    n: usize,
+    sybt: &HashMap<String, u32>,
+    unknown: Option<u32>,
+) -> std::result::Result<ParsedNGram, String> {
+    let (words, count) = split_ngram_line(line, n)?;
+    let symbols = words
+        .iter()
+        .map(|&word| sybt.get(word).copied().or(unknown))
+        .collect::<Option<Vec<u32>>>();
+    Ok((symbols, count))
+}
+
+impl Iterator for ParallelTranslator<'_> {
+    type Item = Result<TranslatedNGram>;
+
+    /// The next ngram that only consists of words from the symbol table
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (parsed, (part, line_number)) = match self.batch.next() {
+                Some(parsed) => parsed,
+                None => {
+                    let chunk = self.next_chunk()?;
+                    self.batch = chunk.ngrams.into_iter().zip(chunk.positions);
+                    continue;
+                }
+            };
+            let (symbols, count) = match parsed {
+                Ok(parsed) => parsed,
+                Err(err) => return Some(Err(err)),
+            };
+            self.ngrams_total.ngrams += 1;
+            self.ngrams_total.cumulative_count += count as u64;
+            if let Some(symbols) = symbols {
+                return Some(Ok(TranslatedNGram {
+                    symbols,
+                    count,
+                    part,
+                    line_number,
+                }));
+            }
+        }
+    }
+}
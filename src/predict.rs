@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+
+use crate::model::LanguageModel;
+
+/// A word suggested by `LanguageModel::predict`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Prediction<'a> {
+    pub word: &'a str,
+    /// Natural log of the probability of the word given the whole history
+    pub log_prob: f32,
+    /// Length of the ngram the word was found in, 1 if it was only found as a unigram
+    pub order: usize,
+}
+
+impl LanguageModel {
+    /// The k best completions of the typed prefix after the history
+    ///
+    /// The words seen after the longest known part of the history come first, followed by the ones seen
+    /// after the shorter parts of the history and finally the unigrams. Within each of them the words are ranked by their probability.
+    /// Only words starting with the typed prefix are suggested, an empty prefix suggests the next word.
+    pub fn predict(&self, history: &[&str], typed_prefix: &str, k: usize) -> Vec<Prediction<'_>> {
+        let history = Self::known_history(&self.symbols(history));
+        let history = &history[history.len().saturating_sub(self.max_ngram_len() - 1)..];
+        let mut predictions: Vec<Prediction> = Vec::with_capacity(k);
+        let mut predicted = HashSet::new();
+
+        let mut ngram = history.to_vec();
+        ngram.push(0);
+        let mut score = |symbol: u32| -> f32 {
+            if let Some(last) = ngram.last_mut() {
+                *last = symbol;
+            }
+            self.log_prob_symbols(&ngram).0
+        };
+
+        // Go through the children of the history, starting with the longest one
+        for start in 0..history.len() {
+            if predictions.len() >= k {
+                return predictions;
+            }
+            let n = history.len() - start;
+            let idx = match self.find(&history[start..]) {
+                Some(idx) => idx,
+                None => continue,
+            };
+            let mut tier: Vec<Prediction> = self
+                .child_records(n - 1, idx)
+                .filter(|record| {
+                    !predicted.contains(&record.label) && self.is_predictable(record.label)
+                })
+                .filter_map(|record| {
+                    let word = self.word(record.label)?;
+                    word.starts_with(typed_prefix).then(|| Prediction {
+                        word,
+                        log_prob: score(record.label),
+                        order: n + 1,
+                    })
+                })
+                .collect();
+            add_best(&mut predictions, &mut tier, k);
+            predicted.extend(predictions.iter().filter_map(|p| self.symbol(p.word)));
+        }
+
+        // Fill up the rest with the most probable unigrams that start with the typed prefix
+        // None of them follows any part of the history, so they all get the same backoff weights and their unigram probabilities decide
+        if predictions.len() < k {
+            // Without a prefix the words come ordered by their probability, so the missing ones are enough
+            let missing = if typed_prefix.is_empty() {
+                k - predictions.len()
+            } else {
+                usize::MAX
+            };
+            let mut tier: Vec<Prediction> = self
+                .words_with_prefix(typed_prefix)
+                .filter(|symbol| !predicted.contains(symbol))
+                .take(missing)
+                .filter_map(|symbol| {
+                    Some(Prediction {
+                        word: self.word(symbol)?,
+                        log_prob: score(symbol),
+                        order: 1,
+                    })
+                })
+                .collect();
+            add_best(&mut predictions, &mut tier, k);
+        }
+        predictions
+    }
+
+    /// Symbols of all words that start with the prefix
+    /// Without a prefix these are all words, the most probable first and words with the same probability sorted by
+    /// their word, so the callers can stop after the first ones they need
+    fn words_with_prefix<'a>(&'a self, prefix: &'a str) -> Box<dyn Iterator<Item = u32> + 'a> {
+        if prefix.is_empty() {
+            return Box::new(self.unigrams_by_prob.iter().copied());
+        }
+        let symbols = self.symbol_table();
+        let start = self
+            .unigrams_by_word
+            .partition_point(|&symbol| symbols[symbol as usize].as_str() < prefix);
+        Box::new(
+            self.unigrams_by_word[start..]
+                .iter()
+                .copied()
+                .take_while(move |&symbol| symbols[symbol as usize].starts_with(prefix)),
+        )
+    }
+}
+
+/// Append the best predictions of the tier until there are k predictions
+fn add_best<'a>(predictions: &mut Vec<Prediction<'a>>, tier: &mut [Prediction<'a>], k: usize) {
+    tier.sort_by(|a, b| {
+        b.log_prob
+            .total_cmp(&a.log_prob)
+            .then_with(|| a.word.cmp(b.word))
+    });
+    let missing = k.saturating_sub(predictions.len());
+    predictions.extend(tier.iter().take(missing).copied());
+}
@@ -0,0 +1,405 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::model::TableEntry;
+use crate::smoothing::Smoothing;
+use crate::{NGramCounts, NGramTable, TranslatedNGram};
+
+/// Which ngrams of length 2 or more are removed from the model
+///
+/// The lists of the cutoffs and of the maximum numbers of continuations start with the bigrams.
+/// Their last value also applies to all longer ngrams, an empty list doesn't prune anything.
+/// Longer ngrams whose prefix or suffix was pruned are skipped as well (see `MissingLowerOrder`).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pruning {
+    /// Minimum count of the ngrams that are kept
+    pub min_counts: Vec<u32>,
+    /// Maximum number of ngrams with the same prefix, the most frequent ones are kept
+    /// Ngrams with the same count are kept in the order they were read in.
+    pub max_continuations: Vec<usize>,
+    /// Number of ngrams of length 2 or more the model is pruned to by relative entropy (Stolcke)
+    /// The ngrams whose removal changes the model the least are removed first, as long as they are not
+    /// the prefix or the suffix of a longer ngram.
+    pub max_ngrams: Option<usize>,
+}
+
+impl Pruning {
+    /// Whether any ngrams are pruned
+    pub fn is_enabled(&self) -> bool {
+        !self.min_counts.is_empty()
+            || !self.max_continuations.is_empty()
+            || self.max_ngrams.is_some()
+    }
+
+    /// Minimum count of the ngrams of length n
+    pub fn min_count(&self, n: usize) -> u32 {
+        per_order(&self.min_counts, n).unwrap_or(0)
+    }
+
+    /// Maximum number of ngrams of length n with the same prefix
+    pub fn max_continuations(&self, n: usize) -> Option<usize> {
+        per_order(&self.max_continuations, n)
+    }
+
+    /// Drop the ngrams of length n below the cutoff and the least frequent ones of each prefix, and add them to `pruned`
+    /// The ngrams with the same prefix need to be next to each other.
+    pub(crate) fn apply<'a, I>(
+        &self,
+        ngrams: I,
+        n: usize,
+        pruned: &'a mut NGramCounts,
+    ) -> PrunedNGrams<'a, I>
+    where
+        I: Iterator<Item = crate::error::Result<TranslatedNGram>>,
+    {
+        PrunedNGrams {
+            ngrams,
+            min_count: self.min_count(n),
+            max_continuations: self.max_continuations(n),
+            group: Vec::new().into_iter(),
+            pending: None,
+            pruned,
+        }
+    }
+}
+
+/// The value of a list per order that starts with the bigrams for the ngrams of length n
+fn per_order<T: Copy>(values: &[T], n: usize) -> Option<T> {
+    if n < 2 {
+        return None;
+    }
+    values.get(n - 2).or(values.last()).copied()
+}
+
+/// The ngrams of an order that were not pruned by their counts, in the order they were read in
+pub(crate) struct PrunedNGrams<'a, I> {
+    ngrams: I,
+    min_count: u32,
+    max_continuations: Option<usize>,
+    /// The rest of the ngrams with the current prefix that were kept
+    group: std::vec::IntoIter<TranslatedNGram>,
+    /// The first ngram of the next prefix, which was read ahead
+    pending: Option<crate::error::Result<TranslatedNGram>>,
+    pruned: &'a mut NGramCounts,
+}
+
+impl<I> PrunedNGrams<'_, I> {
+    fn prune(&mut self, ngram: &TranslatedNGram) {
+        self.pruned.ngrams += 1;
+        self.pruned.cumulative_count += ngram.count as u64;
+    }
+}
+
+impl<I> Iterator for PrunedNGrams<'_, I>
+where
+    I: Iterator<Item = crate::error::Result<TranslatedNGram>>,
+{
+    type Item = crate::error::Result<TranslatedNGram>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(ngram) = self.group.next() {
+                return Some(Ok(ngram));
+            }
+            let first = match self.pending.take().or_else(|| self.ngrams.next())? {
+                Ok(ngram) => ngram,
+                Err(err) => return Some(Err(err)),
+            };
+            let max_continuations = match self.max_continuations {
+                Some(max_continuations) => max_continuations,
+                None if first.count < self.min_count => {
+                    self.prune(&first);
+                    continue;
+                }
+                None => return Some(Ok(first)),
+            };
+
+            // Read all ngrams with the same prefix
+            let n = first.symbols.len();
+            let mut group = vec![first];
+            for ngram in self.ngrams.by_ref() {
+                match ngram {
+                    Ok(ngram) if ngram.symbols[..n - 1] == group[0].symbols[..n - 1] => {
+                        group.push(ngram)
+                    }
+                    other => {
+                        self.pending = Some(other);
+                        break;
+                    }
+                }
+            }
+            let mut ranked: Vec<usize> = (0..group.len())
+                .filter(|&i| group[i].count >= self.min_count)
+                .collect();
+            ranked.sort_by(|&a, &b| group[b].count.cmp(&group[a].count));
+            let mut kept = vec![false; group.len()];
+            for i in ranked.into_iter().take(max_continuations) {
+                kept[i] = true;
+            }
+            let mut continuations = Vec::with_capacity(group.len().min(max_continuations));
+            for (ngram, kept) in group.into_iter().zip(kept) {
+                if kept {
+                    continuations.push(ngram);
+                } else {
+                    self.prune(&ngram);
+                }
+            }
+            self.group = continuations.into_iter();
+        }
+    }
+}
+
+/// An ngram that can be removed and how much removing it changes the model
+struct Candidate {
+    score: f64,
+    /// Index of the table and of the ngram in it
+    table: usize,
+    idx: u32,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    /// The candidate with the lowest score is the greatest, so it is the first one taken from the heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .score
+            .total_cmp(&self.score)
+            .then_with(|| other.table.cmp(&self.table))
+            .then_with(|| other.idx.cmp(&self.idx))
+    }
+}
+
+/// Remove ngrams of length 2 or more from the finished tables by relative entropy until at most `max_ngrams` of them are left
+///
+/// The relative entropy each ngram adds is estimated as described by Stolcke in "Entropy-based Pruning of Backoff
+/// Language Models", once the ngram can be removed. Like his one-pass approximation, the estimates are not updated
+/// after other ngrams with the same history were removed. Only ngrams that are neither the prefix nor the suffix
+/// of a longer ngram that is kept can be removed, so the tables stay closed under prefixes and suffixes, and ngrams
+/// whose removal would leave no probability mass to back off with are kept.
+/// The backoff weights of the histories that lost ngrams are recomputed for the smoothings that are normalized.
+/// Returns the ngrams that were removed from each table.
+pub(crate) fn prune_entropy(
+    tables: &mut [NGramTable],
+    max_ngrams: usize,
+    smoothing: Smoothing,
+) -> Vec<NGramCounts> {
+    let no_tables = tables.len();
+    let mut removed_counts = vec![NGramCounts::default(); no_tables];
+    if no_tables < 2 {
+        return removed_counts;
+    }
+    let mut no_ngrams: usize = tables[1..].iter().map(|table| table.entries.len()).sum();
+    if no_ngrams <= max_ngrams {
+        return removed_counts;
+    }
+
+    // The prefix of every ngram and the number of longer ngrams that use an ngram as their prefix or suffix
+    let mut parents: Vec<Vec<u32>> = vec![Vec::new(); no_tables];
+    let mut references: Vec<Vec<u32>> = tables
+        .iter()
+        .map(|table| {
+            table
+                .entries
+                .iter()
+                .map(|entry| entry.no_longer_ngrams)
+                .collect()
+        })
+        .collect();
+    for k in 1..no_tables {
+        parents[k] = vec![0; tables[k].entries.len()];
+        for (h, entry) in tables[k - 1].entries.iter().enumerate() {
+            for child in entry.children() {
+                parents[k][child] = h as u32;
+            }
+        }
+        if k >= 2 {
+            for entry in &tables[k].entries {
+                references[k - 1][entry.suffix as usize] += 1;
+            }
+        }
+    }
+
+    // Log probabilities of the histories, the product of the probabilities of their words
+    let mut history_log_probs: Vec<Vec<f64>> = Vec::with_capacity(no_tables - 1);
+    history_log_probs.push(
+        tables[0]
+            .entries
+            .iter()
+            .map(|entry| entry.log_prob as f64)
+            .collect(),
+    );
+    for k in 1..no_tables - 1 {
+        let log_probs = tables[k]
+            .entries
+            .iter()
+            .zip(&parents[k])
+            .map(|(entry, &parent)| {
+                history_log_probs[k - 1][parent as usize] + entry.log_prob as f64
+            })
+            .collect();
+        history_log_probs.push(log_probs);
+    }
+
+    // Probability mass the history leaves to the words that don't follow it, with the probabilities given the history
+    // and given the history without its first word
+    let left_mass = |tables: &[NGramTable], k: usize, h: usize, removed: &[Vec<bool>]| {
+        let (mut seen, mut seen_lower) = (0.0, 0.0);
+        for child in tables[k].entries[h].children() {
+            if removed[k + 1][child] {
+                continue;
+            }
+            let entry = &tables[k + 1].entries[child];
+            seen += (entry.log_prob as f64).exp();
+            seen_lower += (tables[k].entries[entry.suffix as usize].log_prob as f64).exp();
+        }
+        (1.0 - seen, 1.0 - seen_lower)
+    };
+
+    let mut removed: Vec<Vec<bool>> = tables
+        .iter()
+        .map(|table| vec![false; table.entries.len()])
+        .collect();
+    // The probabilities of the ngram given its history and given the history without its first word,
+    // the mass its history leaves to the other words and the backoff weight of the history without the ngram.
+    // None if there would be no mass left to back off with.
+    let removal = |tables: &[NGramTable], removed: &[Vec<bool>], k: usize, idx: usize| {
+        let entry = &tables[k].entries[idx];
+        let h = parents[k][idx] as usize;
+        let prob = (entry.log_prob as f64).exp();
+        let lower_prob = (tables[k - 1].entries[entry.suffix as usize].log_prob as f64).exp();
+        let (left, left_lower) = left_mass(tables, k - 1, h, removed);
+        let (new_left, new_left_lower) = (left + prob, left_lower + lower_prob);
+        let new_log_backoff = (new_left / new_left_lower).ln();
+        (new_left > 0.0 && new_left_lower > 0.0 && new_log_backoff.is_finite()).then_some((
+            prob,
+            lower_prob,
+            left,
+            new_log_backoff,
+        ))
+    };
+    let score = |tables: &[NGramTable], removed: &[Vec<bool>], k: usize, idx: usize| {
+        let Some((prob, lower_prob, left, new_log_backoff)) = removal(tables, removed, k, idx)
+        else {
+            return f64::INFINITY;
+        };
+        let h = parents[k][idx] as usize;
+        let log_backoff = tables[k - 1].entries[h].backoff as f64;
+        -history_log_probs[k - 1][h].exp()
+            * (prob * (lower_prob.ln() + new_log_backoff - prob.ln())
+                + (new_log_backoff - log_backoff) * left)
+    };
+
+    let mut heap = BinaryHeap::new();
+    for (k, references) in references.iter().enumerate().skip(1) {
+        for (idx, _) in references.iter().enumerate().filter(|(_, &refs)| refs == 0) {
+            heap.push(Candidate {
+                score: score(tables, &removed, k, idx),
+                table: k,
+                idx: idx as u32,
+            });
+        }
+    }
+    while no_ngrams > max_ngrams {
+        let Some(Candidate { table: k, idx, .. }) = heap.pop() else {
+            break;
+        };
+        let idx = idx as usize;
+        // The siblings that were removed since the score was estimated may have left no mass to back off with
+        if removal(tables, &removed, k, idx).is_none() {
+            continue;
+        }
+        removed[k][idx] = true;
+        no_ngrams -= 1;
+        removed_counts[k].ngrams += 1;
+        removed_counts[k].cumulative_count += tables[k].entries[idx].count as u64;
+
+        // The prefix and the suffix can become removable now
+        let mut lower = vec![parents[k][idx] as usize];
+        if k >= 2 {
+            lower.push(tables[k].entries[idx].suffix as usize);
+        }
+        for lower_idx in lower {
+            references[k - 1][lower_idx] -= 1;
+            if k >= 2 && references[k - 1][lower_idx] == 0 {
+                heap.push(Candidate {
+                    score: score(tables, &removed, k - 1, lower_idx),
+                    table: k - 1,
+                    idx: lower_idx as u32,
+                });
+            }
+        }
+    }
+
+    // Histories that lost ngrams need new backoff weights, so the probabilities still sum up to 1
+    let normalized = matches!(
+        smoothing,
+        Smoothing::AbsoluteDiscounting { .. }
+            | Smoothing::ModifiedKneserNey
+            | Smoothing::Katz { .. }
+    );
+    if normalized {
+        for k in 0..no_tables - 1 {
+            for h in 0..tables[k].entries.len() {
+                if !tables[k].entries[h]
+                    .children()
+                    .any(|child| removed[k + 1][child])
+                {
+                    continue;
+                }
+                // Only ngrams that left mass to back off with were removed
+                let (left, left_lower) = left_mass(tables, k, h, &removed);
+                tables[k].entries[h].backoff = (left / left_lower).ln() as f32;
+            }
+        }
+    }
+
+    // Remove the ngrams and move the offsets and suffixes to the indices of the ngrams that are left
+    let mut new_indices: Vec<u32> = (0..tables[0].entries.len() as u32).collect();
+    for k in 1..no_tables {
+        let mut indices = vec![u32::MAX; tables[k].entries.len()];
+        let mut next = 0;
+        for (idx, &removed) in removed[k].iter().enumerate() {
+            if !removed {
+                indices[idx] = next;
+                next += 1;
+            }
+        }
+        for entry in tables[k - 1].entries.iter_mut() {
+            let children = entry.children();
+            // Ngrams without longer ones have an offset of 0, like the ones `generate` writes
+            let kept = || {
+                indices[children.clone()]
+                    .iter()
+                    .copied()
+                    .filter(|&idx| idx != u32::MAX)
+            };
+            entry.offset = kept().next().unwrap_or(0);
+            entry.no_longer_ngrams = kept().count() as u32;
+        }
+        let mut idx = 0;
+        tables[k].entries.retain(|_| {
+            idx += 1;
+            !removed[k][idx - 1]
+        });
+        if k >= 2 {
+            for entry in tables[k].entries.iter_mut() {
+                entry.suffix = new_indices[entry.suffix as usize];
+            }
+        }
+        new_indices = indices;
+    }
+    removed_counts
+}
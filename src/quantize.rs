@@ -0,0 +1,259 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::evaluate::evaluate;
+use crate::model::{LanguageModel, ModelTables};
+
+/// Maximum number of values k-means is trained on, larger tables are sampled
+const MAX_SAMPLE: usize = 1 << 20;
+/// Maximum number of iterations of k-means
+const MAX_ITERATIONS: usize = 30;
+
+/// How the log probabilities and backoff weights are quantized when a model is written in the binary format
+///
+/// The values of every order except the unigrams are replaced by the index of the closest value of a codebook with `2^bits` values.
+/// The unigrams are few, so they keep their exact values. `bits` is either 8 or 16.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quantization {
+    /// Bins of the same width between the smallest and the largest value
+    Uniform { bits: u8 },
+    /// Bins found with k-means, so ranges with many values get more bins
+    KMeans { bits: u8 },
+}
+
+impl Quantization {
+    /// Number of bits of a quantized value
+    pub fn bits(&self) -> u8 {
+        match self {
+            Quantization::Uniform { bits } | Quantization::KMeans { bits } => *bits,
+        }
+    }
+
+    /// Number of bytes of a quantized value
+    /// Fails if the number of bits is neither 8 nor 16
+    pub(crate) fn value_bytes(&self) -> Result<usize> {
+        match self.bits() {
+            8 => Ok(1),
+            16 => Ok(2),
+            bits => Err(Error::InvalidConfig(format!(
+                "Values can only be quantized to 8 or 16 bits, not {}",
+                bits
+            ))),
+        }
+    }
+
+    /// Find the codebook for the values
+    pub(crate) fn codebook<I: Iterator<Item = f32> + Clone>(&self, values: I) -> Codebook {
+        let no_bins = 1 << self.bits();
+        let has_log_zero = values.clone().any(|value| value == f32::NEG_INFINITY);
+        let finite = values.filter(|value| value.is_finite());
+        // Impossible events are kept exactly, so they get a bin of their own
+        let no_bins = if has_log_zero { no_bins - 1 } else { no_bins };
+        let mut centers = match self {
+            Quantization::Uniform { .. } => uniform(finite, no_bins),
+            Quantization::KMeans { .. } => kmeans(finite, no_bins),
+        };
+        if has_log_zero {
+            centers.insert(0, f32::NEG_INFINITY);
+        }
+        Codebook { centers }
+    }
+
+    /// Copy of the tables with the log probabilities and backoff weights replaced by the ones of the codebooks,
+    /// so it gives the same results as a quantized binary model
+    pub fn apply(&self, tables: &ModelTables) -> Result<ModelTables> {
+        self.value_bytes()?;
+        let mut quantized = tables.clone();
+        for table in quantized.ngrams.iter_mut().skip(1) {
+            let log_probs = self.codebook(table.iter().map(|record| record.log_prob));
+            let backoffs = self.codebook(table.iter().map(|record| record.backoff));
+            for record in table.iter_mut() {
+                record.log_prob = log_probs.decode(log_probs.encode(record.log_prob));
+                record.backoff = backoffs.decode(backoffs.encode(record.backoff));
+            }
+        }
+        Ok(quantized)
+    }
+}
+
+impl fmt::Display for Quantization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Quantization::Uniform { bits } => write!(f, "uniform:{}", bits),
+            Quantization::KMeans { bits } => write!(f, "kmeans:{}", bits),
+        }
+    }
+}
+
+impl FromStr for Quantization {
+    type Err = Error;
+
+    /// Parse `uniform[:<bits>]` or `kmeans[:<bits>]`, the bits default to 8
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, bits) = match s.split_once(':') {
+            Some((name, bits)) => (name, bits),
+            None => (s, "8"),
+        };
+        let invalid = || Error::InvalidConfig(format!("Invalid quantization '{}'", s));
+        let bits = bits.parse().map_err(|_| invalid())?;
+        let quantization = match name {
+            "uniform" => Quantization::Uniform { bits },
+            "kmeans" | "k-means" => Quantization::KMeans { bits },
+            _ => return Err(invalid()),
+        };
+        quantization.value_bytes()?;
+        Ok(quantization)
+    }
+}
+
+/// The values the quantized values stand for, sorted in ascending order
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Codebook {
+    pub(crate) centers: Vec<f32>,
+}
+
+impl Codebook {
+    /// Index of the value of the codebook that is closest to the value
+    pub(crate) fn encode(&self, value: f32) -> u16 {
+        let i = self.centers.partition_point(|&center| center < value);
+        let closer_to_lower = i == self.centers.len()
+            || (i > 0 && value - self.centers[i - 1] < self.centers[i] - value);
+        let closest = if closer_to_lower { i - 1 } else { i };
+        closest as u16
+    }
+
+    /// Value an index stands for
+    pub(crate) fn decode(&self, index: u16) -> f32 {
+        self.centers
+            .get(index as usize)
+            .copied()
+            .unwrap_or(f32::NEG_INFINITY)
+    }
+}
+
+/// Centers of bins of the same width between the smallest and the largest value
+fn uniform<I: Iterator<Item = f32>>(values: I, no_bins: usize) -> Vec<f32> {
+    let (min, max) = values.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+        (min.min(value), max.max(value))
+    });
+    if min > max {
+        return vec![0.0];
+    }
+    if min == max {
+        return vec![min];
+    }
+    let width = (max - min) / no_bins as f32;
+    (0..no_bins)
+        .map(|i| min + (i as f32 + 0.5) * width)
+        .collect()
+}
+
+/// Centers found with Lloyd's algorithm, starting with the quantiles of the values
+fn kmeans<I: Iterator<Item = f32> + Clone>(values: I, no_bins: usize) -> Vec<f32> {
+    let no_values = values.clone().count();
+    let mut sample: Vec<f32> = values.step_by(no_values / MAX_SAMPLE + 1).collect();
+    if sample.is_empty() {
+        return vec![0.0];
+    }
+    sample.sort_by(f32::total_cmp);
+
+    let mut centers: Vec<f32> = (0..no_bins)
+        .map(|i| sample[(2 * i + 1) * sample.len() / (2 * no_bins)])
+        .collect();
+    centers.dedup();
+    for _ in 0..MAX_ITERATIONS {
+        // The values and the centers are sorted, so each bin is a range of the sample
+        let mut sums = vec![0.0f64; centers.len()];
+        let mut counts = vec![0usize; centers.len()];
+        let mut bin = 0;
+        for &value in &sample {
+            while bin + 1 < centers.len() && value - centers[bin] > centers[bin + 1] - value {
+                bin += 1;
+            }
+            sums[bin] += value as f64;
+            counts[bin] += 1;
+        }
+        let updated: Vec<f32> = centers
+            .iter()
+            .zip(sums.iter().zip(&counts))
+            .map(|(&center, (&sum, &count))| {
+                if count == 0 {
+                    center
+                } else {
+                    (sum / count as f64) as f32
+                }
+            })
+            .collect();
+        if updated == centers {
+            break;
+        }
+        centers = updated;
+    }
+    centers.dedup();
+    centers
+}
+
+/// How much quantizing a model changes its perplexity on held-out text
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuantizationReport {
+    pub quantization: Quantization,
+    /// Number of words of the held-out text that were scored, see `Evaluation`
+    pub no_words: usize,
+    pub perplexity: f32,
+    pub quantized_perplexity: f32,
+    /// Size of the records of all orders in the binary format in bytes
+    pub size: u64,
+    pub quantized_size: u64,
+}
+
+impl fmt::Display for QuantizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Quantization {} on {} known words",
+            self.quantization, self.no_words
+        )?;
+        writeln!(
+            f,
+            "Perplexity: {} -> {} ({:+.2}%)",
+            self.perplexity,
+            self.quantized_perplexity,
+            100.0 * (self.quantized_perplexity / self.perplexity - 1.0)
+        )?;
+        write!(
+            f,
+            "Size of the records: {} bytes -> {} bytes ({:.1}%)",
+            self.size,
+            self.quantized_size,
+            100.0 * self.quantized_size as f64 / self.size as f64
+        )
+    }
+}
+
+/// Compare the perplexity of the model before and after quantizing it on a held-out text file with one sentence per line
+pub fn perplexity_impact<P: AsRef<Path>>(
+    tables: &ModelTables,
+    quantization: Quantization,
+    heldout_file: P,
+) -> Result<QuantizationReport> {
+    let heldout_file = heldout_file.as_ref();
+    let quantized = quantization.apply(tables)?;
+    let model = LanguageModel::from_tables(tables.clone())?;
+    // Scored by `evaluate`, so the perplexities are the same ones it reports, reading the file once per model
+    let evaluation = evaluate(&model, heldout_file)?;
+    let with_backoff = tables.has_backoff();
+    let size = crate::binary::records_len(tables, with_backoff, None)?;
+    let quantized_size = crate::binary::records_len(tables, with_backoff, Some(quantization))?;
+    let model = LanguageModel::from_tables(quantized)?;
+    let quantized_perplexity = evaluate(&model, heldout_file)?.perplexity();
+    Ok(QuantizationReport {
+        quantization,
+        no_words: evaluation.no_scored(),
+        perplexity: evaluation.perplexity(),
+        quantized_perplexity,
+        size,
+        quantized_size,
+    })
+}
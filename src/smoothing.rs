@@ -0,0 +1,322 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::model::TableEntry;
+use crate::NGramTable;
+
+/// How the probabilities and backoff weights of the ngrams are estimated from their counts
+///
+/// Except for `MaximumLikelihood` and `StupidBackoff`, the probabilities under each history are normalized over the kept
+/// continuations, so the counts of continuations with words that are not in the vocabulary don't take away any probability mass.
+/// The model can be queried like a backoff model: if the ngram is not in the tables, the probability is the backoff weight
+/// of its history times the probability of the ngram without its first word.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Smoothing {
+    /// `ln(count / count of the prefix)` without any backoff weights
+    #[default]
+    MaximumLikelihood,
+    /// Interpolated absolute discounting with one discount per order
+    /// If no discount is given, it is estimated as `n1 / (n1 + 2 * n2)` from the number of ngrams that occurred once and twice
+    AbsoluteDiscounting { discount: Option<f32> },
+    /// Interpolated modified Kneser-Ney smoothing with three discounts per order as described by Chen and Goodman
+    ModifiedKneserNey,
+    /// Katz backoff where counts up to `max_count` are discounted with Good-Turing estimates
+    Katz { max_count: u32 },
+    /// Stupid Backoff as described by Brants et al.: relative frequencies and a constant backoff weight `alpha`
+    /// The scores are not normalized, so they are no real probabilities
+    StupidBackoff { alpha: f32 },
+}
+
+impl Smoothing {
+    /// Whether the smoothing calculates backoff weights that need to be stored
+    pub fn has_backoff(&self) -> bool {
+        *self != Smoothing::MaximumLikelihood
+    }
+}
+
+impl fmt::Display for Smoothing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Smoothing::MaximumLikelihood => write!(f, "ml"),
+            Smoothing::AbsoluteDiscounting { discount: None } => write!(f, "absolute"),
+            Smoothing::AbsoluteDiscounting {
+                discount: Some(discount),
+            } => write!(f, "absolute:{}", discount),
+            Smoothing::ModifiedKneserNey => write!(f, "kneser-ney"),
+            Smoothing::Katz { max_count } => write!(f, "katz:{}", max_count),
+            Smoothing::StupidBackoff { alpha } => write!(f, "stupid:{}", alpha),
+        }
+    }
+}
+
+impl FromStr for Smoothing {
+    type Err = Error;
+
+    /// Parse `ml`, `absolute[:<discount>]`, `kneser-ney`, `katz[:<max_count>]` or `stupid[:<alpha>]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameter) = match s.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter)),
+            None => (s, None),
+        };
+        let invalid = || Error::InvalidConfig(format!("Invalid smoothing '{}'", s));
+        let smoothing = match (name, parameter) {
+            ("ml" | "maximum-likelihood", None) => Smoothing::MaximumLikelihood,
+            ("absolute", None) => Smoothing::AbsoluteDiscounting { discount: None },
+            ("absolute", Some(discount)) => Smoothing::AbsoluteDiscounting {
+                discount: Some(discount.parse().map_err(|_| invalid())?),
+            },
+            ("kneser-ney" | "kn", None) => Smoothing::ModifiedKneserNey,
+            ("katz", None) => Smoothing::Katz { max_count: 5 },
+            ("katz", Some(max_count)) => Smoothing::Katz {
+                max_count: max_count.parse().map_err(|_| invalid())?,
+            },
+            ("stupid", None) => Smoothing::StupidBackoff { alpha: 0.4 },
+            ("stupid", Some(alpha)) => Smoothing::StupidBackoff {
+                alpha: alpha.parse().map_err(|_| invalid())?,
+            },
+            _ => return Err(invalid()),
+        };
+        Ok(smoothing)
+    }
+}
+
+/// Calculate the log probabilities and backoff weights of all tables
+/// The tables need to be complete, so the offsets, numbers of longer ngrams and suffixes are known
+/// For `MaximumLikelihood` the log probabilities calculated while translating the ngrams are kept
+pub(crate) fn apply(smoothing: Smoothing, tables: &mut [NGramTable]) {
+    match smoothing {
+        Smoothing::MaximumLikelihood => {}
+        Smoothing::StupidBackoff { alpha } => {
+            let backoff = alpha.ln();
+            let no_histories = tables.len() - 1;
+            for table in &mut tables[..no_histories] {
+                for entry in &mut table.entries {
+                    entry.backoff = backoff;
+                }
+            }
+        }
+        Smoothing::AbsoluteDiscounting { discount } => interpolate(tables, false, |counts| {
+            Discounts::absolute(counts, discount)
+        }),
+        Smoothing::ModifiedKneserNey => interpolate(tables, true, Discounts::modified_kneser_ney),
+        Smoothing::Katz { max_count } => katz(tables, max_count),
+    }
+}
+
+/// Number of ngrams that occurred r times for r up to max_count
+fn count_of_counts(counts: &[u64], max_count: usize) -> Vec<u64> {
+    let mut count_of_counts = vec![0; max_count + 1];
+    for &count in counts {
+        if count as usize <= max_count {
+            count_of_counts[count as usize] += 1;
+        }
+    }
+    count_of_counts
+}
+
+/// Discounts for ngrams that occurred once, twice and three or more times
+struct Discounts([f64; 3]);
+
+impl Discounts {
+    /// One discount for all counts, estimated from the counts if none was given
+    fn absolute(counts: &[u64], discount: Option<f32>) -> Self {
+        let discount = match discount {
+            Some(discount) => discount as f64,
+            None => {
+                let n = count_of_counts(counts, 2);
+                if n[1] > 0 && n[2] > 0 {
+                    n[1] as f64 / (n[1] as f64 + 2.0 * n[2] as f64)
+                } else {
+                    // There are not enough different counts to estimate the discount
+                    0.5
+                }
+            }
+        };
+        Discounts([discount; 3])
+    }
+
+    /// Discounts estimated from the number of ngrams that occurred one to four times
+    /// If one of them is 0, the discounts can't be estimated and the ones for absolute discounting are used
+    fn modified_kneser_ney(counts: &[u64]) -> Self {
+        let n: Vec<f64> = count_of_counts(counts, 4)
+            .into_iter()
+            .map(|n| n as f64)
+            .collect();
+        if n[1..].contains(&0.0) {
+            return Self::absolute(counts, None);
+        }
+        let y = n[1] / (n[1] + 2.0 * n[2]);
+        Discounts([
+            (1.0 - 2.0 * y * n[2] / n[1]).clamp(0.0, 1.0),
+            (2.0 - 3.0 * y * n[3] / n[2]).clamp(0.0, 2.0),
+            (3.0 - 4.0 * y * n[4] / n[3]).clamp(0.0, 3.0),
+        ])
+    }
+
+    /// Discount for an ngram with the count, it is never bigger than the count
+    fn get(&self, count: u64) -> f64 {
+        let discount = match count {
+            0 => 0.0,
+            1 => self.0[0],
+            2 => self.0[1],
+            _ => self.0[2],
+        };
+        discount.min(count as f64)
+    }
+}
+
+/// Interpolate the discounted probabilities of each order with the probabilities of the next lower order
+/// The unigrams are interpolated with the uniform distribution over the vocabulary
+/// If `continuation` is set, all orders except the highest use the number of different words that precede them instead of their count
+fn interpolate<F: Fn(&[u64]) -> Discounts>(
+    tables: &mut [NGramTable],
+    continuation: bool,
+    discounts: F,
+) {
+    let max_ngram_len = tables.len();
+    let mut counts: Vec<Vec<u64>> = tables
+        .iter()
+        .map(|table| {
+            table
+                .entries
+                .iter()
+                .map(|entry| entry.count as u64)
+                .collect()
+        })
+        .collect();
+    if continuation {
+        for k in 0..max_ngram_len - 1 {
+            let mut continuation_counts = vec![0; tables[k].entries.len()];
+            for entry in &tables[k + 1].entries {
+                continuation_counts[entry.suffix as usize] += 1;
+            }
+            counts[k] = continuation_counts;
+        }
+    }
+
+    // Unigrams
+    let discount = discounts(&counts[0]);
+    let total: u64 = counts[0].iter().sum();
+    let vocab_size = tables[0].entries.len() as f64;
+    let gamma = if total > 0 {
+        counts[0].iter().map(|&c| discount.get(c)).sum::<f64>() / total as f64
+    } else {
+        1.0
+    };
+    for (entry, &count) in tables[0].entries.iter_mut().zip(&counts[0]) {
+        let discounted = if total > 0 {
+            (count as f64 - discount.get(count)) / total as f64
+        } else {
+            0.0
+        };
+        entry.log_prob = (discounted + gamma / vocab_size).ln() as f32;
+    }
+
+    // All higher orders, the probabilities of the lower order are final when they are used
+    for k in 1..max_ngram_len {
+        let discount = discounts(&counts[k]);
+        let (lower, higher) = tables.split_at_mut(k);
+        let histories = &mut lower[k - 1];
+        let ngrams = &mut higher[0];
+        for h in 0..histories.entries.len() {
+            let children = histories.entries[h].children();
+            if children.is_empty() {
+                continue;
+            }
+            let total: u64 = counts[k][children.clone()].iter().sum();
+            let gamma = if total > 0 {
+                children
+                    .clone()
+                    .map(|i| discount.get(counts[k][i]))
+                    .sum::<f64>()
+                    / total as f64
+            } else {
+                1.0
+            };
+            for i in children {
+                let count = counts[k][i];
+                let lower_prob =
+                    (histories.entries[ngrams.entries[i].suffix as usize].log_prob as f64).exp();
+                let discounted = if total > 0 {
+                    (count as f64 - discount.get(count)) / total as f64
+                } else {
+                    0.0
+                };
+                ngrams.entries[i].log_prob = (discounted + gamma * lower_prob).ln() as f32;
+            }
+            histories.entries[h].backoff = gamma.ln() as f32;
+        }
+    }
+}
+
+/// Ratios the counts 1 to max_count get multiplied with according to Katz
+/// Ratios that can't be estimated or are not between 0 and 1 are set to 1, so the count is not discounted
+fn good_turing_ratios(counts: &[u64], max_count: u32) -> Vec<f64> {
+    let max_count = max_count as usize;
+    let n = count_of_counts(counts, max_count + 1);
+    let mut ratios = vec![1.0; max_count + 1];
+    if n[1] == 0 {
+        return ratios;
+    }
+    let common = (max_count + 1) as f64 * n[max_count + 1] as f64 / n[1] as f64;
+    for r in 1..=max_count {
+        if n[r] == 0 {
+            continue;
+        }
+        let r_star = (r + 1) as f64 * n[r + 1] as f64 / n[r] as f64;
+        let ratio = (r_star / r as f64 - common) / (1.0 - common);
+        if ratio > 0.0 && ratio <= 1.0 {
+            ratios[r] = ratio;
+        }
+    }
+    ratios
+}
+
+/// Discount the counts with Good-Turing estimates and distribute the freed probability mass to the next lower order
+/// The unigrams keep their relative frequencies
+fn katz(tables: &mut [NGramTable], max_count: u32) {
+    for k in 1..tables.len() {
+        let counts: Vec<u64> = tables[k]
+            .entries
+            .iter()
+            .map(|entry| entry.count as u64)
+            .collect();
+        let ratios = good_turing_ratios(&counts, max_count);
+        let (lower, higher) = tables.split_at_mut(k);
+        let histories = &mut lower[k - 1];
+        let ngrams = &mut higher[0];
+        for h in 0..histories.entries.len() {
+            let children = histories.entries[h].children();
+            if children.is_empty() {
+                continue;
+            }
+            let total: u64 = counts[children.clone()].iter().sum();
+            let mut seen_prob = 0.0;
+            let mut seen_lower_prob = 0.0;
+            for i in children.clone() {
+                let count = counts[i];
+                let ratio = ratios.get(count as usize).copied().unwrap_or(1.0);
+                let prob = if total > 0 {
+                    ratio * count as f64 / total as f64
+                } else {
+                    0.0
+                };
+                seen_prob += prob;
+                seen_lower_prob +=
+                    (histories.entries[ngrams.entries[i].suffix as usize].log_prob as f64).exp();
+                ngrams.entries[i].log_prob = prob.ln() as f32;
+            }
+            let left_prob = (1.0 - seen_prob).max(0.0);
+            if seen_lower_prob < 1.0 - 1e-9 {
+                histories.entries[h].backoff = (left_prob / (1.0 - seen_lower_prob)).ln() as f32;
+            } else if seen_prob > 0.0 {
+                // The lower order does not leave anything for the words that were not seen after the history,
+                // so the probabilities of the seen words are renormalized instead
+                for i in children {
+                    ngrams.entries[i].log_prob -= seen_prob.ln() as f32;
+                }
+            }
+        }
+    }
+}
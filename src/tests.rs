@@ -1,21 +1,124 @@
 use super::*;
 use crate::utilities::LinesIterator;
+use std::path::{Path, PathBuf};
+
+const TEST_ROOT: &str = "LanguageModel_TEST/";
+
+/// Builder for the test data that writes its results to its own folder, so tests can run in parallel
+fn test_builder(folder_result: &str) -> GeneratorConfigBuilder {
+    GeneratorConfig::builder()
+        .ngrams_dir(format!("{}ngrams_ALL/", TEST_ROOT))
+        .dictionary(format!("{}dict/words_allow.txt", TEST_ROOT))
+        .output_dir(format!("{}{}", TEST_ROOT, folder_result))
+}
+
+/// Config for the test data that writes its results to its own folder, so tests can run in parallel
+fn test_config(folder_result: &str, max_no_words: usize, max_ngram_len: usize) -> GeneratorConfig {
+    test_builder(folder_result)
+        .max_no_words(max_no_words)
+        .max_ngram_len(max_ngram_len)
+        .build()
+        .unwrap()
+}
+
+/// Write the ngram files, starting with the unigrams, and the dictionary of a test to its own folder
+/// Returns a builder for a model of all of the orders that writes its results to `ngrams_result/` in that folder.
+fn write_test_data(folder: &str, ngrams: &[&str], dictionary: &str) -> GeneratorConfigBuilder {
+    let dir = format!("{}{}", TEST_ROOT, folder);
+    let ngrams_dir = format!("{}ngrams/", dir);
+    fs::create_dir_all(&ngrams_dir).unwrap();
+    for (i, ngrams) in ngrams.iter().enumerate() {
+        fs::write(format!("{}{}gms.txt", ngrams_dir, i + 1), ngrams).unwrap();
+    }
+    let dictionary_file = format!("{}words.txt", dir);
+    fs::write(&dictionary_file, dictionary).unwrap();
+    GeneratorConfig::builder()
+        .ngrams_dir(ngrams_dir)
+        .dictionary(dictionary_file)
+        .output_dir(format!("{}ngrams_result/", dir))
+        .max_ngram_len(ngrams.len())
+}
+
+/// Count the ngrams of the sentences of a test up to trigrams and write them and the dictionary to its own folder
+/// Returns a builder that writes its results to `ngrams_result/` in that folder.
+fn write_corpus_data(folder: &str, corpus: &str, dictionary: &str) -> GeneratorConfigBuilder {
+    let dir = format!("{}{}", TEST_ROOT, folder);
+    let ngrams_dir = format!("{}ngrams/", dir);
+    fs::create_dir_all(&dir).unwrap();
+    let corpus_file = format!("{}corpus.txt", dir);
+    fs::write(&corpus_file, corpus).unwrap();
+    let mut counter = CorpusCounter::new(3, CorpusFormat::SentencePerLine);
+    counter.add_file(&corpus_file).unwrap();
+    counter.write_ngram_files(&ngrams_dir).unwrap();
+    let dictionary_file = format!("{}words.txt", dir);
+    fs::write(&dictionary_file, dictionary).unwrap();
+    GeneratorConfig::builder()
+        .ngrams_dir(ngrams_dir)
+        .dictionary(dictionary_file)
+        .output_dir(format!("{}ngrams_result/", dir))
+}
+
+fn ngram_counts(ngrams: u32, cumulative_count: u64) -> NGramCounts {
+    NGramCounts {
+        ngrams,
+        cumulative_count,
+    }
+}
+
+/// Number of ngrams that were read and kept of each order, which don't depend on how the model was generated
+fn order_counts(summary: &GenerationSummary) -> Vec<(usize, NGramCounts, NGramCounts)> {
+    summary
+        .orders
+        .iter()
+        .map(|order| (order.n, order.total, order.kept))
+        .collect()
+}
+
+/// Check that the config generated the same translated ngrams as the expected one
+fn assert_same_ngrams(config: &GeneratorConfig, expected: &GeneratorConfig) {
+    for n in 1..=expected.max_ngram_len() {
+        assert_eq!(
+            fs::read(config.output_file(n)).unwrap(),
+            fs::read(expected.output_file(n)).unwrap(),
+            "{}grams",
+            n
+        );
+    }
+}
+
+fn assert_file_content<P: AsRef<Path>>(fname: P, correct_content: &[&str]) {
+    let mut lines = LinesIterator::new(fname).unwrap().map(Result::unwrap);
+    for line in correct_content {
+        assert_eq!(lines.next().as_deref(), Some(*line));
+    }
+    assert!(lines.next().is_none());
+}
 
 #[test]
 // Test case C1
+#[allow(clippy::partialeq_to_none)]
 fn test_generation() {
-    let test_mode = true;
     let max_no_words = 100_000;
 
-    let root = "LanguageModel_TEST/";
+    let root = TEST_ROOT;
     let folder_result = "ngrams_result/";
 
-    generate(test_mode, max_no_words);
+    let config = GeneratorConfig::from_root(root, max_no_words, 3).unwrap();
+    let summary = generate(&config).unwrap();
+    assert_eq!(summary.orders.len(), 3);
+    assert_eq!(
+        summary.orders[0].kept,
+        NGramCounts {
+            ngrams: 2,
+            cumulative_count: 6
+        }
+    );
+    assert_eq!(summary.orders[0].skipped().ngrams, 1);
 
     // Check processing 1-grams
     let correct_content = vec!["-0.6931472 0 1".to_string(), "-0.6931472 1 2".to_string()];
     let fname = format! {"{}{}{}", root,folder_result,"1gms.txt"};
-    let mut lines = LinesIterator::new(&fname);
+    let mut lines = LinesIterator::new(&fname).unwrap().map(Result::unwrap);
     for line in correct_content {
         assert!(lines.next() == Some(line));
     }
@@ -28,7 +131,7 @@ fn test_generation() {
         "1 -1.0986123 3 1".to_string(),
     ];
     let fname = format! {"{}{}{}", root,folder_result,"2gms.txt"};
-    let mut lines = LinesIterator::new(&fname);
+    let mut lines = LinesIterator::new(&fname).unwrap().map(Result::unwrap);
     for line in correct_content {
         assert!(lines.next() == Some(line));
     }
@@ -42,7 +145,7 @@ fn test_generation() {
         "0 0 1".to_string(),
     ];
     let fname = format! {"{}{}{}", root,folder_result,"3gms.txt"};
-    let mut lines = LinesIterator::new(&fname);
+    let mut lines = LinesIterator::new(&fname).unwrap().map(Result::unwrap);
     for line in correct_content {
         assert!(lines.next() == Some(line));
     }
@@ -51,9 +154,1641 @@ fn test_generation() {
     // Check result for symbt
     let correct_content = vec!["a".to_string(), "b".to_string()];
     let fname = format! {"{}{}{}", root,folder_result,"symt.txt"};
-    let mut lines = LinesIterator::new(&fname);
+    let mut lines = LinesIterator::new(&fname).unwrap().map(Result::unwrap);
     for line in correct_content {
         assert!(lines.next() == Some(line));
     }
     assert!(lines.next() == None);
 }
+
+#[test]
+fn test_generation_4grams() {
+    let config = test_config("ngrams_result_4grams/", 100_000, 4);
+    generate(&config).unwrap();
+
+    // The lower orders are the same as for trigrams, but the trigrams now have children
+    assert_file_content(config.output_file(1), &["-0.6931472 0 1", "-0.6931472 1 2"]);
+    assert_file_content(
+        config.output_file(2),
+        &["1 -0.40546507 0 2", "0 -0.40546507 2 1", "1 -1.0986123 3 1"],
+    );
+    assert_file_content(
+        config.output_file(3),
+        &[
+            "0 -0.6931472 1 0 1",
+            "1 -0.6931472 2 0 0",
+            "1 -0.6931472 0 1 1",
+            "0 0 1 2 1",
+        ],
+    );
+    assert_file_content(config.output_file(4), &["1 0 2", "1 0 1", "1 0 2"]);
+    assert_eq!(
+        ModelTables::max_ngram_len_in(&config.output_dir).unwrap(),
+        4
+    );
+}
+
+#[test]
+fn test_generation_4grams_missing_lower_order() {
+    // The suffix "c a b" of "b c a b" and the prefix "c a b" of "c a b c" are not trigrams
+    let config = write_test_data(
+        "ngrams_result_4grams_missing/",
+        &[
+            "a 4\nb 4\nc 4\n",
+            "a b 3\nb c 3\nc a 2\n",
+            "a b c 2\nb c a 2\n",
+            "a b c a 2\nb c a b 1\nc a b c 1\n",
+        ],
+        "a\nb\nc\n",
+    )
+    .build()
+    .unwrap();
+    let summary = generate(&config).unwrap();
+    assert_eq!(summary.orders[3].kept.ngrams, 1);
+    assert_eq!(summary.orders[3].skipped().ngrams, 2);
+
+    // Only "a b c" has a child, "a b c a" with the suffix "b c a"
+    assert_file_content(
+        config.output_file(3),
+        &["2 -0.40546507 1 0 1", "0 -0.40546507 2 0 0"],
+    );
+    assert_file_content(config.output_file(4), &["0 0 1"]);
+}
+
+#[test]
+fn test_empty_order_file() {
+    let dir = format!("{}ngrams_result_empty_order/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let trigrams = format!("{}3gms.txt", dir);
+    fs::write(&trigrams, "").unwrap();
+    let config = test_builder("ngrams_result_empty_order/ngrams_result/")
+        .ngram_file(3, &trigrams)
+        .build()
+        .unwrap();
+    let summary = generate(&config).unwrap();
+    assert_eq!(summary.orders[2].total, NGramCounts::default());
+    let printed = summary.orders[2].to_string();
+    assert!(!printed.contains("NaN"), "{}", printed);
+    assert!(printed.contains("0.000% of the ngrams were skipped, which made up 0.000%"));
+}
+
+#[test]
+fn test_config_builder() {
+    let config = GeneratorConfig::builder()
+        .ngrams_dir("ngrams")
+        .ngram_file(2, "bigrams.txt")
+        .dictionary("dict.txt")
+        .output_dir("out")
+        .build()
+        .unwrap();
+    assert_eq!(
+        config.ngram_files,
+        vec![
+            PathBuf::from("ngrams/1gms.txt"),
+            PathBuf::from("bigrams.txt"),
+            PathBuf::from("ngrams/3gms.txt"),
+        ]
+    );
+    assert_eq!(config.max_no_words, 30_000);
+
+    // Without a folder to look them up, every order needs its own file
+    assert!(GeneratorConfig::builder()
+        .ngram_file(1, "unigrams.txt")
+        .dictionary("dict.txt")
+        .output_dir("out")
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_parse_error() {
+    let dir = format!("{}ngrams_result_parse_error/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let fname = format!("{}2gms.txt", dir);
+    fs::write(&fname, "a b 2\na c\n").unwrap();
+
+    let mut ngrams = NGramIterator::new(&fname, 2).unwrap();
+    assert_eq!(
+        ngrams.next().unwrap().unwrap(),
+        (vec!["a".to_string(), "b".to_string()], 2)
+    );
+    match ngrams.next().unwrap() {
+        Err(Error::Parse {
+            line_number, line, ..
+        }) => {
+            assert_eq!(line_number, 2);
+            assert_eq!(line, "a c");
+        }
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_unsorted_ngrams() {
+    let dir = format!("{}ngrams_result_unsorted/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let fname = format!("{}2gms_unsorted.txt", dir);
+    fs::write(&fname, "b a 2\na b 2\nb b 1\n").unwrap();
+    let config = test_builder("ngrams_result_unsorted/ngrams_result/")
+        .ngram_file(2, &fname)
+        .build()
+        .unwrap();
+    match generate(&config) {
+        Err(Error::Parse {
+            path,
+            line_number,
+            line,
+            ..
+        }) => {
+            assert_eq!(path, PathBuf::from(&fname));
+            assert_eq!(line_number, 3);
+            assert_eq!(line, "b b 1");
+        }
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_sort_ngrams() {
+    let dir = format!("{}ngrams_result_sort/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let fname = format!("{}2gms_unsorted.txt", dir);
+    fs::write(&fname, "b a 2\na b 2\nb b 1\n").unwrap();
+    let sorted = test_builder("ngrams_result_sort/ngrams_result/")
+        .ngram_file(2, &fname)
+        .sort_ngrams(true)
+        .build()
+        .unwrap();
+
+    // Sorted, the ngrams give the same model as the sorted test data
+    generate(&sorted).unwrap();
+    let expected = test_config("ngrams_result_sort/expected/", 100_000, 3);
+    generate(&expected).unwrap();
+    assert_same_ngrams(&sorted, &expected);
+}
+
+#[test]
+fn test_missing_order_file() {
+    let config = test_builder("ngrams_result_missing/")
+        .max_ngram_len(5)
+        .build()
+        .unwrap();
+    match generate(&config) {
+        Err(Error::MissingOrderFile { n, .. }) => assert_eq!(n, 5),
+        other => panic!("Expected a missing file error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_model_tables() {
+    let config = test_config("ngrams_result_tables/", 100_000, 3);
+    generate(&config).unwrap();
+
+    let model = ModelTables::load(&config.output_dir, 3).unwrap();
+    assert_eq!(model.symbols, vec!["a".to_string(), "b".to_string()]);
+    assert_eq!(model.max_ngram_len(), 3);
+    let language_model = LanguageModel::from_tables(model.clone()).unwrap();
+    assert_eq!(language_model.symbol("b"), Some(1));
+    assert_eq!(language_model.symbol("c"), None);
+
+    // "b b a" is the last trigram and has the suffix "b a"
+    let record = model.get(&[1, 1, 0]).unwrap();
+    assert_eq!(record.log_prob, 0.0);
+    assert_eq!(model.find(&[1, 0]), Some(record.suffix as usize));
+    assert_eq!(model.get(&[0, 1]).unwrap().children(), 0..2);
+    assert!(model.get(&[0, 0]).is_none());
+}
+
+#[test]
+fn test_language_model_from_inconsistent_tables() {
+    let config = test_config("ngrams_result_inconsistent_tables/", 100_000, 3);
+    generate(&config).unwrap();
+    let tables = ModelTables::load(&config.output_dir, 3).unwrap();
+    assert!(LanguageModel::from_tables(tables.clone()).is_ok());
+
+    // A unigram without a symbol
+    let mut extra_unigram = tables.clone();
+    let unigram = extra_unigram.ngrams[0][0];
+    extra_unigram.ngrams[0].push(unigram);
+    assert!(matches!(
+        LanguageModel::from_tables(extra_unigram),
+        Err(Error::InconsistentModel(_))
+    ));
+
+    // A symbol without a unigram
+    let mut extra_symbol = tables.clone();
+    extra_symbol.symbols.push("c".to_string());
+    assert!(matches!(
+        LanguageModel::from_tables(extra_symbol),
+        Err(Error::InconsistentModel(_))
+    ));
+
+    // A trigram whose label is not a symbol
+    let mut invalid_label = tables;
+    invalid_label.ngrams[2][0].label = 2;
+    assert!(matches!(
+        LanguageModel::from_tables(invalid_label),
+        Err(Error::InconsistentModel(_))
+    ));
+}
+
+/// Sum of the probabilities of all words of the vocabulary after each unigram history of a backoff model
+fn bigram_probability_sums(model: &ModelTables) -> Vec<f64> {
+    let vocab_size = model.symbols.len() as u32;
+    (0..vocab_size)
+        .map(|history| {
+            let backoff = model.ngrams[0][history as usize].backoff as f64;
+            (0..vocab_size)
+                .map(|word| match model.get(&[history, word]) {
+                    Some(record) => (record.log_prob as f64).exp(),
+                    None => (backoff + model.ngrams[0][word as usize].log_prob as f64).exp(),
+                })
+                .sum()
+        })
+        .collect()
+}
+
+#[test]
+fn test_smoothing() {
+    for (folder_result, smoothing) in [
+        (
+            "ngrams_result_absolute/",
+            Smoothing::AbsoluteDiscounting { discount: None },
+        ),
+        ("ngrams_result_kneser_ney/", Smoothing::ModifiedKneserNey),
+        ("ngrams_result_katz/", Smoothing::Katz { max_count: 5 }),
+    ] {
+        let config = test_builder(folder_result)
+            .smoothing(smoothing)
+            .build()
+            .unwrap();
+        generate(&config).unwrap();
+        let model = ModelTables::load(&config.output_dir, 3).unwrap();
+
+        let unigram_sum: f64 = model.ngrams[0]
+            .iter()
+            .map(|record| (record.log_prob as f64).exp())
+            .sum();
+        assert!((unigram_sum - 1.0).abs() < 1e-5, "{}", smoothing);
+        for sum in bigram_probability_sums(&model) {
+            assert!((sum - 1.0).abs() < 1e-5, "{}: {}", smoothing, sum);
+        }
+    }
+}
+
+#[test]
+fn test_stupid_backoff() {
+    let config = test_builder("ngrams_result_stupid/")
+        .smoothing("stupid:0.5".parse().unwrap())
+        .build()
+        .unwrap();
+    generate(&config).unwrap();
+
+    // The scores are the same as without smoothing, but every history now has a backoff weight
+    assert_file_content(
+        config.output_file(1),
+        &["-0.6931472 0 1 -0.6931472", "-0.6931472 1 2 -0.6931472"],
+    );
+    assert_file_content(
+        config.output_file(3),
+        &[
+            "0 -0.6931472 1",
+            "1 -0.6931472 2",
+            "1 -0.6931472 0",
+            "0 0 1",
+        ],
+    );
+}
+
+#[test]
+fn test_arpa_export() {
+    let arpa_file = format!("{}ngrams_result_arpa/model.arpa", TEST_ROOT);
+    let config = test_builder("ngrams_result_arpa/")
+        .arpa_file(&arpa_file)
+        .build()
+        .unwrap();
+    generate(&config).unwrap();
+
+    let correct_content = [
+        "",
+        "\\data\\",
+        "ngram 1=2",
+        "ngram 2=3",
+        "ngram 3=4",
+        "",
+        "\\1-grams:",
+        "-0.30103\ta",
+        "-0.30103\tb",
+        "",
+        "\\2-grams:",
+        "-0.17609124\ta\tb",
+        "-0.17609124\tb\ta",
+        "-0.47712126\tb\tb",
+        "",
+        "\\3-grams:",
+        "-0.30103\ta\tb\ta",
+        "-0.30103\ta\tb\tb",
+        "-0.30103\tb\ta\tb",
+        "0\tb\tb\ta",
+        "",
+        "\\end\\",
+    ];
+    assert_file_content(&arpa_file, &correct_content);
+
+    // Exporting the loaded tables gives the same file
+    let exported_file = format!("{}ngrams_result_arpa/exported.arpa", TEST_ROOT);
+    ModelTables::load(&config.output_dir, 3)
+        .unwrap()
+        .write_arpa(&exported_file)
+        .unwrap();
+    assert_file_content(&exported_file, &correct_content);
+}
+
+#[test]
+fn test_arpa_import() {
+    let folder = format!("{}ngrams_result_arpa_import/", TEST_ROOT);
+    let arpa_file = format!("{}model.arpa", folder);
+    let dictionary = format!("{}dict/words_allow.txt", TEST_ROOT);
+    let config = test_builder("ngrams_result_arpa_import/")
+        .smoothing(Smoothing::ModifiedKneserNey)
+        .arpa_file(&arpa_file)
+        .build()
+        .unwrap();
+    generate(&config).unwrap();
+    let generated = ModelTables::load(&folder, 3).unwrap();
+
+    // Importing the exported model gives the same tables up to rounding
+    let imported = arpa::import_arpa(&arpa_file, &dictionary, 100_000, false).unwrap();
+    assert_eq!(imported.symbols, generated.symbols);
+    for (imported, generated) in imported.ngrams.iter().zip(&generated.ngrams) {
+        assert_eq!(imported.len(), generated.len());
+        for (imported, generated) in imported.iter().zip(generated) {
+            assert_eq!(
+                (imported.label, imported.suffix, imported.children()),
+                (generated.label, generated.suffix, generated.children())
+            );
+            assert!((imported.log_prob - generated.log_prob).abs() < 1e-5);
+            assert!((imported.backoff - generated.backoff).abs() < 1e-5);
+        }
+    }
+
+    // Only "b" is more probable than "a", so only the ngrams made up of "b" remain
+    let imported = arpa::import_arpa(&arpa_file, &dictionary, 1, false).unwrap();
+    assert_eq!(imported.symbols, vec!["b".to_string()]);
+    assert_eq!(imported.ngrams[1].len(), 1);
+    assert_eq!(imported.ngrams[0][0].children(), 0..1);
+    assert!(imported.ngrams[2].is_empty());
+
+    imported.write(format!("{}truncated/", folder)).unwrap();
+    assert_file_content(format!("{}truncated/symt.txt", folder), &["b"]);
+}
+
+#[test]
+fn test_arpa_import_reserved_symbols() {
+    let folder = format!("{}ngrams_result_arpa_import_reserved/", TEST_ROOT);
+    fs::create_dir_all(&folder).unwrap();
+    let arpa_file = format!("{}model.arpa", folder);
+    let dictionary = format!("{}words.txt", folder);
+    fs::write(&dictionary, "a\nb\nc\n").unwrap();
+    fs::write(
+        &arpa_file,
+        "\\data\\\nngram 1=6\nngram 2=4\n\n\\1-grams:\n\
+         -99\t<s>\t-0.5\n-0.5\t</s>\n-1.5\t<unk>\n-0.6\ta\t-0.2\n-0.7\tb\n-0.9\tc\n\n\
+         \\2-grams:\n-0.1\t<s> a\n-0.3\ta </s>\n-0.4\ta <unk>\n-0.2\ta c\n\n\\end\\\n",
+    )
+    .unwrap();
+
+    // Without reserved symbols, only the words of the dictionary are kept
+    let imported = arpa::import_arpa(&arpa_file, &dictionary, 2, false).unwrap();
+    assert_eq!(imported.symbols, vec!["a", "b"]);
+    assert!(imported.ngrams[1].is_empty());
+
+    // The reserved symbols are kept in the order of the file and don't take up places of the vocabulary,
+    // the ngram with "c", which is not kept, is still dropped
+    let imported = arpa::import_arpa(&arpa_file, &dictionary, 2, true).unwrap();
+    assert_eq!(imported.symbols, vec!["<s>", "</s>", "<unk>", "a", "b"]);
+    let bigrams: Vec<(u32, u32)> = imported.ngrams[1]
+        .iter()
+        .map(|record| (record.label, record.suffix))
+        .collect();
+    assert_eq!(bigrams, vec![(3, 3), (1, 1), (2, 2)]);
+    assert_eq!(imported.ngrams[0][0].children(), 0..1);
+    assert_eq!(imported.ngrams[0][3].children(), 1..3);
+    assert_eq!(imported.ngrams[0][0].log_prob, f32::NEG_INFINITY);
+
+    // Unknown words get the probability of <unk>
+    let model = LanguageModel::from_tables(imported).unwrap();
+    assert_eq!(model.log_prob(&["a", "d"]), model.log_prob(&["a", "<unk>"]));
+}
+
+#[test]
+fn test_binary_model() {
+    let folder = format!("{}ngrams_result_binary/", TEST_ROOT);
+    let binary_file = format!("{}model.bin", folder);
+    let config = test_builder("ngrams_result_binary/")
+        .smoothing(Smoothing::ModifiedKneserNey)
+        .binary_file(&binary_file)
+        .build()
+        .unwrap();
+    generate(&config).unwrap();
+
+    // The binary file contains exactly the same tables as the text files
+    let generated = ModelTables::load(&folder, 3).unwrap();
+    let loaded = ModelTables::load_binary(&binary_file).unwrap();
+    assert_eq!(loaded.symbols, generated.symbols);
+    assert_eq!(loaded.ngrams, generated.ngrams);
+
+    // The memory mapped model answers queries like the one loaded into memory
+    let mapped = LanguageModel::map(&binary_file).unwrap();
+    let model = LanguageModel::from_tables(generated.clone()).unwrap();
+    assert!(mapped.tables().is_none());
+    assert_eq!(mapped.no_ngrams(3), generated.ngrams[2].len());
+    for ngram in [&["a", "b", "a"][..], &["b", "a", "a"], &["c", "b"], &["b"]] {
+        assert_eq!(mapped.log_prob(ngram), model.log_prob(ngram));
+    }
+    assert_eq!(
+        mapped.predict(&["b", "b"], "", 5),
+        model.predict(&["b", "b"], "", 5)
+    );
+
+    // Writing the loaded tables again gives the same file
+    let rewritten = format!("{}rewritten.bin", folder);
+    loaded.write_binary(&rewritten, None).unwrap();
+    assert_eq!(
+        std::fs::read(&rewritten).unwrap(),
+        std::fs::read(&binary_file).unwrap()
+    );
+
+    // Files that are not in the binary format or were cut off are rejected
+    let symt = config.symbol_table_file();
+    assert!(matches!(
+        LanguageModel::map(&symt),
+        Err(Error::InvalidModelFile { .. })
+    ));
+    let bytes = std::fs::read(&binary_file).unwrap();
+    let truncated = format!("{}truncated.bin", folder);
+    std::fs::write(&truncated, &bytes[..bytes.len() - 1]).unwrap();
+    assert!(matches!(
+        LanguageModel::map(&truncated),
+        Err(Error::InvalidModelFile { .. })
+    ));
+}
+
+#[test]
+fn test_binary_model_inconsistent_records() {
+    let folder = format!("{}ngrams_result_binary_inconsistent/", TEST_ROOT);
+    let config = test_config("ngrams_result_binary_inconsistent/", 100_000, 3);
+    generate(&config).unwrap();
+    let tables = ModelTables::load(&folder, 3).unwrap();
+    let is_rejected = |name: &str, tables: &ModelTables| {
+        let binary_file = format!("{}{}.bin", folder, name);
+        tables.write_binary(&binary_file, None).unwrap();
+        matches!(
+            LanguageModel::map(&binary_file),
+            Err(Error::InvalidModelFile { .. })
+        )
+    };
+    assert!(!is_rejected("model", &tables));
+
+    // A symbol without a unigram
+    let mut extra_symbol = tables.clone();
+    extra_symbol.symbols.push("c".to_string());
+    assert!(is_rejected("extra_symbol", &extra_symbol));
+
+    // A trigram whose label is not a symbol
+    let mut invalid_label = tables.clone();
+    invalid_label.ngrams[2][0].label = 2;
+    assert!(is_rejected("invalid_label", &invalid_label));
+
+    // Children beyond the end of the trigrams
+    let mut out_of_bounds = tables.clone();
+    out_of_bounds.ngrams[1][0].no_longer_ngrams = 10;
+    assert!(is_rejected("out_of_bounds", &out_of_bounds));
+
+    // Unsorted children in a file whose header claims they are sorted, the flag of the bigrams comes after their number
+    let mut unsorted = tables;
+    let children = unsorted.ngrams[0][1].children();
+    assert_eq!(children.len(), 2);
+    unsorted.ngrams[1].swap(children.start, children.start + 1);
+    let binary_file = format!("{}unsorted.bin", folder);
+    unsorted.write_binary(&binary_file, None).unwrap();
+    assert!(LanguageModel::map(&binary_file).is_ok());
+    let mut bytes = std::fs::read(&binary_file).unwrap();
+    let flag = 28 + 8 + 4;
+    assert_eq!(bytes[flag], 0);
+    bytes[flag] = 1;
+    std::fs::write(&binary_file, bytes).unwrap();
+    assert!(matches!(
+        LanguageModel::map(&binary_file),
+        Err(Error::InvalidModelFile { .. })
+    ));
+}
+
+#[test]
+fn test_quantization() {
+    let folder = format!("{}ngrams_result_quantization/", TEST_ROOT);
+    let binary_file = format!("{}model.bin", folder);
+    let quantization: Quantization = "kmeans:8".parse().unwrap();
+    assert_eq!(quantization, Quantization::KMeans { bits: 8 });
+    assert_eq!(
+        "uniform".parse::<Quantization>().unwrap(),
+        Quantization::Uniform { bits: 8 }
+    );
+    assert!("uniform:12".parse::<Quantization>().is_err());
+    assert!("float".parse::<Quantization>().is_err());
+
+    let config = test_builder("ngrams_result_quantization/")
+        .smoothing(Smoothing::ModifiedKneserNey)
+        .binary_file(&binary_file)
+        .quantization(quantization)
+        .build()
+        .unwrap();
+    generate(&config).unwrap();
+    let tables = ModelTables::load(&folder, 3).unwrap();
+
+    // The few values of the test data all get a bin of their own, so nothing changes
+    let loaded = ModelTables::load_binary(&binary_file).unwrap();
+    assert_eq!(loaded.ngrams, tables.ngrams);
+    // A quantized binary file contains the same values as the tables quantized in memory
+    for quantization in [
+        Quantization::Uniform { bits: 8 },
+        Quantization::KMeans { bits: 8 },
+    ] {
+        let codebook = quantization.codebook([-3.0, -2.0, -1.0, f32::NEG_INFINITY].into_iter());
+        assert_eq!(
+            codebook.decode(codebook.encode(f32::NEG_INFINITY)),
+            f32::NEG_INFINITY
+        );
+        let closest = codebook.decode(codebook.encode(-2.0));
+        assert!((closest + 2.0).abs() < 0.01);
+
+        let quantized = quantization.apply(&tables).unwrap();
+        let file = format!("{}{}.bin", folder, quantization);
+        tables.write_binary(&file, Some(quantization)).unwrap();
+        assert_eq!(
+            ModelTables::load_binary(&file).unwrap().ngrams,
+            quantized.ngrams
+        );
+    }
+
+    let heldout_file = format!("{}heldout.txt", folder);
+    std::fs::write(&heldout_file, "a b a b\nb b a\n").unwrap();
+    let report = quantize::perplexity_impact(&tables, quantization, &heldout_file).unwrap();
+    assert_eq!(report.no_words, 7);
+    // The same perplexity `evaluate` reports
+    let model = LanguageModel::from_tables(tables.clone()).unwrap();
+    let evaluation = evaluate::evaluate(&model, &heldout_file).unwrap();
+    assert_eq!(report.perplexity, evaluation.perplexity());
+    assert!((report.quantized_perplexity - report.perplexity).abs() < 1e-3);
+    assert!(report.quantized_size < report.size);
+}
+
+#[test]
+fn test_memory_budget() {
+    // All ngrams of up to 4 words from the vocabulary "a" and "b"
+    let mut ngrams: Vec<Vec<&str>> = Vec::new();
+    let mut longest: Vec<Vec<&str>> = vec![Vec::new()];
+    for _ in 0..4 {
+        longest = longest
+            .iter()
+            .flat_map(|ngram| ["a", "b"].map(|word| [&ngram[..], &[word]].concat()))
+            .collect();
+        ngrams.extend(longest.iter().cloned());
+    }
+
+    let read_dir = |config: &GeneratorConfig| -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files: Vec<_> = std::fs::read_dir(&config.output_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .map(|path| {
+                (
+                    path.strip_prefix(&config.output_dir).unwrap().to_path_buf(),
+                    std::fs::read(&path).unwrap(),
+                )
+            })
+            .collect();
+        files.sort();
+        files
+    };
+
+    for smoothing in [Smoothing::MaximumLikelihood, Smoothing::ModifiedKneserNey] {
+        let builder = |folder| test_builder(folder).max_ngram_len(4).smoothing(smoothing);
+        let in_memory = builder("ngrams_result_in_memory/").build().unwrap();
+        // A budget of 1 byte spills every ngram to its own run
+        let spilled = builder("ngrams_result_spilled/")
+            .memory_budget(1)
+            .build()
+            .unwrap();
+        let sorted = builder("ngrams_result_sorted/")
+            .memory_budget(1 << 20)
+            .build()
+            .unwrap();
+        let summary = order_counts(&generate(&in_memory).unwrap());
+        assert_eq!(order_counts(&generate(&spilled).unwrap()), summary);
+        assert_eq!(order_counts(&generate(&sorted).unwrap()), summary);
+
+        // The runs are removed and the result does not depend on the number of runs
+        assert_eq!(read_dir(&spilled), read_dir(&sorted));
+        assert_eq!(read_dir(&spilled).len(), 5);
+
+        // The tables are in another order, but they contain the same ngrams
+        let expected = LanguageModel::load(&in_memory.output_dir, 4).unwrap();
+        let model = LanguageModel::load(&spilled.output_dir, 4).unwrap();
+        for n in 1..=4 {
+            assert_eq!(model.no_ngrams(n), expected.no_ngrams(n));
+        }
+        for ngram in &ngrams {
+            assert_eq!(model.log_prob(ngram), expected.log_prob(ngram));
+        }
+    }
+}
+
+#[test]
+fn test_external_sorter_passes() {
+    use crate::external::ExternalSorter;
+    use std::collections::BTreeMap;
+
+    let dir = format!("{}ngrams_result_external_sorter/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    // A budget of 1 byte spills every ngram to its own run and merges 2 runs at once,
+    // one of 3 buffers of 64 KiB merges 3 runs of 12288 bigrams at once
+    for (memory_budget, no_ngrams) in [(1, 300), (3 << 16, 5 * 12288)] {
+        let mut sorter = ExternalSorter::new(&dir, 2, memory_budget);
+        let mut expected: BTreeMap<Vec<u32>, u32> = BTreeMap::new();
+        // Pseudo random bigrams with many duplicates
+        let mut state = 12345u32;
+        for _ in 0..no_ngrams {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let symbols = [(state >> 16) % 50, (state >> 8) % 50];
+            sorter.push(&symbols, 1 + state % 3).unwrap();
+            *expected.entry(symbols.to_vec()).or_default() += 1 + state % 3;
+        }
+        let merged: Vec<(Vec<u32>, u32)> = sorter.merge().unwrap().map(Result::unwrap).collect();
+        assert_eq!(merged, expected.into_iter().collect::<Vec<_>>());
+        // The runs of all passes are removed
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+}
+
+/// Bigrams of the test data among many with unknown words, which spread them over several chunks and batches
+fn many_bigrams() -> String {
+    let mut bigrams = String::from("a b 2\n");
+    for i in 0..100_000 {
+        bigrams.push_str(&format!("a x{} 1\n", i));
+    }
+    bigrams.push_str("b a 2\nb b 1\n");
+    bigrams
+}
+
+#[test]
+fn test_threads() {
+    let dir = format!("{}ngrams_result_threads/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let fname = format!("{}2gms.txt", dir);
+    fs::write(&fname, many_bigrams()).unwrap();
+    let builder = test_builder("ngrams_result_threads/sequential/")
+        .ngram_file(2, &fname)
+        .smoothing(Smoothing::ModifiedKneserNey);
+    let sequential = builder.clone().build().unwrap();
+    let parallel = builder
+        .output_dir(format!("{}parallel/", dir))
+        .threads(4)
+        .build()
+        .unwrap();
+
+    let summary = order_counts(&generate(&sequential).unwrap());
+    assert_eq!(order_counts(&generate(&parallel).unwrap()), summary);
+    assert_eq!(summary[1].1.ngrams, 100_003);
+    assert_eq!(summary[1].2.ngrams, 3);
+    assert_same_ngrams(&parallel, &sequential);
+}
+
+#[test]
+fn test_threads_parse_error() {
+    let dir = format!("{}ngrams_result_threads_error/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let fname = format!("{}2gms.txt", dir);
+    fs::write(&fname, many_bigrams().replace("a x70000 1\n", "a x70000\n")).unwrap();
+
+    // The error is the same as on one thread, although the workers parse the following chunks as well
+    for threads in [1, 4] {
+        let config = test_builder("ngrams_result_threads_error/ngrams_result/")
+            .ngram_file(2, &fname)
+            .threads(threads)
+            .build()
+            .unwrap();
+        match generate(&config) {
+            Err(Error::Parse {
+                line_number, line, ..
+            }) => {
+                assert_eq!(line_number, 70_002);
+                assert_eq!(line, "a x70000");
+            }
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+#[cfg(all(feature = "gzip", feature = "zstd", feature = "xz", feature = "bzip2"))]
+fn test_compressed_input() {
+    use std::io::Write;
+
+    let dir = format!("{}ngrams_result_compressed/", TEST_ROOT);
+    let expected = test_config("ngrams_result_compressed/expected/", 100_000, 3);
+    generate(&expected).unwrap();
+    let read_input =
+        |n: usize| fs::read_to_string(format!("{}ngrams_ALL/{}gms.txt", TEST_ROOT, n)).unwrap();
+    type Compress = fn(&str) -> Vec<u8>;
+    let compressions: [(&str, Compress); 4] = [
+        ("gz", |data| {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        }),
+        ("zst", |data| zstd::encode_all(data.as_bytes(), 0).unwrap()),
+        ("xz", |data| {
+            let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+            encoder.write_all(data.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        }),
+        ("bz2", |data| {
+            let mut encoder =
+                bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+            encoder.write_all(data.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        }),
+    ];
+
+    for (extension, compress) in compressions {
+        let input_dir = format!("{}{}/ngrams_ALL/", dir, extension);
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(
+            format!("{}1gms.txt.{}", input_dir, extension),
+            compress(&read_input(1)),
+        )
+        .unwrap();
+        // The bigrams are two streams, one after the other
+        let bigrams = read_input(2);
+        let (first, second) = bigrams.split_at(bigrams.find("b a").unwrap());
+        fs::write(
+            format!("{}2gms.{}", input_dir, extension),
+            [compress(first), compress(second)].concat(),
+        )
+        .unwrap();
+        // The trigrams are split into two shards
+        let trigrams = read_input(3);
+        let (first, second) = trigrams.split_at(trigrams.find("b a b").unwrap());
+        for (i, shard) in [first, second].into_iter().enumerate() {
+            fs::write(
+                format!("{}3gms-{:05}-of-00002.{}", input_dir, i, extension),
+                compress(shard),
+            )
+            .unwrap();
+        }
+
+        let config = test_builder(&format!(
+            "ngrams_result_compressed/{}/ngrams_result/",
+            extension
+        ))
+        .ngrams_dir(&input_dir)
+        .build()
+        .unwrap();
+        assert_eq!(
+            config.ngram_files[2],
+            PathBuf::from(format!("{}3gms-00000-of-00002.{}", input_dir, extension))
+        );
+        generate(&config).unwrap();
+        assert_same_ngrams(&config, &expected);
+    }
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn test_compression_detection() {
+    use std::io::Write;
+
+    // Without a known extension the compression is detected from the first bytes
+    let dir = format!("{}ngrams_result_compression_detection/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"a b\n").unwrap();
+    let fname = format!("{}gzipped", dir);
+    fs::write(&fname, encoder.finish().unwrap()).unwrap();
+    let lines = LinesIterator::new(&fname).unwrap();
+    assert_eq!(lines.map(Result::unwrap).collect::<Vec<_>>(), ["a b"]);
+}
+
+#[test]
+fn test_shard_parse_error() {
+    // Errors name the shard and the line in it
+    let dir = format!("{}ngrams_result_shards/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    for (i, shard) in ["a b 2\n", "b a 2\nb b\n"].into_iter().enumerate() {
+        fs::write(format!("{}2gms-{}-of-2.txt", dir, i), shard).unwrap();
+    }
+    let mut ngrams = NGramIterator::new(format!("{}2gms-1-of-2.txt", dir), 2).unwrap();
+    assert_eq!(ngrams.next().unwrap().unwrap().1, 2);
+    assert_eq!(ngrams.next().unwrap().unwrap().1, 2);
+    match ngrams.next().unwrap() {
+        Err(Error::Parse {
+            path, line_number, ..
+        }) => {
+            assert_eq!(path, PathBuf::from(format!("{}2gms-1-of-2.txt", dir)));
+            assert_eq!(line_number, 2);
+        }
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_google_books_format() {
+    let dir = format!("{}ngrams_result_google_books/", TEST_ROOT);
+    let input_dir = format!("{}ngrams_ALL/", dir);
+    fs::create_dir_all(&input_dir).unwrap();
+    // Every ngram of the test data is split over two years, with an extra year that is out of the range
+    // and a variant with a tag that is skipped
+    for n in 1..=3 {
+        let ngrams = fs::read_to_string(format!("{}ngrams_ALL/{}gms.txt", TEST_ROOT, n)).unwrap();
+        let mut lines = String::new();
+        for (words, count) in ngrams.lines().filter_map(|line| line.rsplit_once(' ')) {
+            let count: u32 = count.parse().unwrap();
+            lines.push_str(&format!("{}\t1850\t100\t1\n", words));
+            lines.push_str(&format!("{}\t1990\t1\t1\n", words));
+            if count > 1 {
+                lines.push_str(&format!("{}\t2000\t{}\t1\n", words, count - 1));
+            }
+            lines.push_str(&format!("{}_NOUN\t2000\t7\t1\n", words));
+        }
+        lines.push_str(&format!("{}_START_\t2000\t7\t1\n", "a ".repeat(n - 1)));
+        fs::write(format!("{}{}gms.txt", input_dir, n), lines).unwrap();
+    }
+    let format: InputFormat = "google-books:1900-:skip".parse().unwrap();
+    assert_eq!(
+        format,
+        InputFormat::GoogleBooks {
+            first_year: 1900,
+            last_year: u32::MAX,
+            pos_tags: PosTags::Skip,
+        }
+    );
+    let config = test_builder("ngrams_result_google_books/ngrams_result/")
+        .ngrams_dir(&input_dir)
+        .input_format(format)
+        .build()
+        .unwrap();
+    let expected = test_config("ngrams_result_google_books/expected/", 30_000, 3);
+    generate(&config).unwrap();
+    generate(&expected).unwrap();
+    assert_same_ngrams(&config, &expected);
+}
+
+#[test]
+fn test_strip_tags_apart() {
+    // The stripped variants of "run" are not next to each other
+    let builder = write_test_data(
+        "ngrams_result_strip_apart/",
+        &[
+            "run\t2000\t3\t1\nrun's\t2000\t1\t1\nrun_NOUN\t2000\t2\t1\nwalk\t2000\t4\t1\n",
+            "run walk\t2000\t1\t1\nrun run's\t2000\t1\t1\nrun_NOUN walk\t2000\t2\t1\n",
+        ],
+        "run\nrun's\nwalk\n",
+    )
+    .input_format("google-books:strip".parse().unwrap());
+
+    // Longer ngrams that became the same need to be sorted to be merged
+    match generate(&builder.clone().build().unwrap()) {
+        Err(Error::Parse {
+            line_number, line, ..
+        }) => {
+            assert_eq!(line_number, 3);
+            assert_eq!(line, "run walk 2");
+        }
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+
+    // The unigrams are merged anyway, so every word has one symbol
+    let config = builder.sort_ngrams(true).build().unwrap();
+    let summary = generate(&config).unwrap();
+    assert_file_content(config.symbol_table_file(), &["run", "run's", "walk"]);
+    assert_eq!(summary.orders[1].kept, ngram_counts(2, 4));
+    let model = LanguageModel::load(&config.output_dir, 2).unwrap();
+    assert!((model.log_prob(&["run"]).unwrap() - 0.5f32.ln()).abs() < 1e-6);
+    assert!((model.log_prob(&["run", "walk"]).unwrap() - 0.6f32.ln()).abs() < 1e-6);
+}
+
+#[test]
+fn test_google_books_strip_tags() {
+    // Stripped tags merge the ngrams that follow each other
+    let dir = format!("{}ngrams_result_google_books_strip/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let fname = format!("{}2gms_tagged.txt", dir);
+    fs::write(
+        &fname,
+        "a b\t2000\t1\t1\na_DET b\t2000\t2\t1\na_DET b_NOUN\t2000\t4\t1\nb a\t2000\t8\t1\nb a\tyear\t1\t1\n",
+    )
+    .unwrap();
+    let format = "google-books:strip".parse().unwrap();
+    let mut ngrams = NGramIterator::with_format(&fname, 2, format).unwrap();
+    let words = |words: &[&str]| {
+        words
+            .iter()
+            .map(|word| word.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ngrams.next().unwrap().unwrap(), (words(&["a", "b"]), 7));
+    assert_eq!(ngrams.line_number(), 1);
+    assert_eq!(ngrams.next().unwrap().unwrap(), (words(&["b", "a"]), 8));
+    assert_eq!(ngrams.line_number(), 4);
+    match ngrams.next().unwrap() {
+        Err(Error::Parse { line_number, .. }) => assert_eq!(line_number, 5),
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+    assert!(ngrams.next().is_none());
+}
+
+#[test]
+fn test_corpus_counter() {
+    let dir = format!("{}ngrams_result_corpus/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let corpus = format!("{}corpus.txt", dir);
+    fs::write(&corpus, "a b a\n\nb b\na b\n").unwrap();
+
+    let mut counter = CorpusCounter::new(3, CorpusFormat::SentencePerLine);
+    counter.add_file(&corpus).unwrap();
+    assert_eq!(counter.no_sentences(), 3);
+    let bigrams: Vec<(Vec<String>, u32)> = counter.ngrams(2).unwrap().map(Result::unwrap).collect();
+    let bigram = |words: &str, count| {
+        let words = words.split(' ').map(|word| word.to_string()).collect();
+        (words, count)
+    };
+    // Sorted by the order the words first appeared in: <s>, a, b, </s>
+    assert_eq!(
+        bigrams,
+        [
+            bigram("<s> a", 2),
+            bigram("<s> b", 1),
+            bigram("a b", 2),
+            bigram("a </s>", 1),
+            bigram("b a", 1),
+            bigram("b b", 1),
+            bigram("b </s>", 2),
+        ]
+    );
+}
+
+#[test]
+fn test_corpus_counter_memory_budget() {
+    let dir = format!("{}ngrams_result_corpus_spilled/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let corpus = format!("{}corpus.txt", dir);
+    fs::write(&corpus, "a b a\n\nb b\na b\n").unwrap();
+
+    // Spilling after every sentence gives the same files
+    let in_memory = format!("{}in_memory/", dir);
+    let spilled = format!("{}spilled/", dir);
+    let mut counter = CorpusCounter::new(3, CorpusFormat::SentencePerLine);
+    counter.add_file(&corpus).unwrap();
+    counter.write_ngram_files(&in_memory).unwrap();
+    let mut counter =
+        CorpusCounter::new(3, CorpusFormat::SentencePerLine).memory_budget(1, &spilled);
+    counter.add_file(&corpus).unwrap();
+    let files = counter.write_ngram_files(&spilled).unwrap();
+    assert_eq!(fs::read_dir(&spilled).unwrap().count(), 3);
+    for (n, file) in files.iter().enumerate() {
+        assert_eq!(
+            fs::read(file).unwrap(),
+            fs::read(format!("{}{}gms.txt", in_memory, n + 1)).unwrap()
+        );
+    }
+
+    // The counted files can be turned into a model
+    let dictionary = format!("{}words.txt", dir);
+    fs::write(&dictionary, "<s>\n</s>\na\nb\n").unwrap();
+    let config = GeneratorConfig::builder()
+        .ngrams_dir(&spilled)
+        .dictionary(&dictionary)
+        .output_dir(format!("{}ngrams_result/", dir))
+        .build()
+        .unwrap();
+    let summary = generate(&config).unwrap();
+    assert_eq!(summary.orders[1].kept.ngrams, 7);
+    let model = LanguageModel::load(&config.output_dir, 3).unwrap();
+    assert!((model.log_prob(&["a", "b", "</s>"]).unwrap() - 0.5f32.ln()).abs() < 1e-6);
+}
+
+#[test]
+fn test_corpus_plain_text() {
+    // Running text is split into sentences and the punctuation into words of its own
+    let dir = format!("{}ngrams_result_corpus_text/", TEST_ROOT);
+    fs::create_dir_all(&dir).unwrap();
+    let text = format!("{}text.txt", dir);
+    fs::write(&text, "(A b.) B, a! A\nb\n\nb").unwrap();
+    let mut counter = CorpusCounter::new(1, CorpusFormat::PlainText);
+    counter.add_file(&text).unwrap();
+    assert_eq!(counter.no_sentences(), 4);
+    let unigrams: Vec<(Vec<String>, u32)> =
+        counter.ngrams(1).unwrap().map(Result::unwrap).collect();
+    let words: Vec<(&str, u32)> = unigrams
+        .iter()
+        .map(|(words, count)| (words[0].as_str(), *count))
+        .collect();
+    assert_eq!(
+        words,
+        [
+            ("<s>", 4),
+            ("(", 1),
+            ("A", 2),
+            ("b", 3),
+            (".", 1),
+            ("</s>", 4),
+            (")", 1),
+            ("B", 1),
+            (",", 1),
+            ("a", 1),
+            ("!", 1),
+        ]
+    );
+}
+
+#[test]
+fn test_reserved_symbols() {
+    let config = write_test_data(
+        "ngrams_result_reserved_symbols/",
+        &[
+            "<s> 4\na 5\nb 3\nc 2\n</s> 4\nx 1\n",
+            "<s> a 2\n<s> c 1\n<s> x 1\na b 3\na </s> 2\nb </s> 3\nc </s> 1\nx </s> 1\n",
+        ],
+        "a\nb\nc\n",
+    )
+    .max_no_words(2)
+    .reserved_symbols(true)
+    .build()
+    .unwrap();
+    let summary = generate(&config).unwrap();
+
+    // "c" is cut by the maximum number of words and "x" is not in the dictionary, so both become <unk>
+    assert_file_content(
+        config.symbol_table_file(),
+        &["<unk>", "<s>", "a", "b", "</s>"],
+    );
+    // The bigrams that became the same are merged
+    assert_eq!(summary.orders[1].kept.ngrams, 6);
+    assert_eq!(summary.orders[1].kept.cumulative_count, 14);
+
+    let model = LanguageModel::load(&config.output_dir, 2).unwrap();
+    assert_eq!(model.symbol("zebra"), model.symbol("<unk>"));
+    assert!((model.log_prob(&["<unk>"]).unwrap() - (3.0f32 / 19.0).ln()).abs() < 1e-6);
+    assert_eq!(
+        model.log_prob(&["<s>", "c"]),
+        model.log_prob(&["<s>", "<unk>"])
+    );
+    assert!((model.log_prob(&["<s>", "zebra"]).unwrap() - 0.5f32.ln()).abs() < 1e-6);
+
+    // Neither <unk> nor <s> are suggested
+    let candidates = model.next_word_candidates(&["<s>"], 10);
+    assert_eq!(candidates[0].0, "a");
+    assert!(candidates
+        .iter()
+        .all(|&(word, _)| word != "<unk>" && word != "<s>"));
+}
+
+#[test]
+fn test_vocabulary_tie_break() {
+    // 29990 words are more frequent than the 14 tied words, which are in reverse lexicographic order
+    let mut unigrams = String::new();
+    let mut words = String::new();
+    for i in 0..29_990 {
+        unigrams.push_str(&format!("w{:05} 100\n", i));
+        words.push_str(&format!("w{:05}\n", i));
+    }
+    for i in (0..14).rev() {
+        unigrams.push_str(&format!("t{:02} 10\n", i));
+        words.push_str(&format!("t{:02}\n", i));
+    }
+    let builder = write_test_data("ngrams_result_tie_break/", &[&unigrams], &words);
+    let tied_symbols = |tie_break: TieBreak| {
+        let config = builder
+            .clone()
+            .output_dir(format!(
+                "{}ngrams_result_tie_break/{}/",
+                TEST_ROOT, tie_break
+            ))
+            .max_no_words(30_000)
+            .tie_break(tie_break)
+            .build()
+            .unwrap();
+        let summary = generate(&config).unwrap();
+        let symbols = fs::read_to_string(config.symbol_table_file()).unwrap();
+        let tied: Vec<String> = symbols
+            .lines()
+            .filter(|word| word.starts_with('t'))
+            .map(String::from)
+            .collect();
+        assert_eq!(symbols.lines().count(), 29_990 + tied.len());
+        (tied, summary)
+    };
+
+    let (tied, summary) = tied_symbols(TieBreak::KeepAll);
+    assert_eq!(tied.len(), 14);
+    assert_eq!(summary.tied_words_dropped, 0);
+    assert_eq!(summary.tie_warning(), None);
+
+    // The first 10 tied words fill the vocabulary, in the order of the file or their lexicographic order
+    let warning = "Warning: 4 words were dropped from the vocabulary although they had the same count as its last word";
+    let (tied, summary) = tied_symbols(TieBreak::FirstSeen);
+    let expected: Vec<String> = (4..14).rev().map(|i| format!("t{:02}", i)).collect();
+    assert_eq!(tied, expected);
+    assert_eq!(summary.tied_words_dropped, 4);
+    assert_eq!(summary.tie_warning().as_deref(), Some(warning));
+
+    let (tied, summary) = tied_symbols(TieBreak::Lexicographic);
+    let expected: Vec<String> = (0..10).rev().map(|i| format!("t{:02}", i)).collect();
+    assert_eq!(tied, expected);
+    assert_eq!(summary.tied_words_dropped, 4);
+    assert_eq!(summary.tie_warning().as_deref(), Some(warning));
+}
+
+/// Builder for the unigrams of the vocabulary tests, "foo" is not in the dictionary
+fn vocabulary_test_data(folder: &str) -> GeneratorConfigBuilder {
+    write_test_data(
+        folder,
+        &["the 50\nof 20\nand 20\ncat 5\ndog 5\nzebra 1\nfoo 10\n"],
+        "the\nof\nand\ncat\ndog\nzebra\n",
+    )
+}
+
+/// The symbol table of the generated vocabulary and the number of tied words it dropped
+fn vocabulary(builder: GeneratorConfigBuilder) -> String {
+    let config = builder.build().unwrap();
+    let summary = generate(&config).unwrap();
+    let symbols = fs::read_to_string(config.symbol_table_file()).unwrap();
+    if summary.tied_words_dropped > 0 {
+        format!("{}{} dropped", symbols, summary.tied_words_dropped)
+    } else {
+        symbols
+    }
+}
+
+#[test]
+fn test_vocabulary_ties() {
+    // Words that are tied with the last of the most frequent ones are kept as well, unless the ties are broken
+    let builder = vocabulary_test_data("ngrams_result_vocabulary_ties/").max_no_words(2);
+    assert_eq!(vocabulary(builder.clone()), "the\nof\nand\n");
+    assert_eq!(
+        vocabulary(builder.clone().tie_break(TieBreak::FirstSeen)),
+        "the\nof\n1 dropped"
+    );
+    assert_eq!(
+        vocabulary(builder.tie_break(TieBreak::Lexicographic)),
+        "the\nand\n1 dropped"
+    );
+}
+
+#[test]
+fn test_vocabulary_min_count() {
+    let min_count = "min-count:6".parse().unwrap();
+    let builder = vocabulary_test_data("ngrams_result_vocabulary_min_count/").vocabulary(min_count);
+    assert_eq!(vocabulary(builder), "the\nof\nand\n");
+}
+
+#[test]
+fn test_vocabulary_coverage() {
+    // 50 + 20 of the 111 tokens cover half of them, "of" wins the tie because it comes first
+    let coverage = VocabularySelection::Coverage(0.5);
+    let builder = vocabulary_test_data("ngrams_result_vocabulary_coverage/").vocabulary(coverage);
+    assert_eq!(vocabulary(builder), "the\nof\n");
+    assert!("coverage:1.5".parse::<VocabularySelection>().is_err());
+}
+
+#[test]
+fn test_include_and_deny_words() {
+    // Included words are kept even if they are not in the dictionary, denied words never
+    let dir = format!("{}ngrams_result_vocabulary_lists/", TEST_ROOT);
+    let builder = vocabulary_test_data("ngrams_result_vocabulary_lists/");
+    let include_words = format!("{}include.txt", dir);
+    fs::write(&include_words, "foo\n").unwrap();
+    let deny_words = format!("{}deny.txt", dir);
+    fs::write(&deny_words, "the\n").unwrap();
+    let builder = builder
+        .max_no_words(3)
+        .include_words(&include_words)
+        .deny_words(&deny_words);
+    assert_eq!(vocabulary(builder), "of\nand\nfoo\n");
+}
+
+#[test]
+fn test_validate_model() {
+    for (folder, smoothing) in [
+        ("ngrams_result_validate_ml/", Smoothing::MaximumLikelihood),
+        ("ngrams_result_validate_kn/", Smoothing::ModifiedKneserNey),
+        ("ngrams_result_validate_katz/", "katz".parse().unwrap()),
+    ] {
+        let config = test_builder(folder)
+            .max_ngram_len(4)
+            .smoothing(smoothing)
+            .build()
+            .unwrap();
+        generate(&config).unwrap();
+        let tables = ModelTables::load(&config.output_dir, 4).unwrap();
+        let problems: Vec<String> = tables.validate().iter().map(Error::to_string).collect();
+        assert_eq!(problems, Vec::<String>::new(), "{}", smoothing);
+    }
+
+    let valid = ModelTables::load(format!("{}ngrams_result_validate_ml/", TEST_ROOT), 4).unwrap();
+    let problems = |corrupt: fn(&mut ModelTables)| {
+        let mut tables = valid.clone();
+        corrupt(&mut tables);
+        let problems: Vec<String> = tables.validate().iter().map(Error::to_string).collect();
+        problems
+    };
+    assert_eq!(
+        problems(|tables| tables.ngrams[0][0].log_prob = 0.0),
+        ["Inconsistent model: The probabilities of the unigrams sum up to 1.500, which is more than 1"]
+    );
+    // "b" has no children, so "b a" and "b b" have no prefix and can't be found as the suffixes of longer ngrams
+    assert_eq!(
+        problems(|tables| tables.ngrams[0][1].no_longer_ngrams = 0),
+        [
+            "Inconsistent model: The 2grams 1..3 are not the children of any 1gram",
+            "Inconsistent model: The suffix of the 3gram 0 is not in the model",
+            "Inconsistent model: The suffix of the 3gram 1 is not in the model",
+            "Inconsistent model: The suffix of the 3gram 3 is not in the model",
+            "Inconsistent model: The suffix of the 4gram 0 is not in the model",
+            "Inconsistent model: The suffix of the 4gram 2 is not in the model",
+        ]
+    );
+    // "a b" gets the child "b a b" of "b a" as well, which turns it into "a b b" with the wrong suffix
+    assert_eq!(
+        problems(|tables| tables.ngrams[1][0].no_longer_ngrams = 3),
+        [
+            "Inconsistent model: The probabilities of the children of the 2gram 0 sum up to 1.500, which is more than 1",
+            "Inconsistent model: The children 2..3 of the 2gram 1 overlap with the ones of the 2gram 0",
+            "Inconsistent model: The 3gram 2 has the suffix 0, but its suffix is the 2gram 2",
+            "Inconsistent model: The suffix of the 4gram 1 is not in the model",
+        ]
+    );
+    assert_eq!(
+        problems(|tables| tables.ngrams[3][0].suffix = 0),
+        ["Inconsistent model: The 4gram 0 has the suffix 0, but its suffix is the 3gram 2"]
+    );
+    assert_eq!(
+        problems(|tables| tables.ngrams[3][1].label = 7),
+        [
+            "Inconsistent model: The 4gram 1 has the label 7, but there are only 2 symbols",
+            "Inconsistent model: The suffix of the 4gram 1 is not in the model",
+        ]
+    );
+}
+
+#[test]
+fn test_missing_lower_order() {
+    // "a c", "c a" and "c b" were pruned from the bigrams.
+    // "a c b" and "c a b" lack their prefixes, "b c a" its suffix and "a b d" has a word that is not in the vocabulary.
+    let dir = format!("{}ngrams_result_missing_lower_order/", TEST_ROOT);
+    let builder = write_test_data(
+        "ngrams_result_missing_lower_order/",
+        &[
+            "a 4\nb 4\nc 4\nd 1\n",
+            "a b 3\nb c 3\nb a 1\n",
+            "a b c 2\na b d 1\na c b 1\nb c a 3\nc a b 4\n",
+        ],
+        "a\nb\nc\n",
+    );
+    for (folder, builder) in [
+        ("in_memory/", builder.clone()),
+        ("sorted_on_disk/", builder.memory_budget(1 << 20)),
+    ] {
+        let config = builder
+            .output_dir(format!("{}{}", dir, folder))
+            .build()
+            .unwrap();
+        let summary = generate(&config).unwrap();
+        let trigrams = summary.orders[2];
+        assert_eq!(trigrams.kept.ngrams, 1);
+        assert_eq!(trigrams.missing.prefix, ngram_counts(2, 5));
+        assert_eq!(trigrams.missing.suffix, ngram_counts(1, 3));
+        assert_eq!(summary.orders[1].missing, MissingLowerOrder::default());
+        // The ngram with the unknown word is skipped, but not counted as missing a lower order
+        assert_eq!(trigrams.skipped().ngrams, 4);
+
+        let tables = ModelTables::load(&config.output_dir, 3).unwrap();
+        assert!(tables.validate().is_empty());
+    }
+}
+
+/// Builder for the ngrams of the pruning tests with Kneser-Ney smoothing
+fn pruning_test_data(folder: &str) -> GeneratorConfigBuilder {
+    write_test_data(
+        folder,
+        &[
+            "a 5\nb 4\nc 4\nd 2\n",
+            "a b 3\na c 2\na d 1\nb a 2\nb c 1\nc a 3\nc b 1\n",
+            "a b c 1\na c a 1\nb a b 2\nc a b 2\nc a c 1\n",
+        ],
+        "a\nb\nc\nd\n",
+    )
+    .smoothing(Smoothing::ModifiedKneserNey)
+}
+
+#[test]
+fn test_pruning_min_counts() {
+    // The count cutoff of the bigrams also applies to the trigrams
+    let config = pruning_test_data("ngrams_result_pruning_min_counts/")
+        .pruning(Pruning {
+            min_counts: vec![2],
+            ..Pruning::default()
+        })
+        .build()
+        .unwrap();
+    let summary = generate(&config).unwrap();
+    assert_eq!(summary.orders[0].pruned, NGramCounts::default());
+    assert_eq!(summary.orders[1].pruned, ngram_counts(3, 3));
+    assert_eq!(summary.orders[1].kept, ngram_counts(4, 10));
+    assert_eq!(summary.orders[2].pruned, ngram_counts(3, 3));
+    assert_eq!(summary.orders[2].kept, ngram_counts(2, 4));
+    assert!(ModelTables::load(&config.output_dir, 3)
+        .unwrap()
+        .validate()
+        .is_empty());
+}
+
+#[test]
+fn test_pruning_max_continuations() {
+    // The most frequent continuations of each prefix
+    let config = pruning_test_data("ngrams_result_pruning_max_continuations/")
+        .pruning(Pruning {
+            max_continuations: vec![2, 1],
+            ..Pruning::default()
+        })
+        .build()
+        .unwrap();
+    let summary = generate(&config).unwrap();
+    assert_eq!(summary.orders[1].pruned, ngram_counts(1, 1));
+    assert_eq!(summary.orders[2].pruned, ngram_counts(1, 1));
+    assert_eq!(summary.orders[2].kept.ngrams, 4);
+}
+
+#[test]
+fn test_pruning_relative_entropy() {
+    let config = pruning_test_data("ngrams_result_pruning_entropy/")
+        .pruning(Pruning {
+            max_ngrams: Some(5),
+            ..Pruning::default()
+        })
+        .build()
+        .unwrap();
+    let summary = generate(&config).unwrap();
+    let kept: u32 = summary.orders[1..]
+        .iter()
+        .map(|order| order.kept.ngrams)
+        .sum();
+    let pruned: u32 = summary.orders[1..]
+        .iter()
+        .map(|order| order.pruned.ngrams)
+        .sum();
+    assert_eq!((kept, pruned), (5, 7));
+    let tables = ModelTables::load(&config.output_dir, 3).unwrap();
+    assert_eq!(
+        tables.ngrams[1..]
+            .iter()
+            .map(|table| table.len())
+            .sum::<usize>(),
+        5
+    );
+    let problems = tables.validate();
+    assert!(problems.is_empty(), "{:?}", problems);
+    // The backoff weights of the histories that lost children are recomputed
+    for sum in bigram_probability_sums(&tables) {
+        assert!((sum - 1.0).abs() < 1e-5, "{}", sum);
+    }
+}
+
+#[test]
+fn test_pruning_keeps_backoff_mass() {
+    // The probabilities of the unigrams sum up to 2, so without either bigram of "a" there would be no mass left
+    // to back off to the unigrams with, and their scores were infinite when nothing else was left to remove
+    let entry = |label, log_prob: f32, no_longer_ngrams| NGramEntry {
+        label,
+        log_prob,
+        count: 1,
+        suffix: label,
+        no_longer_ngrams,
+        ..NGramEntry::default()
+    };
+    let mut tables = vec![
+        NGramTable {
+            entries: vec![entry(0, 0.0, 2), entry(1, 0.0, 0)],
+            ..NGramTable::default()
+        },
+        NGramTable {
+            entries: vec![entry(0, 0.5f32.ln(), 0), entry(1, 0.5f32.ln(), 0)],
+            ..NGramTable::default()
+        },
+    ];
+    tables[0].entries[0].backoff = -1.0;
+    let removed = prune::prune_entropy(&mut tables, 0, Smoothing::ModifiedKneserNey);
+    assert_eq!(removed[1], NGramCounts::default());
+    assert_eq!(tables[1].entries.len(), 2);
+    assert_eq!(tables[0].entries[0].backoff, -1.0);
+}
+
+#[test]
+fn test_evaluate() {
+    let dir = format!("{}ngrams_result_evaluate/", TEST_ROOT);
+    let config = write_corpus_data(
+        "ngrams_result_evaluate/",
+        "a b a\nb b\na b\n",
+        "<s>\n</s>\na\nb\n",
+    )
+    .smoothing(Smoothing::ModifiedKneserNey)
+    .build()
+    .unwrap();
+    generate(&config).unwrap();
+    let model = LanguageModel::load(&config.output_dir, 3).unwrap();
+
+    let heldout_file = format!("{}heldout.txt", dir);
+    fs::write(&heldout_file, "a b\n\nb c\n").unwrap();
+    let evaluation = evaluate::evaluate(&model, &heldout_file).unwrap();
+    assert_eq!(evaluation.no_sentences, 2);
+    // The ends of the sentences are words, "c" is not in the vocabulary
+    assert_eq!(evaluation.no_words, 6);
+    assert_eq!(evaluation.no_oov, 1);
+    assert!((evaluation.oov_rate() - 1.0 / 6.0).abs() < 1e-6);
+    // "<s> a b" and "a b </s>" are trigrams, "</s>" after "c" has no history
+    assert_eq!(evaluation.hits, [1, 2, 2]);
+    assert!((evaluation.hit_rate(3) - 0.4).abs() < 1e-6);
+    assert_eq!(evaluation.hit_rate(4), 0.0);
+
+    let log_prob: f64 = [
+        &["<s>", "a"][..],
+        &["<s>", "a", "b"],
+        &["a", "b", "</s>"],
+        &["<s>", "b"],
+        &["</s>"],
+    ]
+    .iter()
+    .map(|ngram| model.log_prob(ngram).unwrap() as f64)
+    .sum();
+    assert!((evaluation.log_prob - log_prob).abs() < 1e-5);
+    assert!((evaluation.perplexity() as f64 - (-log_prob / 5.0).exp()).abs() < 1e-4);
+    assert!(
+        (evaluation.cross_entropy() - evaluation.perplexity().log2()).abs() < 1e-5,
+        "{}",
+        evaluation
+    );
+}
+
+#[test]
+fn test_keystroke_savings() {
+    let dir = format!("{}ngrams_result_keystrokes/", TEST_ROOT);
+    let config = write_corpus_data(
+        "ngrams_result_keystrokes/",
+        "the cat sat\nthe cat ran\nthe dog sat\n",
+        "<s>\n</s>\nthe\ncat\ndog\nsat\nran\n",
+    )
+    .build()
+    .unwrap();
+    generate(&config).unwrap();
+    let model = LanguageModel::load(&config.output_dir, 3).unwrap();
+
+    // "the" is always predicted first, "dog" second after "the" and "ran" only once "r" is typed.
+    // "cow" is not in the vocabulary, so it is typed in full.
+    let test_file = format!("{}test.txt", dir);
+    fs::write(&test_file, "the dog ran\nthe cow\n").unwrap();
+    let savings = evaluate::keystroke_savings(&model, &test_file, 1).unwrap();
+    assert_eq!(savings.no_words, 5);
+    assert_eq!(savings.no_chars, 20);
+    assert_eq!(savings.no_keystrokes, 1 + 2 + 2 + 1 + 4);
+    assert!((savings.savings_rate() - 0.5).abs() < 1e-6);
+    assert_eq!((savings.top1, savings.top3), (2, 3));
+    assert!((savings.mean_reciprocal_rank() - 0.4).abs() < 1e-6);
+
+    // With a bigger bar "dog" can be selected right away and counts for the reciprocal rank
+    let savings = model.keystroke_savings(["the dog ran", "the cow"], 3);
+    assert_eq!(savings.no_keystrokes, 1 + 1 + 2 + 1 + 4);
+    assert_eq!((savings.top1, savings.top3), (2, 3));
+    assert!((savings.mean_reciprocal_rank() - 0.5).abs() < 1e-6);
+}
+
+#[test]
+fn test_keystroke_savings_sentence_end() {
+    let config = write_corpus_data(
+        "ngrams_result_keystrokes_sentence_end/",
+        "the cat\nthe cat\nthe cat sat\n",
+        "<s>\n</s>\nthe\ncat\nsat\n",
+    )
+    .build()
+    .unwrap();
+    generate(&config).unwrap();
+    let model = LanguageModel::load(&config.output_dir, 3).unwrap();
+
+    // The end of the sentence is the most probable continuation, but it is never suggested
+    assert!(model.log_prob(&["the", "cat", "</s>"]) > model.log_prob(&["the", "cat", "sat"]));
+    let predictions = model.predict(&["the", "cat"], "", 3);
+    assert_eq!(predictions[0].word, "sat");
+    assert!(predictions
+        .iter()
+        .all(|prediction| prediction.word != "</s>"));
+    let savings = model.keystroke_savings(["the cat sat"], 1);
+    assert_eq!((savings.top1, savings.top3), (3, 3));
+    assert!((savings.mean_reciprocal_rank() - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_language_model() {
+    let config = test_builder("ngrams_result_language_model/")
+        .smoothing(Smoothing::ModifiedKneserNey)
+        .build()
+        .unwrap();
+    generate(&config).unwrap();
+    let model = LanguageModel::load(&config.output_dir, 3).unwrap();
+    let stored = |symbols: &[u32]| model.get(symbols).unwrap().log_prob;
+    let backoff = |symbols: &[u32]| model.get(symbols).unwrap().backoff;
+
+    // Ngrams in the tables
+    assert_eq!(model.log_prob(&["a", "b", "a"]), Some(stored(&[0, 1, 0])));
+    assert_eq!(model.log_prob(&["b"]), Some(stored(&[1])));
+    // "a a" is not a history, so its backoff weight is 1
+    assert_eq!(model.log_prob(&["a", "a", "b"]), Some(stored(&[0, 1])));
+    // Neither "b a a" nor "a a" are in the tables
+    assert_eq!(
+        model.log_prob(&["b", "a", "a"]),
+        Some(backoff(&[1, 0]) + backoff(&[0]) + stored(&[0]))
+    );
+    // Unknown words
+    assert_eq!(model.log_prob(&["a", "c"]), None);
+    assert_eq!(model.log_prob(&["c", "b"]), Some(stored(&[1])));
+
+    let score = model.score_sentence(&["a", "b", "c", "a"]);
+    assert_eq!(score, stored(&[0]) + stored(&[0, 1]) + stored(&[0]));
+    let perplexity = model.perplexity(&["a", "b", "c", "a"]);
+    assert!((perplexity - (-score / 3.0).exp()).abs() < 1e-5);
+
+    // All words are candidates, so their probabilities sum up to 1
+    let candidates = model.next_word_candidates(&["b", "a"], 10);
+    assert_eq!(candidates.len(), 2);
+    assert!(candidates[0].1 >= candidates[1].1);
+    let sum: f32 = candidates.iter().map(|(_, log_prob)| log_prob.exp()).sum();
+    assert!((sum - 1.0).abs() < 1e-5);
+    assert_eq!(model.next_word_candidates(&["b", "a"], 1)[0], candidates[0]);
+}
+
+#[test]
+fn test_predict() {
+    let config = test_config("ngrams_result_predict/", 30_000, 3);
+    generate(&config).unwrap();
+    let model = LanguageModel::load(&config.output_dir, 3).unwrap();
+
+    // "b b a" is the only trigram after "b b", "b b b" backs off to the bigram "b b"
+    let predictions = model.predict(&["b", "b"], "", 5);
+    let words: Vec<&str> = predictions.iter().map(|p| p.word).collect();
+    let orders: Vec<usize> = predictions.iter().map(|p| p.order).collect();
+    assert_eq!(words, ["a", "b"]);
+    assert_eq!(orders, [3, 2]);
+    assert_eq!(
+        predictions[1].log_prob,
+        model.log_prob(&["b", "b", "b"]).unwrap()
+    );
+
+    // Only the words starting with the prefix
+    let predictions = model.predict(&["b", "b"], "b", 5);
+    assert_eq!(predictions.len(), 1);
+    assert_eq!((predictions[0].word, predictions[0].order), ("b", 2));
+    assert!(model.predict(&["b", "b"], "x", 5).is_empty());
+    assert_eq!(model.predict(&["b", "b"], "", 1).len(), 1);
+
+    // Without a known history only the unigrams are left
+    let predictions = model.predict(&["c"], "", 5);
+    assert!(predictions.iter().all(|p| p.order == 1));
+    assert!(predictions[0].log_prob >= predictions[1].log_prob);
+}
+
+#[test]
+fn test_predict_unigrams() {
+    let config = write_test_data(
+        "ngrams_result_predict_unigrams/",
+        &["e 1\nd 3\nc 3\nb 5\na 2\nf 3\n", "a e 1\nb c 2\n"],
+        "a\nb\nc\nd\ne\nf\n",
+    )
+    .build()
+    .unwrap();
+    generate(&config).unwrap();
+    let model = LanguageModel::load(&config.output_dir, 2).unwrap();
+    let words = |history: &[&str], k| -> Vec<String> {
+        model
+            .predict(history, "", k)
+            .iter()
+            .map(|p| p.word.to_string())
+            .collect()
+    };
+
+    // Only the needed unigrams are scored, the tied ones by their word like when all of them are ranked
+    for k in 1..=6 {
+        assert_eq!(words(&["x"], k), ["b", "c", "d", "f", "a", "e"][..k]);
+    }
+    // The words that follow the history are left out of the unigrams
+    assert_eq!(words(&["a"], 3), ["e", "b", "c"]);
+    assert_eq!(words(&["b"], 3), ["c", "b", "d"]);
+}
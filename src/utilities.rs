@@ -1,8 +1,13 @@
 use std::cmp::Reverse;
 use std::collections::BinaryHeap;
+use std::fmt;
 use std::fs::File;
 use std::io::Lines;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::input::{split_google_books_line, InputFormat, PosTags};
 
 pub struct LimitedMinHeap {
     min_heap: BinaryHeap<Reverse<u32>>,
@@ -56,27 +61,201 @@ impl LimitedMinHeap {
     }
 }
 
+/// How an input file is compressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    /// Detect the compression from the extension of the file or, if it has none of the known ones, from its first bytes
+    pub fn detect(path: &Path, first_bytes: &[u8]) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => return Compression::Gzip,
+            Some("zst") => return Compression::Zstd,
+            Some("xz") => return Compression::Xz,
+            Some("bz2") => return Compression::Bzip2,
+            _ => {}
+        }
+        if first_bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if first_bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if first_bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if first_bytes.len() >= 10
+            && first_bytes.starts_with(b"BZh")
+            && first_bytes[3].is_ascii_digit()
+            && first_bytes[4..10] == [0x31, 0x41, 0x59, 0x26, 0x53, 0x59]
+        {
+            // Text files can start with "BZh" too, so the magic number of the first block is checked as well
+            Compression::Bzip2
+        } else {
+            Compression::None
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Compression::None => "uncompressed",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+            Compression::Bzip2 => "bzip2",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Open a file for reading and decompress it if it is compressed
+fn open_input(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    let io_error = |err| Error::io(path, err);
+    let mut reader = BufReader::new(File::open(path).map_err(io_error)?);
+    let compression = Compression::detect(path, reader.fill_buf().map_err(io_error)?);
+    // Concatenated streams are common for large dumps, so all decoders read all of them
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(BufReader::new(
+            zstd::Decoder::with_buffer(reader).map_err(io_error)?,
+        )),
+        #[cfg(feature = "xz")]
+        Compression::Xz => Box::new(BufReader::new(xz2::bufread::XzDecoder::new_multi_decoder(
+            reader,
+        ))),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader))),
+        #[allow(unreachable_patterns)]
+        compression => {
+            return Err(io_error(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Support for {} files was not compiled in", compression),
+            )))
+        }
+    })
+}
+
+/// All parts of a file that was split into shards named like `3gms-00000-of-00042.gz`, in the order of their numbers
+/// Any of the shards can be given. Other files are their only part.
+pub fn input_parts<P: AsRef<Path>>(filename: P) -> Vec<PathBuf> {
+    let path = filename.as_ref();
+    let shards = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| {
+            let (before, after) = name.rsplit_once("-of-")?;
+            let (base, index) = before.rsplit_once('-')?;
+            let total_len = after
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(after.len());
+            let (total, rest) = after.split_at(total_len);
+            if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let no_parts: usize = total.parse().ok()?;
+            let parts: Vec<PathBuf> = (0..no_parts)
+                .map(|i| {
+                    path.with_file_name(format!(
+                        "{}-{:0width$}-of-{}{}",
+                        base,
+                        i,
+                        total,
+                        rest,
+                        width = index.len()
+                    ))
+                })
+                .collect();
+            Some(parts)
+        });
+    match shards {
+        Some(parts) if !parts.is_empty() => parts,
+        _ => vec![path.to_path_buf()],
+    }
+}
+
+/// Iterates over the lines of a file
+///
+/// Compressed files are decompressed on the fly and the shards of a file that was split into several parts
+/// are read one after the other as if they were one file (see `input_parts`).
 pub struct LinesIterator {
-    lines: Lines<BufReader<File>>,
+    lines: Lines<Box<dyn BufRead + Send>>,
+    parts: Vec<PathBuf>,
+    part: usize,
+    line_number: usize,
 }
 
 impl LinesIterator {
-    pub fn new(filename: &str) -> Self {
+    pub fn new<P: AsRef<Path>>(filename: P) -> Result<Self> {
         // Open the file in read-only mode.
-        let file = File::open(filename).unwrap();
-        let buf_reader = BufReader::new(file);
-        let lines = buf_reader.lines();
-        LinesIterator { lines }
+        let parts = input_parts(filename);
+        let lines = open_input(&parts[0])?.lines();
+        Ok(LinesIterator {
+            lines,
+            parts,
+            part: 0,
+            line_number: 0,
+        })
+    }
+
+    /// Path of the part of the file that is read
+    pub fn path(&self) -> &Path {
+        &self.parts[self.part]
+    }
+
+    /// Index of the part of the file that is read
+    pub fn part(&self) -> usize {
+        self.part
+    }
+
+    /// Paths of all parts of the file
+    pub fn parts(&self) -> &[PathBuf] {
+        &self.parts
+    }
+
+    /// Number of the line that was returned last within its part, starting at 1
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Create an error for the line that was returned last
+    pub fn parse_error(&self, line: &str, reason: String) -> Error {
+        Error::Parse {
+            path: self.path().to_path_buf(),
+            line_number: self.line_number,
+            line: line.to_string(),
+            reason,
+        }
     }
 }
 
 impl Iterator for LinesIterator {
-    type Item = String;
-    fn next(&mut self) -> Option<String> {
-        if let Some(Ok(line)) = self.lines.next() {
-            Some(line)
-        } else {
-            None
+    type Item = Result<String>;
+    fn next(&mut self) -> Option<Result<String>> {
+        loop {
+            match self.lines.next() {
+                Some(line) => {
+                    self.line_number += 1;
+                    return Some(line.map_err(|err| Error::io(self.path(), err)));
+                }
+                // Continue with the next part once a part is finished
+                None if self.part + 1 < self.parts.len() => {
+                    self.part += 1;
+                    self.line_number = 0;
+                    match open_input(&self.parts[self.part]) {
+                        Ok(reader) => self.lines = reader.lines(),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                None => return None,
+            }
         }
     }
 }
@@ -86,54 +265,199 @@ pub struct WordListIterator {
 }
 
 impl WordListIterator {
-    pub fn new(filename: &str) -> Self {
-        WordListIterator {
-            lines_iterator: LinesIterator::new(filename),
-        }
+    pub fn new<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        Ok(WordListIterator {
+            lines_iterator: LinesIterator::new(filename)?,
+        })
     }
 }
 
 impl Iterator for WordListIterator {
-    type Item = String;
-    fn next(&mut self) -> Option<String> {
+    type Item = Result<String>;
+    fn next(&mut self) -> Option<Result<String>> {
         // If the end of the file was reached, return None
-        if let Some(line) = self.lines_iterator.next() {
-            let word = line.trim();
-            Some(word.to_string())
-        } else {
-            None
-        }
+        let line = self.lines_iterator.next()?;
+        Some(line.map(|line| line.trim().to_string()))
     }
 }
 
+/// Iterates over the ngrams of a file and their counts
+///
+/// In the Google Books format the lines of one ngram are aggregated into one ngram (see `InputFormat`).
 pub struct NGramIterator {
     lines_iterator: LinesIterator,
     n: usize,
+    format: InputFormat,
+    /// Line that was read ahead because it belongs to the next ngram and its part and line number
+    pending: Option<(String, usize, usize)>,
+    /// Part and line number of the first line of the last ngram
+    position: (usize, usize),
 }
 
 impl NGramIterator {
-    pub fn new(filename: &str, n: usize) -> Self {
-        NGramIterator {
-            lines_iterator: LinesIterator::new(filename),
+    pub fn new<P: AsRef<Path>>(filename: P, n: usize) -> Result<Self> {
+        Self::with_format(filename, n, InputFormat::Counts)
+    }
+
+    /// Iterate over the ngrams of a file in the given format
+    pub fn with_format<P: AsRef<Path>>(filename: P, n: usize, format: InputFormat) -> Result<Self> {
+        Ok(NGramIterator {
+            lines_iterator: LinesIterator::new(filename)?,
             n,
+            format,
+            pending: None,
+            position: (0, 0),
+        })
+    }
+
+    /// Number of the line the last ngram was read from within its part, starting at 1
+    /// In the Google Books format it is the first line of the ngram
+    pub fn line_number(&self) -> usize {
+        self.position.1
+    }
+
+    /// Index of the part of the file the last ngram was read from
+    pub fn part(&self) -> usize {
+        self.position.0
+    }
+
+    fn parse_line(&self, line: &str) -> Result<(Vec<String>, u32)> {
+        let (words, count) = split_ngram_line(line, self.n)
+            .map_err(|reason| self.lines_iterator.parse_error(line, reason))?;
+        Ok((words.into_iter().map(str::to_string).collect(), count))
+    }
+
+    /// The next line and its part and line number
+    fn next_line(&mut self) -> Option<Result<(String, usize, usize)>> {
+        if let Some(pending) = self.pending.take() {
+            return Some(Ok(pending));
+        }
+        let line = self.lines_iterator.next()?;
+        let (part, line_number) = (
+            self.lines_iterator.part(),
+            self.lines_iterator.line_number(),
+        );
+        Some(line.map(|line| (line, part, line_number)))
+    }
+
+    /// Sum the match counts of the lines of the next ngram in the Google Books format
+    /// Ngrams that are skipped because of their tags are left out
+    fn next_google_books(
+        &mut self,
+        first_year: u32,
+        last_year: u32,
+        pos_tags: PosTags,
+    ) -> Option<Result<(Vec<String>, u64)>> {
+        let mut current: Option<(Vec<String>, u64)> = None;
+        while let Some(line) = self.next_line() {
+            let (line, part, line_number) = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err)),
+            };
+            let (words, year, match_count) = match split_google_books_line(&line, self.n) {
+                Ok(parsed) => parsed,
+                // The error belongs to the line after the current ngram
+                Err(_) if current.is_some() => {
+                    self.pending = Some((line, part, line_number));
+                    break;
+                }
+                Err(reason) => {
+                    self.position = (part, line_number);
+                    return Some(Err(Error::Parse {
+                        path: self.lines_iterator.parts()[part].clone(),
+                        line_number,
+                        line,
+                        reason,
+                    }));
+                }
+            };
+            let words = match words
+                .iter()
+                .map(|word| pos_tags.apply(word))
+                .collect::<Option<Vec<&str>>>()
+            {
+                Some(words) => words,
+                None => continue,
+            };
+            let match_count = if (first_year..=last_year).contains(&year) {
+                match_count
+            } else {
+                0
+            };
+            match &mut current {
+                Some((current_words, count)) if current_words.iter().eq(words.iter()) => {
+                    *count = count.saturating_add(match_count)
+                }
+                Some(_) => {
+                    self.pending = Some((line, part, line_number));
+                    break;
+                }
+                None => {
+                    self.position = (part, line_number);
+                    current = Some((
+                        words.iter().map(|word| word.to_string()).collect(),
+                        match_count,
+                    ));
+                }
+            }
         }
+        current.map(Ok)
+    }
+}
+
+/// Split a line into the n words of an ngram and its count
+/// Returns why the line is invalid if it is
+pub(crate) fn split_ngram_line(
+    line: &str,
+    n: usize,
+) -> std::result::Result<(Vec<&str>, u32), String> {
+    let mut token = line.split_whitespace();
+    let mut words = Vec::with_capacity(n);
+    for _ in 0..n {
+        let word = token
+            .next()
+            .ok_or_else(|| format!("Expected {} words and a count", n))?;
+        words.push(word)
     }
+    let count = token
+        .next()
+        .ok_or_else(|| format!("Expected a count after {} words", n))?;
+    let count = count
+        .parse::<u32>()
+        .map_err(|err| format!("Invalid count '{}' ({})", count, err))?;
+    Ok((words, count))
 }
 
 impl Iterator for NGramIterator {
-    type Item = (Vec<String>, u32);
-    fn next(&mut self) -> Option<(Vec<String>, u32)> {
-        // If the end of the file was reached, return None
-        if let Some(line) = self.lines_iterator.next() {
-            let mut token = line.split_whitespace();
-            let mut words = Vec::new();
-            for _ in 0..self.n as usize {
-                words.push(token.next().unwrap().trim().parse::<String>().unwrap())
+    type Item = Result<(Vec<String>, u32)>;
+    fn next(&mut self) -> Option<Result<(Vec<String>, u32)>> {
+        match self.format {
+            InputFormat::Counts => {
+                // If the end of the file was reached, return None
+                let line = match self.lines_iterator.next()? {
+                    Ok(line) => line,
+                    Err(err) => return Some(Err(err)),
+                };
+                self.position = (
+                    self.lines_iterator.part(),
+                    self.lines_iterator.line_number(),
+                );
+                Some(self.parse_line(&line))
             }
-            let count = token.next().unwrap().parse::<u32>().unwrap();
-            Some((words, count))
-        } else {
-            None
+            InputFormat::GoogleBooks {
+                first_year,
+                last_year,
+                pos_tags,
+            } => loop {
+                // Ngrams that did not occur in the years are skipped
+                match self.next_google_books(first_year, last_year, pos_tags)? {
+                    Ok((_, 0)) => continue,
+                    Ok((words, count)) => {
+                        return Some(Ok((words, count.min(u32::MAX as u64) as u32)))
+                    }
+                    Err(err) => return Some(Err(err)),
+                }
+            },
         }
     }
 }
@@ -144,12 +468,49 @@ pub struct NGramProcessedIterator {
 }
 
 impl NGramProcessedIterator {
-    pub fn new(filename: &str, n: usize, is_longest_ngram: bool) -> Self {
-        Self {
-            lines_iterator: LinesIterator::new(filename),
+    pub fn new<P: AsRef<Path>>(filename: P, n: usize, is_longest_ngram: bool) -> Result<Self> {
+        Ok(Self {
+            lines_iterator: LinesIterator::new(filename)?,
             n,
             is_longest_ngram,
-        }
+        })
+    }
+
+    fn parse_line(&self, line: &str) -> Result<(Option<StateId>, Vec<Label>, Count)> {
+        let mut token = line.split_whitespace();
+        let mut next_number = |what: &str| -> Result<usize> {
+            let token = token.next().ok_or_else(|| {
+                self.lines_iterator
+                    .parse_error(line, format!("Expected {}", what))
+            })?;
+            token.parse::<usize>().map_err(|err| {
+                self.lines_iterator
+                    .parse_error(line, format!("Invalid {} '{}' ({})", what, token, err))
+            })
+        };
+        let state_id = if self.is_longest_ngram {
+            None
+        } else {
+            Some(next_number("state id")?)
+        };
+        let label = if self.n == 1 {
+            match state_id {
+                Some(state_id) if state_id > 0 => vec![state_id - 1],
+                _ => {
+                    return Err(self
+                        .lines_iterator
+                        .parse_error(line, "Expected a state id of at least 1".to_string()))
+                }
+            }
+        } else {
+            let mut label = Vec::with_capacity(self.n);
+            for _ in 0..self.n {
+                label.push(next_number("label")?)
+            }
+            label
+        };
+        let count = next_number("count")?;
+        Ok((state_id, label, count))
     }
 }
 
@@ -158,29 +519,13 @@ pub type Label = usize;
 pub type Count = usize;
 
 impl Iterator for NGramProcessedIterator {
-    type Item = (Option<StateId>, Vec<Label>, Count);
-    fn next(&mut self) -> Option<(Option<StateId>, Vec<Label>, Count)> {
+    type Item = Result<(Option<StateId>, Vec<Label>, Count)>;
+    fn next(&mut self) -> Option<Result<(Option<StateId>, Vec<Label>, Count)>> {
         // If the end of the file was reached, return None
-        if let Some(line) = self.lines_iterator.next() {
-            let mut token = line.split_whitespace();
-            let state_id = if self.is_longest_ngram {
-                None
-            } else {
-                Some(token.next().unwrap().trim().parse::<StateId>().unwrap())
-            };
-            let label = if self.n as usize == 1 {
-                vec![state_id.unwrap() - 1]
-            } else {
-                let mut label = Vec::new();
-                for _ in 0..self.n as usize {
-                    label.push(token.next().unwrap().trim().parse::<Label>().unwrap())
-                }
-                label
-            };
-            let count = token.next().unwrap().parse::<Count>().unwrap();
-            Some((state_id, label, count))
-        } else {
-            None
-        }
+        let line = match self.lines_iterator.next()? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(self.parse_line(&line))
     }
 }
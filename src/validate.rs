@@ -0,0 +1,175 @@
+use std::ops::Range;
+
+use crate::error::Error;
+use crate::model::ModelTables;
+
+/// How much the probabilities under a history may sum up to more than 1 because of rounding
+const PROBABILITY_TOLERANCE: f64 = 1e-3;
+
+impl ModelTables {
+    /// Check that the tables fit together and return every violation that was found
+    ///
+    /// - every symbol has a unigram and the labels of the longer ngrams are symbols
+    /// - the children of the ngrams of each order are in bounds of the next higher order and their ranges
+    ///   cover that table without gaps or overlaps
+    /// - the suffix of every ngram of length 3 or more points to the ngram made up of all but its first word
+    /// - the probabilities of the unigrams and of the children of every ngram sum up to at most 1
+    pub fn validate(&self) -> Vec<Error> {
+        let mut problems = Vec::new();
+        let mut problem = |message: String| problems.push(Error::InconsistentModel(message));
+        let no_symbols = self.symbols.len();
+        if self.ngrams.is_empty() {
+            problem("The model has no tables".to_string());
+            return problems;
+        }
+        if self.ngrams[0].len() != no_symbols {
+            problem(format!(
+                "There are {} unigrams, but {} symbols",
+                self.ngrams[0].len(),
+                no_symbols
+            ));
+        }
+        for (i, table) in self.ngrams.iter().enumerate().skip(1) {
+            for (idx, record) in table.iter().enumerate() {
+                if record.label as usize >= no_symbols {
+                    problem(format!(
+                        "The {}gram {} has the label {}, but there are only {} symbols",
+                        i + 1,
+                        idx,
+                        record.label,
+                        no_symbols
+                    ));
+                }
+            }
+        }
+        check_probabilities(
+            "the unigrams",
+            self.ngrams[0].iter().map(|record| record.log_prob),
+            &mut problem,
+        );
+
+        // Index of the prefix of every ngram of the next higher order, it is None if the ngram has no prefix
+        let mut parents: Vec<Vec<Option<u32>>> = vec![Vec::new()];
+        for i in 0..self.max_ngram_len() - 1 {
+            let (table, next) = (&self.ngrams[i], &self.ngrams[i + 1]);
+            let mut ranges: Vec<(Range<usize>, usize)> = table
+                .iter()
+                .enumerate()
+                .filter(|(_, record)| record.no_longer_ngrams > 0)
+                .map(|(idx, record)| (record.children(), idx))
+                .collect();
+            ranges.sort_by_key(|(children, _)| (children.start, children.end));
+            let mut parents_next = vec![None; next.len()];
+            let mut end = 0;
+            let mut previous = None;
+            for (children, idx) in ranges {
+                if children.start > end {
+                    problem(format!(
+                        "The {}grams {:?} are not the children of any {}gram",
+                        i + 2,
+                        end..children.start,
+                        i + 1
+                    ));
+                } else if children.start < end {
+                    problem(format!(
+                        "The children {:?} of the {}gram {} overlap with the ones of the {}gram {}",
+                        children,
+                        i + 1,
+                        idx,
+                        i + 1,
+                        previous.unwrap_or_default()
+                    ));
+                }
+                if children.end > next.len() {
+                    problem(format!(
+                        "The {}gram {} has the children {:?}, but there are only {} {}grams",
+                        i + 1,
+                        idx,
+                        children,
+                        next.len(),
+                        i + 2
+                    ));
+                }
+                let in_bounds = children.start.min(next.len())..children.end.min(next.len());
+                for child in in_bounds.clone() {
+                    parents_next[child].get_or_insert(idx as u32);
+                }
+                check_probabilities(
+                    &format!("the children of the {}gram {}", i + 1, idx),
+                    next[in_bounds].iter().map(|record| record.log_prob),
+                    &mut problem,
+                );
+                end = end.max(children.end);
+                previous = Some(idx);
+            }
+            if end < next.len() {
+                problem(format!(
+                    "The {}grams {:?} are not the children of any {}gram",
+                    i + 2,
+                    end..next.len(),
+                    i + 1
+                ));
+            }
+            parents.push(parents_next);
+        }
+
+        // The suffix of an ngram is the child of the suffix of its prefix with the same label
+        // The suffix of a bigram is the unigram of its label, so it is its label
+        let mut suffixes: Vec<Option<u32>> = self
+            .ngrams
+            .get(1)
+            .into_iter()
+            .flatten()
+            .map(|record| Some(record.label))
+            .collect();
+        for (i, parents) in parents.iter().enumerate().skip(2) {
+            let mut suffixes_next = Vec::with_capacity(self.ngrams[i].len());
+            for (idx, record) in self.ngrams[i].iter().enumerate() {
+                let expected = parents[idx]
+                    .and_then(|parent| suffixes[parent as usize])
+                    .and_then(|suffix| self.ngrams[i - 2].get(suffix as usize))
+                    .and_then(|suffix| {
+                        suffix.children().find(|&child| {
+                            self.ngrams[i - 1].get(child).map(|child| child.label)
+                                == Some(record.label)
+                        })
+                    });
+                if parents[idx].is_some() && expected != Some(record.suffix as usize) {
+                    match expected {
+                        Some(expected) => problem(format!(
+                            "The {}gram {} has the suffix {}, but its suffix is the {}gram {}",
+                            i + 1,
+                            idx,
+                            record.suffix,
+                            i,
+                            expected
+                        )),
+                        None => problem(format!(
+                            "The suffix of the {}gram {} is not in the model",
+                            i + 1,
+                            idx
+                        )),
+                    }
+                }
+                suffixes_next.push(expected.map(|suffix| suffix as u32));
+            }
+            suffixes = suffixes_next;
+        }
+        problems
+    }
+}
+
+/// Report the ngrams if their probabilities sum up to more than 1
+fn check_probabilities<I: Iterator<Item = f32>>(
+    ngrams: &str,
+    log_probs: I,
+    problem: &mut impl FnMut(String),
+) {
+    let sum: f64 = log_probs.map(|log_prob| (log_prob as f64).exp()).sum();
+    if sum > 1.0 + PROBABILITY_TOLERANCE {
+        problem(format!(
+            "The probabilities of {} sum up to {:.3}, which is more than 1",
+            ngrams, sum
+        ));
+    }
+}
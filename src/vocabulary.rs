@@ -0,0 +1,185 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::utilities::LimitedMinHeap;
+
+/// How the words of the vocabulary are chosen among the unigrams that are in the dictionary
+///
+/// At most `max_no_words` words are chosen, apart from words that have the same count as the last of them
+/// unless they are dropped by a `TieBreak`. Words of the include list are always kept and take their places among them, the selection only fills the rest.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VocabularySelection {
+    /// The most frequent words, words with the same count as the last of them are kept as well
+    #[default]
+    MostFrequent,
+    /// The most frequent words that occur at least `min_count` times
+    MinCount(u32),
+    /// The fewest most frequent words that cover this fraction of all tokens of the unigram file
+    /// The tokens of the words that are always kept are covered from the start.
+    /// Words with the same count are chosen in the order of the unigram file unless the tie break is lexicographic.
+    Coverage(f64),
+}
+
+impl fmt::Display for VocabularySelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VocabularySelection::MostFrequent => write!(f, "top"),
+            VocabularySelection::MinCount(min_count) => write!(f, "min-count:{}", min_count),
+            VocabularySelection::Coverage(coverage) => write!(f, "coverage:{}", coverage),
+        }
+    }
+}
+
+impl FromStr for VocabularySelection {
+    type Err = Error;
+
+    /// Parse `top`, `min-count:<count>` or `coverage:<fraction>` with the fraction in (0, 1]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidConfig(format!("Invalid vocabulary selection '{}'", s));
+        let selection = match s.split_once(':') {
+            None if s == "top" => VocabularySelection::MostFrequent,
+            Some(("min-count", min_count)) => {
+                VocabularySelection::MinCount(min_count.parse().map_err(|_| invalid())?)
+            }
+            Some(("coverage", coverage)) => {
+                let coverage: f64 = coverage.parse().map_err(|_| invalid())?;
+                if !(coverage > 0.0 && coverage <= 1.0) {
+                    return Err(invalid());
+                }
+                VocabularySelection::Coverage(coverage)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(selection)
+    }
+}
+
+/// Which of the words that have the same count as the last word of the vocabulary are kept
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// All of them, so the vocabulary can have more than `max_no_words` words
+    #[default]
+    KeepAll,
+    /// The ones that come first in the unigram file
+    FirstSeen,
+    /// The ones that come first in the lexicographic order of their bytes
+    Lexicographic,
+}
+
+impl fmt::Display for TieBreak {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TieBreak::KeepAll => write!(f, "keep"),
+            TieBreak::FirstSeen => write!(f, "first"),
+            TieBreak::Lexicographic => write!(f, "lexicographic"),
+        }
+    }
+}
+
+impl FromStr for TieBreak {
+    type Err = Error;
+
+    /// Parse `keep`, `first` or `lexicographic`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(TieBreak::KeepAll),
+            "first" | "first-seen" => Ok(TieBreak::FirstSeen),
+            "lexicographic" | "lex" => Ok(TieBreak::Lexicographic),
+            _ => Err(Error::InvalidConfig(format!("Invalid tie break '{}'", s))),
+        }
+    }
+}
+
+impl VocabularySelection {
+    /// Choose the words of the vocabulary among the candidates, given by their words, counts and whether they are always kept
+    /// `total_count` is the number of tokens of the unigram file.
+    /// Returns for each candidate whether it is kept and how many words were dropped although they had the same count
+    /// as the last word of the vocabulary.
+    pub(crate) fn select(
+        &self,
+        candidates: &[(String, u32, bool)],
+        max_no_words: usize,
+        tie_break: TieBreak,
+        total_count: u64,
+    ) -> (Vec<bool>, usize) {
+        let mut kept: Vec<bool> = candidates
+            .iter()
+            .map(|&(_, _, mandatory)| mandatory)
+            .collect();
+        let no_mandatory = kept.iter().filter(|&&kept| kept).count();
+        let budget = max_no_words.saturating_sub(no_mandatory);
+        if budget == 0 {
+            return (kept, 0);
+        }
+        let min_count = match self {
+            VocabularySelection::MinCount(min_count) => *min_count,
+            _ => 0,
+        };
+        let eligible =
+            |&(_, count, mandatory): &(String, u32, bool)| !mandatory && count >= min_count;
+        // Order of the words with the same count, the stable sorts keep the order of the file otherwise
+        let break_tie = |a: usize, b: usize| match tie_break {
+            TieBreak::Lexicographic => candidates[a].0.cmp(&candidates[b].0),
+            _ => std::cmp::Ordering::Equal,
+        };
+
+        if let VocabularySelection::Coverage(coverage) = self {
+            let target = (coverage * total_count as f64).ceil() as u64;
+            let mut covered: u64 = candidates
+                .iter()
+                .filter(|(_, _, mandatory)| *mandatory)
+                .map(|&(_, count, _)| count as u64)
+                .sum();
+            // Most frequent first
+            let mut ranked: Vec<usize> = (0..candidates.len())
+                .filter(|&i| eligible(&candidates[i]))
+                .collect();
+            ranked.sort_by(|&a, &b| {
+                candidates[b]
+                    .1
+                    .cmp(&candidates[a].1)
+                    .then_with(|| break_tie(a, b))
+            });
+            for i in ranked.into_iter().take(budget) {
+                if covered >= target {
+                    break;
+                }
+                kept[i] = true;
+                covered += candidates[i].1 as u64;
+            }
+            return (kept, 0);
+        }
+
+        // The count of the least frequent of the most frequent words is the threshold
+        let mut threshold = 0;
+        let mut min_heap = LimitedMinHeap::new(budget);
+        for candidate in candidates.iter().filter(|candidate| eligible(candidate)) {
+            if let Some(new_k_highest_count) = min_heap.insert(candidate.1) {
+                threshold = new_k_highest_count;
+            }
+        }
+        let mut tied = Vec::new();
+        for (i, candidate) in candidates.iter().enumerate() {
+            if eligible(candidate) && candidate.1 > threshold {
+                kept[i] = true;
+            } else if eligible(candidate) && candidate.1 == threshold {
+                tied.push(i);
+            }
+        }
+        // The words above the threshold are fewer than the budget, the tied words fill the rest of it
+        let free = budget + no_mandatory - kept.iter().filter(|&&kept| kept).count();
+        if tie_break == TieBreak::KeepAll || tied.len() <= free {
+            for i in tied {
+                kept[i] = true;
+            }
+            return (kept, 0);
+        }
+        tied.sort_by(|&a, &b| break_tie(a, b));
+        let dropped = tied.len() - free;
+        for i in tied.into_iter().take(free) {
+            kept[i] = true;
+        }
+        (kept, dropped)
+    }
+}
@@ -0,0 +1,41 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+const TEST_ROOT: &str = "LanguageModel_TEST/";
+
+fn run(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_ngrams_to_language_model"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{:?}: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn test_model_order_detection() {
+    let output_dir = format!("{}ngrams_result_cli_4grams/", TEST_ROOT);
+    run(&["build", "-r", TEST_ROOT, "-o", &output_dir, "-n", "4", "-q"]);
+    assert!(Path::new(&output_dir).join("4gms.txt").is_file());
+
+    // The commands that read the model find all four orders without --order
+    let stats = run(&["stats", "-o", &output_dir]);
+    assert_eq!(
+        String::from_utf8(stats.stdout).unwrap(),
+        "2 words in the symbol table\n2 1grams\n3 2grams\n4 3grams\n3 4grams\n"
+    );
+    run(&["validate", "--model", "-o", &output_dir]);
+    run(&["query", "-o", &output_dir, "a", "b", "a", "b"]);
+
+    // --order still overrides it
+    let stats = run(&["stats", "-o", &output_dir, "-n", "2", "-f", "json"]);
+    assert_eq!(
+        String::from_utf8(stats.stdout).unwrap(),
+        "{\"symbols\":2,\"ngrams\":[2,3]}\n"
+    );
+}